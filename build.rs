@@ -0,0 +1,40 @@
+use std::{env, fs, process::Command};
+
+/// Embeds build information for the `/version` and `/readyz` endpoints
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+
+    // Migrations are recorded by diesel with the digits of their directory
+    // name's date prefix
+    let latest_migration = fs::read_dir("migrations")
+        .expect("Error reading migrations")
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            let prefix = name.split('_').next()?.to_owned();
+            Some(prefix.replace('-', ""))
+        })
+        .max()
+        .expect("There should be at least one migration");
+    println!("cargo:rustc-env=LATEST_MIGRATION={latest_migration}");
+
+    // Builds from outside a git checkout can pass the commit in themselves
+    let commit = env::var("GIT_COMMIT").ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+    });
+    println!(
+        "cargo:rustc-env=GIT_COMMIT={}",
+        commit.as_deref().unwrap_or("unknown")
+    );
+    println!(
+        "cargo:rustc-env=BUILD_PROFILE={}",
+        env::var("PROFILE").unwrap_or_default()
+    );
+}
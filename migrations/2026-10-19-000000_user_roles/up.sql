@@ -0,0 +1,8 @@
+CREATE TABLE user_roles (
+    user_id BIGINT NOT NULL
+        CONSTRAINT fk_user_roles_user_id REFERENCES users(id),
+    role VARCHAR(50) NOT NULL,
+    date_created TIMESTAMP WITH TIME ZONE NOT NULL
+        CONSTRAINT df_user_roles_date_created DEFAULT (now() AT TIME ZONE 'utc'),
+    CONSTRAINT pk_user_roles PRIMARY KEY (user_id, role)
+);
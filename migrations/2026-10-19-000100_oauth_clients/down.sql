@@ -0,0 +1,7 @@
+ALTER TABLE auth_tokens
+DROP COLUMN client_id,
+DROP COLUMN scope;
+
+DROP TABLE oauth_authorization_codes;
+
+DROP TABLE oauth_clients;
@@ -0,0 +1,35 @@
+CREATE TABLE oauth_clients (
+    id BIGSERIAL PRIMARY KEY,
+    client_id VARCHAR(64) NOT NULL
+        CONSTRAINT uq_oauth_clients_client_id UNIQUE,
+    -- SHA-256 of the client secret, NULL for public clients
+    client_secret BYTEA,
+    name VARCHAR(255) NOT NULL,
+    redirect_uris TEXT[] NOT NULL,
+    date_created TIMESTAMP WITH TIME ZONE NOT NULL
+        CONSTRAINT df_oauth_clients_date_created DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE TABLE oauth_authorization_codes (
+    id BIGSERIAL PRIMARY KEY,
+    -- SHA-256 of the code handed to the client
+    code BYTEA NOT NULL,
+    client_id BIGINT NOT NULL
+        CONSTRAINT fk_oauth_authorization_codes_client_id REFERENCES oauth_clients(id),
+    user_id BIGINT NOT NULL
+        CONSTRAINT fk_oauth_authorization_codes_user_id REFERENCES users(id),
+    redirect_uri TEXT NOT NULL,
+    scope VARCHAR NOT NULL,
+    code_challenge VARCHAR(128) NOT NULL,
+    date_created TIMESTAMP WITH TIME ZONE NOT NULL
+        CONSTRAINT df_oauth_authorization_codes_date_created DEFAULT (now() AT TIME ZONE 'utc'),
+    date_expired TIMESTAMP WITH TIME ZONE NOT NULL,
+    date_used TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX ix_oauth_authorization_codes_code ON oauth_authorization_codes USING hash (code);
+
+ALTER TABLE auth_tokens
+ADD COLUMN client_id BIGINT
+    CONSTRAINT fk_auth_tokens_client_id REFERENCES oauth_clients(id),
+ADD COLUMN scope VARCHAR;
@@ -0,0 +1,10 @@
+DELETE FROM auth_tokens
+WHERE user_id IS NULL;
+
+ALTER TABLE auth_tokens
+DROP CONSTRAINT ck_auth_tokens_principal,
+ALTER user_id SET NOT NULL;
+
+ALTER TABLE oauth_clients
+DROP COLUMN grant_types,
+DROP COLUMN allowed_scopes;
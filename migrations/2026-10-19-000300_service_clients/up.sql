@@ -0,0 +1,12 @@
+ALTER TABLE oauth_clients
+ADD COLUMN grant_types TEXT[] NOT NULL
+    CONSTRAINT df_oauth_clients_grant_types DEFAULT '{authorization_code}',
+ADD COLUMN allowed_scopes TEXT[] NOT NULL
+    CONSTRAINT df_oauth_clients_allowed_scopes DEFAULT '{}';
+
+-- Tokens issued with the client credentials grant belong to the client
+-- rather than a user
+ALTER TABLE auth_tokens
+ALTER user_id DROP NOT NULL,
+ADD CONSTRAINT ck_auth_tokens_principal
+    CHECK (user_id IS NOT NULL OR client_id IS NOT NULL);
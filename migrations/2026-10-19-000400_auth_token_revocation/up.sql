@@ -0,0 +1,2 @@
+ALTER TABLE auth_tokens
+ADD COLUMN date_revoked TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,24 @@
+CREATE TABLE device_authorizations (
+    id BIGSERIAL PRIMARY KEY,
+    -- SHA-256 of the device code handed to the client
+    device_code BYTEA NOT NULL,
+    -- Stored without the separator shown to the user
+    user_code VARCHAR(8) NOT NULL,
+    client_id BIGINT NOT NULL
+        CONSTRAINT fk_device_authorizations_client_id REFERENCES oauth_clients(id),
+    scope VARCHAR NOT NULL,
+    -- Set once a user approves the request
+    user_id BIGINT
+        CONSTRAINT fk_device_authorizations_user_id REFERENCES users(id),
+    poll_interval INTEGER NOT NULL,
+    date_created TIMESTAMP WITH TIME ZONE NOT NULL
+        CONSTRAINT df_device_authorizations_date_created DEFAULT (now() AT TIME ZONE 'utc'),
+    date_expired TIMESTAMP WITH TIME ZONE NOT NULL,
+    date_last_polled TIMESTAMP WITH TIME ZONE,
+    date_approved TIMESTAMP WITH TIME ZONE,
+    date_denied TIMESTAMP WITH TIME ZONE,
+    date_used TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX ix_device_authorizations_device_code ON device_authorizations USING hash (device_code);
+CREATE INDEX ix_device_authorizations_user_code ON device_authorizations (user_code);
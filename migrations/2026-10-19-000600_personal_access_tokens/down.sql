@@ -0,0 +1,7 @@
+DELETE FROM auth_tokens
+WHERE date_expired IS NULL;
+
+ALTER TABLE auth_tokens
+DROP CONSTRAINT ck_auth_tokens_expiry,
+DROP COLUMN name,
+ALTER date_expired SET NOT NULL;
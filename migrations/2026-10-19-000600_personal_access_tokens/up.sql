@@ -0,0 +1,6 @@
+-- Personal access tokens are named and may never expire
+ALTER TABLE auth_tokens
+ALTER date_expired DROP NOT NULL,
+ADD COLUMN name VARCHAR(255),
+ADD CONSTRAINT ck_auth_tokens_expiry
+    CHECK (date_expired IS NOT NULL OR token_type = 'personal');
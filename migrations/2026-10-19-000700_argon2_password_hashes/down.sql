@@ -0,0 +1,3 @@
+-- Fails if any Argon2 hashes are stored, as they won't fit
+ALTER TABLE users
+ALTER password TYPE VARCHAR(60);
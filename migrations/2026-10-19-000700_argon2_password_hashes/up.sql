@@ -0,0 +1,3 @@
+-- Argon2 PHC strings are longer than bcrypt hashes
+ALTER TABLE users
+ALTER password TYPE VARCHAR(255);
@@ -0,0 +1,4 @@
+-- Existing hashes all use the original HMAC_HASH, which becomes version 1
+ALTER TABLE users
+ADD COLUMN pepper_id INTEGER NOT NULL
+    CONSTRAINT df_users_pepper_id DEFAULT 1;
@@ -0,0 +1,5 @@
+DROP INDEX ix_auth_log_email_date_created;
+
+ALTER TABLE auth_log
+DROP COLUMN flag_reasons,
+DROP COLUMN flagged;
@@ -0,0 +1,8 @@
+ALTER TABLE auth_log
+ADD COLUMN flagged BOOLEAN NOT NULL
+    CONSTRAINT df_auth_log_flagged DEFAULT FALSE,
+ADD COLUMN flag_reasons VARCHAR(255);
+
+-- Login history is looked up by email on every login
+CREATE INDEX ix_auth_log_email_date_created
+ON auth_log (email, date_created);
@@ -0,0 +1,39 @@
+-- Actors and targets aren't foreign keys, so events outlive what they refer to
+CREATE TABLE audit_events (
+    id BIGSERIAL PRIMARY KEY,
+    event_type VARCHAR(100) NOT NULL,
+    actor_type VARCHAR(20) NOT NULL,
+    actor_id VARCHAR(255),
+    target_type VARCHAR(50) NOT NULL,
+    target_id VARCHAR(255) NOT NULL,
+    ip_address VARCHAR(50) NOT NULL,
+    user_agent VARCHAR NOT NULL,
+    details JSONB NOT NULL
+        CONSTRAINT df_audit_events_details DEFAULT '{}',
+    date_created TIMESTAMP WITH TIME ZONE NOT NULL
+        CONSTRAINT df_audit_events_date_created
+            DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE INDEX ix_audit_events_actor
+ON audit_events (actor_type, actor_id);
+
+CREATE INDEX ix_audit_events_target
+ON audit_events (target_type, target_id);
+
+CREATE INDEX ix_audit_events_date_created
+ON audit_events (date_created);
+
+CREATE FUNCTION audit_events_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER tr_audit_events_append_only
+BEFORE UPDATE OR DELETE ON audit_events
+FOR EACH ROW EXECUTE PROCEDURE audit_events_append_only();
+
+CREATE TRIGGER tr_audit_events_no_truncate
+BEFORE TRUNCATE ON audit_events
+FOR EACH STATEMENT EXECUTE PROCEDURE audit_events_append_only();
@@ -0,0 +1,7 @@
+ALTER TABLE audit_events
+DROP COLUMN row_hash,
+DROP COLUMN previous_hash;
+
+ALTER TABLE auth_log
+DROP COLUMN row_hash,
+DROP COLUMN previous_hash;
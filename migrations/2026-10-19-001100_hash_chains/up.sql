@@ -0,0 +1,8 @@
+-- Rows from before chaining keep NULL hashes
+ALTER TABLE auth_log
+ADD COLUMN previous_hash BYTEA,
+ADD COLUMN row_hash BYTEA;
+
+ALTER TABLE audit_events
+ADD COLUMN previous_hash BYTEA,
+ADD COLUMN row_hash BYTEA;
@@ -0,0 +1,238 @@
+use config::{self, ProxyHeader};
+use ipnet::IpNet;
+use rouille::Request;
+use std::{net::IpAddr, sync::LazyLock};
+
+/// Who made a request, as recorded in the auth log
+pub struct ClientInfo<'a> {
+    pub ip_address: String,
+    /// Empty if the client didn't send one
+    pub user_agent: &'a str,
+}
+
+/// Parses a `proxies.trusted` entry, a CIDR or a single address
+#[must_use]
+pub fn parse_proxy(proxy: &str) -> Option<IpNet> {
+    proxy
+        .parse()
+        .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+        .ok()
+}
+
+static TRUSTED_PROXIES: LazyLock<Vec<IpNet>> = LazyLock::new(|| {
+    config::get()
+        .proxies
+        .trusted
+        .iter()
+        .map(|proxy| {
+            parse_proxy(proxy).expect(
+                "proxies.trusted is checked when loading the configuration",
+            )
+        })
+        .collect()
+});
+
+fn is_trusted(trusted: &[IpNet], address: IpAddr) -> bool {
+    trusted.iter().any(|proxy| proxy.contains(&address))
+}
+
+/// Treats IPv4-mapped IPv6 addresses, as seen on dual stack sockets, as the
+/// IPv4 addresses they are
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+        IpAddr::V4(_) => address,
+    }
+}
+
+/// Parses a node as found in `X-Forwarded-For` or a `Forwarded` `for`
+/// parameter: an IPv4 or IPv6 address, optionally with a port, with IPv6
+/// addresses in brackets if they have one. Obfuscated and `unknown`
+/// identifiers give `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(bracketed) = node.strip_prefix('[') {
+        let (address, _port) = bracketed.split_once(']')?;
+        return address.parse().ok();
+    }
+    node.parse().ok().or_else(|| {
+        // Only IPv4 addresses can have an unbracketed port
+        let (address, _port) = node.rsplit_once(':')?;
+        address.parse().ok().filter(IpAddr::is_ipv4)
+    })
+}
+
+/// The `for` node of each `Forwarded` element, oldest first
+fn forwarded_nodes(forwarded: &str) -> Vec<&str> {
+    forwarded
+        .split(',')
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .map_or("unknown", |(_, value)| value)
+        })
+        .collect()
+}
+
+/// Every value of a header joined into one list, as they're equivalent
+fn joined_header(request: &Request, name: &str) -> Option<String> {
+    let values: Vec<&str> = request
+        .headers()
+        .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+        .collect();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(","))
+    }
+}
+
+/// The nodes listed in a proxy header, oldest first
+fn proxy_nodes(header: ProxyHeader, value: &str) -> Vec<&str> {
+    match header {
+        ProxyHeader::Forwarded => forwarded_nodes(value),
+        ProxyHeader::XForwardedFor => value.split(',').collect(),
+    }
+}
+
+/// Follows a request from `address` back through the nodes its proxies
+/// reported, to the first address that isn't a trusted proxy
+fn forwarded_client(
+    address: IpAddr,
+    nodes: &[&str],
+    trusted: &[IpNet],
+) -> IpAddr {
+    let mut client = canonical(address);
+    for node in nodes.iter().rev() {
+        if !is_trusted(trusted, client) {
+            break;
+        }
+        // Stop at the last known address if a proxy hid the next one
+        let Some(address) = parse_node(node) else {
+            break;
+        };
+        client = canonical(address);
+    }
+    client
+}
+
+/// The address of the client that made a request.
+///
+/// Requests from trusted proxies are followed back through the address chain
+/// in the configured proxy header to the first address that isn't a trusted
+/// proxy. Entries before it could have been forged by the client, so are
+/// ignored.
+pub fn client_ip(request: &Request) -> IpAddr {
+    let address = request.remote_addr().ip();
+    let Some(header) = config::get().proxies.header else {
+        return canonical(address);
+    };
+    let name = match header {
+        ProxyHeader::Forwarded => "Forwarded",
+        ProxyHeader::XForwardedFor => "X-Forwarded-For",
+    };
+    let value = joined_header(request, name);
+    let nodes = value
+        .as_deref()
+        .map_or_else(Vec::new, |value| proxy_nodes(header, value));
+    forwarded_client(address, &nodes, &TRUSTED_PROXIES)
+}
+
+#[must_use]
+pub fn client_info(request: &Request) -> ClientInfo<'_> {
+    ClientInfo {
+        ip_address: client_ip(request).to_string(),
+        user_agent: request.header("User-Agent").unwrap_or(""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_nodes() {
+        assert_eq!(parse_node("192.0.2.1"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node(" 192.0.2.1:8080"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(
+            parse_node("\"[2001:db8:cafe::17]:4711\""),
+            Some(ip("2001:db8:cafe::17"))
+        );
+        assert_eq!(parse_node("[2001:db8::1]"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+        assert_eq!(parse_node("[2001:db8::1"), None);
+    }
+
+    #[test]
+    fn finds_forwarded_for_nodes() {
+        assert_eq!(
+            forwarded_nodes(
+                "for=192.0.2.60;proto=http;by=203.0.113.43, \
+                 For=\"[2001:db8:cafe::17]:4711\", proto=https"
+            ),
+            ["192.0.2.60", "\"[2001:db8:cafe::17]:4711\"", "unknown"]
+        );
+    }
+
+    #[test]
+    fn walks_back_through_trusted_proxies() {
+        let trusted = [parse_proxy("10.0.0.0/8").unwrap()];
+        let client = |remote: &str, header: ProxyHeader, value: &str| {
+            forwarded_client(
+                ip(remote),
+                &proxy_nodes(header, value),
+                &trusted,
+            )
+        };
+
+        // Entries before the first untrusted address are ignored
+        assert_eq!(
+            client(
+                "10.0.0.1",
+                ProxyHeader::XForwardedFor,
+                "198.51.100.1, 192.0.2.1, 10.0.0.2",
+            ),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            client(
+                "10.0.0.1",
+                ProxyHeader::Forwarded,
+                "for=\"[2001:db8::1]:4711\", for=10.0.0.2",
+            ),
+            ip("2001:db8::1")
+        );
+        // A hidden node stops the walk at the last known address
+        assert_eq!(
+            client(
+                "10.0.0.1",
+                ProxyHeader::Forwarded,
+                "for=192.0.2.1, for=unknown, for=10.0.0.2",
+            ),
+            ip("10.0.0.2")
+        );
+        // Mapped IPv4 addresses are matched against IPv4 proxies
+        assert_eq!(
+            client(
+                "::ffff:10.0.0.1",
+                ProxyHeader::XForwardedFor,
+                "::ffff:192.0.2.1",
+            ),
+            ip("192.0.2.1")
+        );
+        // Headers from untrusted addresses are ignored
+        assert_eq!(
+            client("192.0.2.9", ProxyHeader::XForwardedFor, "198.51.100.1"),
+            ip("192.0.2.9")
+        );
+    }
+}
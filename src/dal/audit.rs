@@ -0,0 +1,181 @@
+use super::{schema::audit_events, DalConnection};
+use chrono::{DateTime, Utc};
+use diesel::{self, prelude::*, result::Error::NotFound};
+use serde_json::Value;
+use telemetry;
+
+#[derive(Insertable)]
+#[table_name = "audit_events"]
+pub struct NewAuditEvent<'a> {
+    pub event_type: &'a str,
+    pub actor_type: &'a str,
+    pub actor_id: Option<&'a str>,
+    pub target_type: &'a str,
+    pub target_id: &'a str,
+    pub ip_address: &'a str,
+    pub user_agent: &'a str,
+    pub details: &'a Value,
+    pub date_created: DateTime<Utc>,
+    pub previous_hash: Option<&'a [u8]>,
+    pub row_hash: Option<&'a [u8]>,
+}
+
+#[derive(Identifiable, Queryable)]
+#[table_name = "audit_events"]
+pub struct AuditEvent {
+    pub id: i64,
+    pub event_type: String,
+    pub actor_type: String,
+    pub actor_id: Option<String>,
+    pub target_type: String,
+    pub target_id: String,
+    pub ip_address: String,
+    pub user_agent: String,
+    pub details: Value,
+    pub date_created: DateTime<Utc>,
+    pub previous_hash: Option<Vec<u8>>,
+    pub row_hash: Option<Vec<u8>>,
+}
+
+pub enum CreateAuditEventError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Inserts an audit event
+///
+/// # Errors
+///
+/// If the insert fails
+pub fn create_audit_event(
+    connection: &DalConnection,
+    new_event: &NewAuditEvent<'_>,
+) -> Result<AuditEvent, CreateAuditEventError> {
+    let _span = telemetry::db_span("dal::audit::create_audit_event");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::insert_into(audit_events::table)
+        .values(new_event)
+        .get_result(pg_connection);
+    match result {
+        Ok(event) => Ok(event),
+        Err(error) => Err(CreateAuditEventError::OtherDbError(error)),
+    }
+}
+
+pub enum GetAuditEventError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Gets the hash of the most recent audit event, if it has one
+///
+/// # Errors
+///
+/// If the query fails
+pub fn get_last_audit_event_hash(
+    connection: &DalConnection,
+) -> Result<Option<Vec<u8>>, GetAuditEventError> {
+    use super::schema::audit_events::dsl::*;
+
+    let _span = telemetry::db_span("dal::audit::get_last_audit_event_hash");
+    let pg_connection = &connection.pg_connection;
+    match audit_events
+        .select(row_hash)
+        .order(id.desc())
+        .first(pg_connection)
+    {
+        Ok(hash) => Ok(hash),
+        Err(NotFound) => Ok(None),
+        Err(error) => Err(GetAuditEventError::OtherDbError(error)),
+    }
+}
+
+/// Gets audit events with IDs above `after_id`, oldest first
+///
+/// # Errors
+///
+/// If the query fails
+pub fn get_audit_events_after(
+    connection: &DalConnection,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<AuditEvent>, GetAuditEventError> {
+    use super::schema::audit_events::dsl::*;
+
+    let _span = telemetry::db_span("dal::audit::get_audit_events_after");
+    let pg_connection = &connection.pg_connection;
+    let result = audit_events
+        .filter(id.gt(after_id))
+        .order(id)
+        .limit(limit)
+        .load(pg_connection);
+
+    match result {
+        Ok(events) => Ok(events),
+        Err(error) => Err(GetAuditEventError::OtherDbError(error)),
+    }
+}
+
+/// Conditions audit events must all meet, where set
+#[derive(Default)]
+pub struct AuditEventFilter<'a> {
+    pub event_type: Option<&'a str>,
+    pub actor_type: Option<&'a str>,
+    pub actor_id: Option<&'a str>,
+    pub target_type: Option<&'a str>,
+    pub target_id: Option<&'a str>,
+    pub ip_address: Option<&'a str>,
+    pub date_from: Option<DateTime<Utc>>,
+    pub date_to: Option<DateTime<Utc>>,
+}
+
+/// Gets audit events matching a filter with IDs below `before_id`, most
+/// recent first
+///
+/// # Errors
+///
+/// If the query fails
+pub fn search_audit_events(
+    connection: &DalConnection,
+    filter: &AuditEventFilter<'_>,
+    before_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<AuditEvent>, GetAuditEventError> {
+    use super::schema::audit_events::dsl::*;
+
+    let _span = telemetry::db_span("dal::audit::search_audit_events");
+    let mut query = audit_events.into_boxed();
+    if let Some(filter_event_type) = filter.event_type {
+        query = query.filter(event_type.eq(filter_event_type));
+    }
+    if let Some(filter_actor_type) = filter.actor_type {
+        query = query.filter(actor_type.eq(filter_actor_type));
+    }
+    if let Some(filter_actor_id) = filter.actor_id {
+        query = query.filter(actor_id.eq(filter_actor_id));
+    }
+    if let Some(filter_target_type) = filter.target_type {
+        query = query.filter(target_type.eq(filter_target_type));
+    }
+    if let Some(filter_target_id) = filter.target_id {
+        query = query.filter(target_id.eq(filter_target_id));
+    }
+    if let Some(filter_ip_address) = filter.ip_address {
+        query = query.filter(ip_address.eq(filter_ip_address));
+    }
+    if let Some(date_from) = filter.date_from {
+        query = query.filter(date_created.ge(date_from));
+    }
+    if let Some(date_to) = filter.date_to {
+        query = query.filter(date_created.lt(date_to));
+    }
+    if let Some(before_id) = before_id {
+        query = query.filter(id.lt(before_id));
+    }
+
+    let pg_connection = &connection.pg_connection;
+    let result = query.order(id.desc()).limit(limit).load(pg_connection);
+
+    match result {
+        Ok(events) => Ok(events),
+        Err(error) => Err(GetAuditEventError::OtherDbError(error)),
+    }
+}
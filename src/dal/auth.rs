@@ -1,39 +1,58 @@
 use super::{
-    schema::{auth_log, auth_tokens},
+    schema::{auth_log, auth_tokens, device_authorizations},
     DalConnection,
 };
 use chrono::{DateTime, Utc};
-use diesel::{self, prelude::*, result::Error::NotFound};
+use diesel::{
+    self,
+    prelude::*,
+    result::Error::NotFound,
+    sql_types::{BigInt, Timestamptz, Varchar},
+};
+use telemetry;
 
 #[derive(Insertable)]
 #[table_name = "auth_tokens"]
 pub struct NewAuthToken<'a> {
-    pub user_id: i64,
+    pub user_id: Option<i64>,
     pub token: Vec<u8>,
     pub date_created: DateTime<Utc>,
-    pub date_expired: DateTime<Utc>,
+    pub date_expired: Option<DateTime<Utc>>,
     pub token_type: &'a str,
+    pub client_id: Option<i64>,
+    pub scope: Option<&'a str>,
+    pub name: Option<&'a str>,
 }
 
 #[derive(Identifiable, Queryable)]
 #[table_name = "auth_tokens"]
 pub struct AuthToken {
     pub id: i64,
-    pub user_id: i64,
+    pub user_id: Option<i64>,
     pub token: Vec<u8>,
     pub date_created: DateTime<Utc>,
-    pub date_expired: DateTime<Utc>,
+    pub date_expired: Option<DateTime<Utc>>,
     pub token_type: String,
+    pub client_id: Option<i64>,
+    pub scope: Option<String>,
+    pub date_revoked: Option<DateTime<Utc>>,
+    pub name: Option<String>,
 }
 
 pub enum CreateAuthTokenError {
     OtherDbError(diesel::result::Error),
 }
 
-pub fn create_token<'a>(
+/// Inserts a token
+///
+/// # Errors
+///
+/// If the insert fails
+pub fn create_token(
     connection: &DalConnection,
-    new_token: &NewAuthToken<'a>,
+    new_token: &NewAuthToken<'_>,
 ) -> Result<AuthToken, CreateAuthTokenError> {
+    let _span = telemetry::db_span("dal::auth::create_token");
     let pg_connection = &connection.pg_connection;
     let result = diesel::insert_into(auth_tokens::table)
         .values(new_token)
@@ -50,12 +69,18 @@ pub enum GetAuthTokenError {
     OtherDbError(diesel::result::Error),
 }
 
+/// Gets a token by its ID
+///
+/// # Errors
+///
+/// `AuthTokenNotFound` if there's no token with the ID, or if the query fails
 pub fn get_auth_token(
     connection: &DalConnection,
     token_id: i64,
 ) -> Result<AuthToken, GetAuthTokenError> {
     use super::schema::auth_tokens::dsl::*;
 
+    let _span = telemetry::db_span("dal::auth::get_auth_token");
     let pg_connection = &connection.pg_connection;
     match auth_tokens.filter(id.eq(token_id)).first(pg_connection) {
         Ok(user) => Ok(user),
@@ -64,6 +89,345 @@ pub fn get_auth_token(
     }
 }
 
+/// Gets a token by the hash of its secret
+///
+/// # Errors
+///
+/// `AuthTokenNotFound` if no token has the hash, or if the query fails
+pub fn get_auth_token_by_token(
+    connection: &DalConnection,
+    token_hash: &[u8],
+) -> Result<AuthToken, GetAuthTokenError> {
+    use super::schema::auth_tokens::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::get_auth_token_by_token");
+    let pg_connection = &connection.pg_connection;
+    match auth_tokens.filter(token.eq(token_hash)).first(pg_connection) {
+        Ok(auth_token) => Ok(auth_token),
+        Err(NotFound) => Err(GetAuthTokenError::AuthTokenNotFound),
+        Err(error) => Err(GetAuthTokenError::OtherDbError(error)),
+    }
+}
+
+/// Lists a user's unrevoked tokens of the given type, oldest first
+///
+/// # Errors
+///
+/// If the query fails
+pub fn get_auth_tokens_for_user(
+    connection: &DalConnection,
+    user_id_to_check: i64,
+    token_type_to_check: &str,
+) -> Result<Vec<AuthToken>, GetAuthTokenError> {
+    use super::schema::auth_tokens::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::get_auth_tokens_for_user");
+    let pg_connection = &connection.pg_connection;
+    let result = auth_tokens
+        .filter(user_id.eq(user_id_to_check))
+        .filter(token_type.eq(token_type_to_check))
+        .filter(date_revoked.is_null())
+        .order(id)
+        .load(pg_connection);
+
+    match result {
+        Ok(tokens) => Ok(tokens),
+        Err(error) => Err(GetAuthTokenError::OtherDbError(error)),
+    }
+}
+
+pub enum RevokeAuthTokenError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Marks a token as revoked, returning whether it hadn't been revoked already
+///
+/// # Errors
+///
+/// If the update fails
+pub fn revoke_auth_token(
+    connection: &DalConnection,
+    token_id: i64,
+) -> Result<bool, RevokeAuthTokenError> {
+    use super::schema::auth_tokens::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::revoke_auth_token");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::update(
+        auth_tokens.filter(id.eq(token_id)).filter(date_revoked.is_null()),
+    )
+    .set(date_revoked.eq(Utc::now()))
+    .execute(pg_connection);
+
+    match result {
+        Ok(rows) => Ok(rows > 0),
+        Err(error) => Err(RevokeAuthTokenError::OtherDbError(error)),
+    }
+}
+
+pub enum DeleteAuthTokensError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Deletes up to `limit` tokens with IDs above `after_id` that expired or
+/// were revoked before `cutoff`, returning their IDs in order
+///
+/// # Errors
+///
+/// If the delete fails
+pub fn delete_expired_auth_tokens(
+    connection: &DalConnection,
+    cutoff: DateTime<Utc>,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<i64>, DeleteAuthTokensError> {
+    use super::schema::auth_tokens::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::delete_expired_auth_tokens");
+    let pg_connection = &connection.pg_connection;
+    let result = auth_tokens
+        .select(id)
+        .filter(id.gt(after_id))
+        .filter(date_expired.lt(cutoff).or(date_revoked.lt(cutoff)))
+        .order(id)
+        .limit(limit)
+        .load::<i64>(pg_connection)
+        .and_then(|expired_ids| {
+            diesel::delete(auth_tokens.filter(id.eq_any(&expired_ids)))
+                .execute(pg_connection)
+                .map(|_| expired_ids)
+        });
+
+    match result {
+        Ok(ids) => Ok(ids),
+        Err(error) => Err(DeleteAuthTokensError::OtherDbError(error)),
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "device_authorizations"]
+pub struct NewDeviceAuthorization<'a> {
+    pub device_code: Vec<u8>,
+    pub user_code: &'a str,
+    pub client_id: i64,
+    pub scope: &'a str,
+    pub poll_interval: i32,
+    pub date_created: DateTime<Utc>,
+    pub date_expired: DateTime<Utc>,
+}
+
+#[derive(Identifiable, Queryable)]
+#[table_name = "device_authorizations"]
+pub struct DeviceAuthorization {
+    pub id: i64,
+    pub device_code: Vec<u8>,
+    pub user_code: String,
+    pub client_id: i64,
+    pub scope: String,
+    pub user_id: Option<i64>,
+    pub poll_interval: i32,
+    pub date_created: DateTime<Utc>,
+    pub date_expired: DateTime<Utc>,
+    pub date_last_polled: Option<DateTime<Utc>>,
+    pub date_approved: Option<DateTime<Utc>>,
+    pub date_denied: Option<DateTime<Utc>>,
+    pub date_used: Option<DateTime<Utc>>,
+}
+
+pub enum CreateDeviceAuthorizationError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Inserts a device authorization request
+///
+/// # Errors
+///
+/// If the insert fails
+pub fn create_device_authorization(
+    connection: &DalConnection,
+    new_authorization: &NewDeviceAuthorization<'_>,
+) -> Result<DeviceAuthorization, CreateDeviceAuthorizationError> {
+    let _span = telemetry::db_span("dal::auth::create_device_authorization");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::insert_into(device_authorizations::table)
+        .values(new_authorization)
+        .get_result(pg_connection);
+    match result {
+        Ok(authorization) => Ok(authorization),
+        Err(error) => Err(CreateDeviceAuthorizationError::OtherDbError(error)),
+    }
+}
+
+pub enum GetDeviceAuthorizationError {
+    DeviceAuthorizationNotFound,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Gets a device authorization request by the hash of its device code
+///
+/// # Errors
+///
+/// `DeviceAuthorizationNotFound` if no request has the hash, or if the query
+/// fails
+pub fn get_device_authorization_by_device_code(
+    connection: &DalConnection,
+    device_code_hash: &[u8],
+) -> Result<DeviceAuthorization, GetDeviceAuthorizationError> {
+    use super::schema::device_authorizations::dsl::*;
+
+    let _span = telemetry::db_span(
+        "dal::auth::get_device_authorization_by_device_code",
+    );
+    let pg_connection = &connection.pg_connection;
+    let result = device_authorizations
+        .filter(device_code.eq(device_code_hash))
+        .first(pg_connection);
+
+    match result {
+        Ok(authorization) => Ok(authorization),
+        Err(NotFound) => {
+            Err(GetDeviceAuthorizationError::DeviceAuthorizationNotFound)
+        }
+        Err(error) => Err(GetDeviceAuthorizationError::OtherDbError(error)),
+    }
+}
+
+/// Finds an unexpired request with `user_code_to_check` that the user hasn't
+/// approved or denied yet
+///
+/// # Errors
+///
+/// `DeviceAuthorizationNotFound` if there's no such pending request, or if the
+/// query fails
+pub fn get_pending_device_authorization(
+    connection: &DalConnection,
+    user_code_to_check: &str,
+) -> Result<DeviceAuthorization, GetDeviceAuthorizationError> {
+    use super::schema::device_authorizations::dsl::*;
+
+    let _span =
+        telemetry::db_span("dal::auth::get_pending_device_authorization");
+    let pg_connection = &connection.pg_connection;
+    let result = device_authorizations
+        .filter(user_code.eq(user_code_to_check))
+        .filter(date_expired.gt(Utc::now()))
+        .filter(date_approved.is_null())
+        .filter(date_denied.is_null())
+        .first(pg_connection);
+
+    match result {
+        Ok(authorization) => Ok(authorization),
+        Err(NotFound) => {
+            Err(GetDeviceAuthorizationError::DeviceAuthorizationNotFound)
+        }
+        Err(error) => Err(GetDeviceAuthorizationError::OtherDbError(error)),
+    }
+}
+
+pub enum UpdateDeviceAuthorizationError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Records the user's decision on a pending request. `approved_by` is the
+/// approving user, or `None` if the request was denied. Returns whether the
+/// request was still pending.
+///
+/// # Errors
+///
+/// If the update fails
+pub fn decide_device_authorization(
+    connection: &DalConnection,
+    authorization_id: i64,
+    approved_by: Option<i64>,
+) -> Result<bool, UpdateDeviceAuthorizationError> {
+    use super::schema::device_authorizations::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::decide_device_authorization");
+    let pg_connection = &connection.pg_connection;
+    let pending = device_authorizations
+        .filter(id.eq(authorization_id))
+        .filter(date_approved.is_null())
+        .filter(date_denied.is_null());
+    let result = approved_by.map_or_else(
+        || {
+            diesel::update(pending)
+                .set(date_denied.eq(Utc::now()))
+                .execute(pg_connection)
+        },
+        |approving_user_id| {
+            diesel::update(pending)
+                .set((
+                    user_id.eq(approving_user_id),
+                    date_approved.eq(Utc::now()),
+                ))
+                .execute(pg_connection)
+        },
+    );
+
+    match result {
+        Ok(rows) => Ok(rows > 0),
+        Err(error) => Err(UpdateDeviceAuthorizationError::OtherDbError(error)),
+    }
+}
+
+/// Records a poll from the device along with the interval it must wait
+/// before polling again
+///
+/// # Errors
+///
+/// If the update fails
+pub fn record_device_poll(
+    connection: &DalConnection,
+    authorization_id: i64,
+    polled_at: DateTime<Utc>,
+    new_poll_interval: i32,
+) -> Result<(), UpdateDeviceAuthorizationError> {
+    use super::schema::device_authorizations::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::record_device_poll");
+    let pg_connection = &connection.pg_connection;
+    let result =
+        diesel::update(device_authorizations.filter(id.eq(authorization_id)))
+            .set((
+                date_last_polled.eq(polled_at),
+                poll_interval.eq(new_poll_interval),
+            ))
+            .execute(pg_connection);
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(error) => Err(UpdateDeviceAuthorizationError::OtherDbError(error)),
+    }
+}
+
+/// Marks an approved request as used, returning whether it hadn't been used
+/// already, so each device code only ever yields one token
+///
+/// # Errors
+///
+/// If the update fails
+pub fn redeem_device_authorization(
+    connection: &DalConnection,
+    authorization_id: i64,
+) -> Result<bool, UpdateDeviceAuthorizationError> {
+    use super::schema::device_authorizations::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::redeem_device_authorization");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::update(
+        device_authorizations
+            .filter(id.eq(authorization_id))
+            .filter(date_used.is_null()),
+    )
+    .set(date_used.eq(Utc::now()))
+    .execute(pg_connection);
+
+    match result {
+        Ok(rows) => Ok(rows > 0),
+        Err(error) => Err(UpdateDeviceAuthorizationError::OtherDbError(error)),
+    }
+}
+
 #[derive(Insertable)]
 #[table_name = "auth_log"]
 pub struct NewAuthLog<'a> {
@@ -72,6 +436,11 @@ pub struct NewAuthLog<'a> {
     pub ip_address: &'a str,
     pub user_agent: &'a str,
     pub date_created: DateTime<Utc>,
+    pub flagged: bool,
+    /// Comma separated reasons the attempt looked suspicious
+    pub flag_reasons: Option<&'a str>,
+    pub previous_hash: Option<&'a [u8]>,
+    pub row_hash: Option<&'a [u8]>,
 }
 
 #[derive(Identifiable, Queryable)]
@@ -83,16 +452,26 @@ pub struct AuthLog {
     pub ip_address: String,
     pub user_agent: String,
     pub date_created: DateTime<Utc>,
+    pub flagged: bool,
+    pub flag_reasons: Option<String>,
+    pub previous_hash: Option<Vec<u8>>,
+    pub row_hash: Option<Vec<u8>>,
 }
 
 pub enum CreateAuthLogError {
     OtherDbError(diesel::result::Error),
 }
 
-pub fn create_auth_log<'a>(
+/// Inserts an auth log entry
+///
+/// # Errors
+///
+/// If the insert fails
+pub fn create_auth_log(
     connection: &DalConnection,
-    new_log: &NewAuthLog<'a>,
+    new_log: &NewAuthLog<'_>,
 ) -> Result<AuthLog, CreateAuthLogError> {
+    let _span = telemetry::db_span("dal::auth::create_auth_log");
     let pg_connection = &connection.pg_connection;
     let result = diesel::insert_into(auth_log::table)
         .values(new_log)
@@ -102,3 +481,288 @@ pub fn create_auth_log<'a>(
         Err(error) => Err(CreateAuthLogError::OtherDbError(error)),
     }
 }
+
+pub enum GetAuthLogError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Gets the successful or failed login attempts for an email since a date,
+/// most recent first
+///
+/// # Errors
+///
+/// If the query fails
+pub fn get_auth_logs_for_email(
+    connection: &DalConnection,
+    log_email: &str,
+    log_success: bool,
+    since: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<AuthLog>, GetAuthLogError> {
+    use super::schema::auth_log::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::get_auth_logs_for_email");
+    let pg_connection = &connection.pg_connection;
+    let result = auth_log
+        .filter(email.eq(log_email))
+        .filter(success.eq(log_success))
+        .filter(date_created.ge(since))
+        .order(date_created.desc())
+        .limit(limit)
+        .load(pg_connection);
+
+    match result {
+        Ok(logs) => Ok(logs),
+        Err(error) => Err(GetAuthLogError::OtherDbError(error)),
+    }
+}
+
+/// Gets the hash of the most recent auth log entry, if it has one
+///
+/// # Errors
+///
+/// If the query fails
+pub fn get_last_auth_log_hash(
+    connection: &DalConnection,
+) -> Result<Option<Vec<u8>>, GetAuthLogError> {
+    use super::schema::auth_log::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::get_last_auth_log_hash");
+    let pg_connection = &connection.pg_connection;
+    match auth_log.select(row_hash).order(id.desc()).first(pg_connection) {
+        Ok(hash) => Ok(hash),
+        Err(NotFound) => Ok(None),
+        Err(error) => Err(GetAuthLogError::OtherDbError(error)),
+    }
+}
+
+/// Gets auth log entries with IDs above `after_id`, oldest first
+///
+/// # Errors
+///
+/// If the query fails
+pub fn get_auth_logs_after(
+    connection: &DalConnection,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<AuthLog>, GetAuthLogError> {
+    use super::schema::auth_log::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::get_auth_logs_after");
+    let pg_connection = &connection.pg_connection;
+    let result = auth_log
+        .filter(id.gt(after_id))
+        .order(id)
+        .limit(limit)
+        .load(pg_connection);
+
+    match result {
+        Ok(logs) => Ok(logs),
+        Err(error) => Err(GetAuthLogError::OtherDbError(error)),
+    }
+}
+
+/// Gets the IDs and dates of the oldest auth log entries, oldest first
+///
+/// # Errors
+///
+/// If the query fails
+pub fn get_oldest_auth_log_dates(
+    connection: &DalConnection,
+    limit: i64,
+) -> Result<Vec<(i64, DateTime<Utc>)>, GetAuthLogError> {
+    use super::schema::auth_log::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::get_oldest_auth_log_dates");
+    let pg_connection = &connection.pg_connection;
+    let result = auth_log
+        .select((id, date_created))
+        .order(id)
+        .limit(limit)
+        .load(pg_connection);
+
+    match result {
+        Ok(dates) => Ok(dates),
+        Err(error) => Err(GetAuthLogError::OtherDbError(error)),
+    }
+}
+
+pub enum DeleteAuthLogError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Deletes the auth log entries with IDs up to `last_id`, returning them
+///
+/// # Errors
+///
+/// If the delete fails
+pub fn delete_auth_logs_through(
+    connection: &DalConnection,
+    last_id: i64,
+) -> Result<Vec<AuthLog>, DeleteAuthLogError> {
+    use super::schema::auth_log::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::delete_auth_logs_through");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::delete(auth_log.filter(id.le(last_id)))
+        .get_results(pg_connection);
+
+    match result {
+        Ok(logs) => Ok(logs),
+        Err(error) => Err(DeleteAuthLogError::OtherDbError(error)),
+    }
+}
+
+/// Conditions auth log entries must all meet, where set
+#[derive(Default)]
+pub struct AuthLogFilter<'a> {
+    pub email: Option<&'a str>,
+    pub ip_address: Option<&'a str>,
+    pub success: Option<bool>,
+    pub flagged: Option<bool>,
+    pub date_from: Option<DateTime<Utc>>,
+    pub date_to: Option<DateTime<Utc>>,
+}
+
+/// Gets auth log entries matching a filter with IDs below `before_id`, most
+/// recent first
+///
+/// # Errors
+///
+/// If the query fails
+pub fn search_auth_logs(
+    connection: &DalConnection,
+    filter: &AuthLogFilter<'_>,
+    before_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<AuthLog>, GetAuthLogError> {
+    use super::schema::auth_log::dsl::*;
+
+    let _span = telemetry::db_span("dal::auth::search_auth_logs");
+    let mut query = auth_log.into_boxed();
+    if let Some(log_email) = filter.email {
+        query = query.filter(email.eq(log_email));
+    }
+    if let Some(log_ip_address) = filter.ip_address {
+        query = query.filter(ip_address.eq(log_ip_address));
+    }
+    if let Some(log_success) = filter.success {
+        query = query.filter(success.eq(log_success));
+    }
+    if let Some(log_flagged) = filter.flagged {
+        query = query.filter(flagged.eq(log_flagged));
+    }
+    if let Some(date_from) = filter.date_from {
+        query = query.filter(date_created.ge(date_from));
+    }
+    if let Some(date_to) = filter.date_to {
+        query = query.filter(date_created.lt(date_to));
+    }
+    if let Some(before_id) = before_id {
+        query = query.filter(id.lt(before_id));
+    }
+
+    let pg_connection = &connection.pg_connection;
+    let result = query.order(id.desc()).limit(limit).load(pg_connection);
+
+    match result {
+        Ok(logs) => Ok(logs),
+        Err(error) => Err(GetAuthLogError::OtherDbError(error)),
+    }
+}
+
+#[derive(QueryableByName)]
+pub struct IpFailureCount {
+    #[sql_type = "Varchar"]
+    pub ip_address: String,
+    #[sql_type = "Timestamptz"]
+    pub hour: DateTime<Utc>,
+    #[sql_type = "BigInt"]
+    pub failures: i64,
+}
+
+/// Counts failed login attempts from each IP address in each hour between
+/// two dates, busiest first
+///
+/// # Errors
+///
+/// If the query fails
+pub fn count_failures_by_ip_hour(
+    connection: &DalConnection,
+    date_from: DateTime<Utc>,
+    date_to: DateTime<Utc>,
+    min_failures: i64,
+    limit: i64,
+) -> Result<Vec<IpFailureCount>, GetAuthLogError> {
+    let _span = telemetry::db_span("dal::auth::count_failures_by_ip_hour");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::sql_query(
+        "SELECT ip_address, \
+             date_trunc('hour', date_created AT TIME ZONE 'utc') \
+                 AT TIME ZONE 'utc' AS hour, \
+             count(*) AS failures \
+         FROM auth_log \
+         WHERE NOT success AND date_created >= $1 AND date_created < $2 \
+         GROUP BY ip_address, hour \
+         HAVING count(*) >= $3 \
+         ORDER BY failures DESC, hour DESC, ip_address \
+         LIMIT $4",
+    )
+    .bind::<Timestamptz, _>(date_from)
+    .bind::<Timestamptz, _>(date_to)
+    .bind::<BigInt, _>(min_failures)
+    .bind::<BigInt, _>(limit)
+    .load(pg_connection);
+
+    match result {
+        Ok(counts) => Ok(counts),
+        Err(error) => Err(GetAuthLogError::OtherDbError(error)),
+    }
+}
+
+#[derive(QueryableByName)]
+pub struct TargetedAccount {
+    #[sql_type = "Varchar"]
+    pub email: String,
+    #[sql_type = "BigInt"]
+    pub failures: i64,
+    #[sql_type = "BigInt"]
+    pub ip_addresses: i64,
+    #[sql_type = "Timestamptz"]
+    pub last_failure: DateTime<Utc>,
+}
+
+/// Finds the emails with the most failed login attempts between two dates
+///
+/// # Errors
+///
+/// If the query fails
+pub fn get_targeted_accounts(
+    connection: &DalConnection,
+    date_from: DateTime<Utc>,
+    date_to: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<TargetedAccount>, GetAuthLogError> {
+    let _span = telemetry::db_span("dal::auth::get_targeted_accounts");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::sql_query(
+        "SELECT email, \
+             count(*) AS failures, \
+             count(DISTINCT ip_address) AS ip_addresses, \
+             max(date_created) AS last_failure \
+         FROM auth_log \
+         WHERE NOT success AND date_created >= $1 AND date_created < $2 \
+         GROUP BY email \
+         ORDER BY failures DESC, email \
+         LIMIT $3",
+    )
+    .bind::<Timestamptz, _>(date_from)
+    .bind::<Timestamptz, _>(date_to)
+    .bind::<BigInt, _>(limit)
+    .load(pg_connection);
+
+    match result {
+        Ok(accounts) => Ok(accounts),
+        Err(error) => Err(GetAuthLogError::OtherDbError(error)),
+    }
+}
@@ -1,23 +1,157 @@
+// Queries glob import their table's DSL, as diesel intends
+#![allow(clippy::wildcard_imports)]
+
+pub mod audit;
 pub mod auth;
+pub mod oauth;
+pub mod roles;
 pub mod schema;
 pub mod users;
 
-use diesel::{pg::PgConnection, Connection};
+use diesel::{
+    self,
+    pg::PgConnection,
+    r2d2::{ConnectionManager, Pool, PooledConnection},
+    sql_types::{BigInt, Bool, Varchar},
+    Connection,
+    RunQueryDsl,
+};
+use std::time::Duration;
+use telemetry;
+
+pub type DalPool = Pool<ConnectionManager<PgConnection>>;
 
+/// Connects to the database outside of the pool
+///
+/// # Panics
+///
+/// If the database can't be connected to
+#[must_use]
 pub fn establish_connection(database_url: &str) -> PgConnection {
     PgConnection::establish(database_url)
         .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
 }
 
+/// Creates a pool of up to `max_connections` connections
+///
+/// # Panics
+///
+/// If the pool can't connect to the database
+#[must_use]
+pub fn create_pool(
+    database_url: &str,
+    max_connections: u32,
+    connection_timeout: Duration,
+) -> DalPool {
+    Pool::builder()
+        .max_size(max_connections)
+        .connection_timeout(connection_timeout)
+        .build(ConnectionManager::new(database_url))
+        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
+}
+
 /// Thin wrapper to reduce refactoring work should connection code get changed
 pub struct DalConnection {
-    pub pg_connection: PgConnection,
+    pub pg_connection: PooledConnection<ConnectionManager<PgConnection>>,
 }
 
 impl DalConnection {
-    pub const fn new(connection: PgConnection) -> Self {
+    pub const fn new(
+        connection: PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Self {
         Self {
             pg_connection: connection,
         }
     }
 }
+
+pub enum LockError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Takes an advisory lock on `key`, waiting for any other transaction holding
+/// it. The lock is held until the transaction ends.
+///
+/// # Errors
+///
+/// If the query fails
+pub fn lock_for_transaction(
+    connection: &DalConnection,
+    key: i64,
+) -> Result<(), LockError> {
+    let _span = telemetry::db_span("dal::lock_for_transaction");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+        .bind::<BigInt, _>(key)
+        .execute(pg_connection);
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(error) => Err(LockError::OtherDbError(error)),
+    }
+}
+
+#[derive(QueryableByName)]
+struct LockTaken {
+    #[sql_type = "Bool"]
+    locked: bool,
+}
+
+/// Takes an advisory lock on `key` if no other transaction holds it,
+/// returning whether it was taken. The lock is held until the transaction
+/// ends.
+///
+/// # Errors
+///
+/// If the query fails
+pub fn try_lock_for_transaction(
+    connection: &DalConnection,
+    key: i64,
+) -> Result<bool, LockError> {
+    let _span = telemetry::db_span("dal::try_lock_for_transaction");
+    let pg_connection = &connection.pg_connection;
+    let result =
+        diesel::sql_query("SELECT pg_try_advisory_xact_lock($1) AS locked")
+            .bind::<BigInt, _>(key)
+            .get_result::<LockTaken>(pg_connection);
+
+    match result {
+        Ok(lock) => Ok(lock.locked),
+        Err(error) => Err(LockError::OtherDbError(error)),
+    }
+}
+
+pub enum GetMigrationError {
+    OtherDbError(diesel::result::Error),
+}
+
+#[derive(QueryableByName)]
+struct MigrationApplied {
+    #[sql_type = "Bool"]
+    applied: bool,
+}
+
+/// Checks whether diesel has run the migration with the given version
+///
+/// # Errors
+///
+/// If the query fails
+pub fn is_migration_applied(
+    connection: &DalConnection,
+    version: &str,
+) -> Result<bool, GetMigrationError> {
+    let _span = telemetry::db_span("dal::is_migration_applied");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::sql_query(
+        "SELECT EXISTS( \
+             SELECT 1 FROM __diesel_schema_migrations WHERE version = $1 \
+         ) AS applied",
+    )
+    .bind::<Varchar, _>(version)
+    .get_result::<MigrationApplied>(pg_connection);
+
+    match result {
+        Ok(migration) => Ok(migration.applied),
+        Err(error) => Err(GetMigrationError::OtherDbError(error)),
+    }
+}
@@ -0,0 +1,196 @@
+use super::{
+    schema::{oauth_authorization_codes, oauth_clients},
+    DalConnection,
+};
+use chrono::{DateTime, Utc};
+use diesel::{self, prelude::*, result::Error::NotFound};
+use telemetry;
+
+#[derive(Insertable)]
+#[table_name = "oauth_clients"]
+pub struct NewOAuthClient<'a> {
+    pub client_id: &'a str,
+    pub client_secret: Option<Vec<u8>>,
+    pub name: &'a str,
+    pub redirect_uris: &'a [String],
+    pub grant_types: &'a [String],
+    pub allowed_scopes: &'a [String],
+}
+
+#[derive(Identifiable, Queryable)]
+#[table_name = "oauth_clients"]
+pub struct OAuthClient {
+    pub id: i64,
+    pub client_id: String,
+    pub client_secret: Option<Vec<u8>>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub date_created: DateTime<Utc>,
+    pub grant_types: Vec<String>,
+    pub allowed_scopes: Vec<String>,
+}
+
+pub enum CreateOAuthClientError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Inserts a client
+///
+/// # Errors
+///
+/// If the insert fails
+pub fn create_client(
+    connection: &DalConnection,
+    new_client: &NewOAuthClient<'_>,
+) -> Result<OAuthClient, CreateOAuthClientError> {
+    let _span = telemetry::db_span("dal::oauth::create_client");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::insert_into(oauth_clients::table)
+        .values(new_client)
+        .get_result(pg_connection);
+    match result {
+        Ok(client) => Ok(client),
+        Err(error) => Err(CreateOAuthClientError::OtherDbError(error)),
+    }
+}
+
+pub enum GetOAuthClientError {
+    ClientNotFound,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Gets a client by its database ID
+///
+/// # Errors
+///
+/// `ClientNotFound` if there's no client with the ID, or if the query fails
+pub fn get_client_by_id(
+    connection: &DalConnection,
+    id_to_check: i64,
+) -> Result<OAuthClient, GetOAuthClientError> {
+    use super::schema::oauth_clients::dsl::*;
+
+    let _span = telemetry::db_span("dal::oauth::get_client_by_id");
+    let pg_connection = &connection.pg_connection;
+    let result = oauth_clients.filter(id.eq(id_to_check)).first(pg_connection);
+
+    match result {
+        Ok(client) => Ok(client),
+        Err(NotFound) => Err(GetOAuthClientError::ClientNotFound),
+        Err(error) => Err(GetOAuthClientError::OtherDbError(error)),
+    }
+}
+
+/// Gets a client by its public client ID
+///
+/// # Errors
+///
+/// `ClientNotFound` if there's no client with the client ID, or if the query
+/// fails
+pub fn get_client_by_client_id(
+    connection: &DalConnection,
+    client_id_to_check: &str,
+) -> Result<OAuthClient, GetOAuthClientError> {
+    use super::schema::oauth_clients::dsl::*;
+
+    let _span = telemetry::db_span("dal::oauth::get_client_by_client_id");
+    let pg_connection = &connection.pg_connection;
+    let result = oauth_clients
+        .filter(client_id.eq(client_id_to_check))
+        .first(pg_connection);
+
+    match result {
+        Ok(client) => Ok(client),
+        Err(NotFound) => Err(GetOAuthClientError::ClientNotFound),
+        Err(error) => Err(GetOAuthClientError::OtherDbError(error)),
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "oauth_authorization_codes"]
+pub struct NewAuthorizationCode<'a> {
+    pub code: Vec<u8>,
+    pub client_id: i64,
+    pub user_id: i64,
+    pub redirect_uri: &'a str,
+    pub scope: &'a str,
+    pub code_challenge: &'a str,
+    pub date_created: DateTime<Utc>,
+    pub date_expired: DateTime<Utc>,
+    pub nonce: Option<&'a str>,
+}
+
+#[derive(Identifiable, Queryable)]
+#[table_name = "oauth_authorization_codes"]
+pub struct AuthorizationCode {
+    pub id: i64,
+    pub code: Vec<u8>,
+    pub client_id: i64,
+    pub user_id: i64,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub code_challenge: String,
+    pub date_created: DateTime<Utc>,
+    pub date_expired: DateTime<Utc>,
+    pub date_used: Option<DateTime<Utc>>,
+    pub nonce: Option<String>,
+}
+
+pub enum CreateAuthorizationCodeError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Inserts an authorization code
+///
+/// # Errors
+///
+/// If the insert fails
+pub fn create_authorization_code(
+    connection: &DalConnection,
+    new_code: &NewAuthorizationCode<'_>,
+) -> Result<AuthorizationCode, CreateAuthorizationCodeError> {
+    let _span = telemetry::db_span("dal::oauth::create_authorization_code");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::insert_into(oauth_authorization_codes::table)
+        .values(new_code)
+        .get_result(pg_connection);
+    match result {
+        Ok(code) => Ok(code),
+        Err(error) => Err(CreateAuthorizationCodeError::OtherDbError(error)),
+    }
+}
+
+pub enum RedeemAuthorizationCodeError {
+    CodeNotFound,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Marks an unused authorization code as used and returns it, so a code can
+/// only ever be redeemed once
+///
+/// # Errors
+///
+/// `CodeNotFound` if the code doesn't exist or was already used, or if the
+/// update fails
+pub fn redeem_authorization_code(
+    connection: &DalConnection,
+    code_hash: &[u8],
+) -> Result<AuthorizationCode, RedeemAuthorizationCodeError> {
+    use super::schema::oauth_authorization_codes::dsl::*;
+
+    let _span = telemetry::db_span("dal::oauth::redeem_authorization_code");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::update(
+        oauth_authorization_codes
+            .filter(code.eq(code_hash))
+            .filter(date_used.is_null()),
+    )
+    .set(date_used.eq(Utc::now()))
+    .get_result(pg_connection);
+
+    match result {
+        Ok(authorization_code) => Ok(authorization_code),
+        Err(NotFound) => Err(RedeemAuthorizationCodeError::CodeNotFound),
+        Err(error) => Err(RedeemAuthorizationCodeError::OtherDbError(error)),
+    }
+}
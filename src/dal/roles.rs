@@ -0,0 +1,32 @@
+use super::DalConnection;
+use diesel::{self, prelude::*};
+use telemetry;
+
+pub enum GetRolesError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Gets the names of a user's roles, in alphabetical order
+///
+/// # Errors
+///
+/// If the query fails
+pub fn get_roles_for_user(
+    connection: &DalConnection,
+    user_id_to_check: i64,
+) -> Result<Vec<String>, GetRolesError> {
+    use super::schema::user_roles::dsl::*;
+
+    let _span = telemetry::db_span("dal::roles::get_roles_for_user");
+    let pg_connection = &connection.pg_connection;
+    let result = user_roles
+        .filter(user_id.eq(user_id_to_check))
+        .select(role)
+        .order(role)
+        .load(pg_connection);
+
+    match result {
+        Ok(roles) => Ok(roles),
+        Err(error) => Err(GetRolesError::OtherDbError(error)),
+    }
+}
@@ -1,3 +1,20 @@
+table! {
+    audit_events (id) {
+        id -> Int8,
+        event_type -> Varchar,
+        actor_type -> Varchar,
+        actor_id -> Nullable<Varchar>,
+        target_type -> Varchar,
+        target_id -> Varchar,
+        ip_address -> Varchar,
+        user_agent -> Varchar,
+        details -> Jsonb,
+        date_created -> Timestamptz,
+        previous_hash -> Nullable<Bytea>,
+        row_hash -> Nullable<Bytea>,
+    }
+}
+
 table! {
     auth_log (id) {
         id -> Int8,
@@ -6,17 +23,80 @@ table! {
         ip_address -> Varchar,
         user_agent -> Varchar,
         date_created -> Timestamptz,
+        flagged -> Bool,
+        flag_reasons -> Nullable<Varchar>,
+        previous_hash -> Nullable<Bytea>,
+        row_hash -> Nullable<Bytea>,
     }
 }
 
 table! {
     auth_tokens (id) {
         id -> Int8,
-        user_id -> Int8,
+        user_id -> Nullable<Int8>,
         token -> Bytea,
         date_created -> Timestamptz,
-        date_expired -> Timestamptz,
+        date_expired -> Nullable<Timestamptz>,
         token_type -> Varchar,
+        client_id -> Nullable<Int8>,
+        scope -> Nullable<Varchar>,
+        date_revoked -> Nullable<Timestamptz>,
+        name -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    device_authorizations (id) {
+        id -> Int8,
+        device_code -> Bytea,
+        user_code -> Varchar,
+        client_id -> Int8,
+        scope -> Varchar,
+        user_id -> Nullable<Int8>,
+        poll_interval -> Int4,
+        date_created -> Timestamptz,
+        date_expired -> Timestamptz,
+        date_last_polled -> Nullable<Timestamptz>,
+        date_approved -> Nullable<Timestamptz>,
+        date_denied -> Nullable<Timestamptz>,
+        date_used -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    oauth_authorization_codes (id) {
+        id -> Int8,
+        code -> Bytea,
+        client_id -> Int8,
+        user_id -> Int8,
+        redirect_uri -> Text,
+        scope -> Varchar,
+        code_challenge -> Varchar,
+        date_created -> Timestamptz,
+        date_expired -> Timestamptz,
+        date_used -> Nullable<Timestamptz>,
+        nonce -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    oauth_clients (id) {
+        id -> Int8,
+        client_id -> Varchar,
+        client_secret -> Nullable<Bytea>,
+        name -> Varchar,
+        redirect_uris -> Array<Text>,
+        date_created -> Timestamptz,
+        grant_types -> Array<Text>,
+        allowed_scopes -> Array<Text>,
+    }
+}
+
+table! {
+    user_roles (user_id, role) {
+        user_id -> Int8,
+        role -> Varchar,
+        date_created -> Timestamptz,
     }
 }
 
@@ -27,9 +107,25 @@ table! {
         password -> Varchar,
         date_created -> Timestamptz,
         date_modified -> Timestamptz,
+        pepper_id -> Int4,
     }
 }
 
+joinable!(auth_tokens -> oauth_clients (client_id));
 joinable!(auth_tokens -> users (user_id));
+joinable!(device_authorizations -> oauth_clients (client_id));
+joinable!(device_authorizations -> users (user_id));
+joinable!(oauth_authorization_codes -> oauth_clients (client_id));
+joinable!(oauth_authorization_codes -> users (user_id));
+joinable!(user_roles -> users (user_id));
 
-allow_tables_to_appear_in_same_query!(auth_log, auth_tokens, users,);
+allow_tables_to_appear_in_same_query!(
+    audit_events,
+    auth_log,
+    auth_tokens,
+    device_authorizations,
+    oauth_authorization_codes,
+    oauth_clients,
+    user_roles,
+    users,
+);
@@ -8,12 +8,14 @@ use diesel::{
         Error::{DatabaseError, NotFound},
     },
 };
+use telemetry;
 
 #[derive(Insertable)]
 #[table_name = "users"]
 pub struct NewUser<'a> {
     pub email: &'a str,
     pub password: &'a str,
+    pub pepper_id: i32,
 }
 
 #[derive(Identifiable, Queryable)]
@@ -23,6 +25,7 @@ pub struct User {
     pub password: String,
     pub date_created: DateTime<Utc>,
     pub date_modified: DateTime<Utc>,
+    pub pepper_id: i32,
 }
 
 pub enum CreateUserError {
@@ -30,10 +33,16 @@ pub enum CreateUserError {
     OtherDbError(diesel::result::Error),
 }
 
-pub fn create_user<'a>(
+/// Inserts a user
+///
+/// # Errors
+///
+/// `EmailExists` if a user already has the email, or if the insert fails
+pub fn create_user(
     connection: &DalConnection,
-    new_user: &NewUser<'a>,
+    new_user: &NewUser<'_>,
 ) -> Result<User, CreateUserError> {
+    let _span = telemetry::db_span("dal::users::create_user");
     let pg_connection = &connection.pg_connection;
     let result = diesel::insert_into(users::table)
         .values(new_user)
@@ -57,12 +66,18 @@ pub enum GetUserError {
     OtherDbError(diesel::result::Error),
 }
 
+/// Gets a user by their email address
+///
+/// # Errors
+///
+/// `UserNotFound` if no user has the email, or if the query fails
 pub fn get_user_by_email(
     connection: &DalConnection,
     email_to_check: &str,
 ) -> Result<User, GetUserError> {
     use super::schema::users::dsl::*;
 
+    let _span = telemetry::db_span("dal::users::get_user_by_email");
     let pg_connection = &connection.pg_connection;
     let result = users.filter(email.eq(email_to_check)).first(pg_connection);
 
@@ -73,12 +88,18 @@ pub fn get_user_by_email(
     }
 }
 
+/// Gets a user by their ID
+///
+/// # Errors
+///
+/// `UserNotFound` if there's no user with the ID, or if the query fails
 pub fn get_user_by_id(
     connection: &DalConnection,
     user_id: i64,
 ) -> Result<User, GetUserError> {
     use super::schema::users::dsl::*;
 
+    let _span = telemetry::db_span("dal::users::get_user_by_id");
     let pg_connection = &connection.pg_connection;
     let result = users.filter(id.eq(user_id)).first(pg_connection);
 
@@ -88,3 +109,68 @@ pub fn get_user_by_id(
         Err(error) => Err(GetUserError::OtherDbError(error)),
     }
 }
+
+pub enum UpdateUserError {
+    UserNotFound,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Replaces a user's password hash along with the pepper it used
+///
+/// # Errors
+///
+/// `UserNotFound` if there's no user with the ID, or if the update fails
+pub fn update_user_password(
+    connection: &DalConnection,
+    user_id: i64,
+    password_hash: &str,
+    password_pepper_id: i32,
+) -> Result<User, UpdateUserError> {
+    use super::schema::users::dsl::*;
+
+    let _span = telemetry::db_span("dal::users::update_user_password");
+    let pg_connection = &connection.pg_connection;
+    let result = diesel::update(users.filter(id.eq(user_id)))
+        .set((
+            password.eq(password_hash),
+            pepper_id.eq(password_pepper_id),
+            date_modified.eq(Utc::now()),
+        ))
+        .get_result(pg_connection);
+
+    match result {
+        Ok(user) => Ok(user),
+        Err(NotFound) => Err(UpdateUserError::UserNotFound),
+        Err(error) => Err(UpdateUserError::OtherDbError(error)),
+    }
+}
+
+pub enum CountUsersError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Counts users by the pepper their password was hashed with
+///
+/// # Errors
+///
+/// If the query fails
+pub fn count_users_by_pepper(
+    connection: &DalConnection,
+) -> Result<Vec<(i32, i64)>, CountUsersError> {
+    use super::schema::users::dsl::*;
+    use diesel::{dsl::sql, sql_types::BigInt};
+
+    let _span = telemetry::db_span("dal::users::count_users_by_pepper");
+    // Diesel 1.x can't select aggregates alongside grouped columns
+    let pg_connection = &connection.pg_connection;
+    let result = users
+        .group_by(pepper_id)
+        .select((pepper_id, sql::<BigInt>("count(*)")))
+        .order(pepper_id)
+        .load(pg_connection);
+
+    match result {
+        Ok(counts) => Ok(counts),
+        Err(error) => Err(CountUsersError::OtherDbError(error)),
+    }
+}
@@ -0,0 +1,192 @@
+use chrono::Utc;
+use client::ClientInfo;
+use dal::{
+    self,
+    audit::{
+        AuditEvent,
+        AuditEventFilter,
+        CreateAuditEventError,
+        GetAuditEventError,
+        NewAuditEvent,
+    },
+    DalConnection,
+};
+use handlers::{
+    hash_chain::{self, Chain, LinkError},
+    pagination::{self, Page, SearchPageError},
+    user::Principal,
+};
+use serde_json::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventType {
+    UserCreated,
+    TokenCreated,
+    TokenRevoked,
+    ClientRegistered,
+    AuthorizationGranted,
+    DeviceAuthorizationApproved,
+    DeviceAuthorizationDenied,
+}
+
+impl EventType {
+    /// Identifies the event type in the audit log
+    #[must_use]
+    pub const fn code(self) -> &'static str {
+        match self {
+            Self::UserCreated => "user.created",
+            Self::TokenCreated => "token.created",
+            Self::TokenRevoked => "token.revoked",
+            Self::ClientRegistered => "oauth_client.registered",
+            Self::AuthorizationGranted => "oauth_authorization.granted",
+            Self::DeviceAuthorizationApproved => {
+                "device_authorization.approved"
+            }
+            Self::DeviceAuthorizationDenied => "device_authorization.denied",
+        }
+    }
+}
+
+/// Who caused an event
+pub enum Actor<'a> {
+    /// Someone who hadn't signed in, such as a new user signing up
+    Anonymous,
+    User(i64),
+    /// An OAuth client acting for itself, by its public client ID
+    Client(&'a str),
+}
+
+impl<'a> Actor<'a> {
+    #[must_use]
+    pub fn from_principal(principal: &'a Principal) -> Self {
+        match principal {
+            Principal::User { user_id, .. } => Self::User(*user_id),
+            Principal::Service { client_id, .. } => Self::Client(client_id),
+        }
+    }
+
+    const fn actor_type(&self) -> &'static str {
+        match self {
+            Self::Anonymous => "anonymous",
+            Self::User(_) => "user",
+            Self::Client(_) => "client",
+        }
+    }
+
+    fn actor_id(&self) -> Option<String> {
+        match self {
+            Self::Anonymous => None,
+            Self::User(user_id) => Some(user_id.to_string()),
+            Self::Client(client_id) => Some((*client_id).to_owned()),
+        }
+    }
+}
+
+/// What an event happened to
+pub enum Target<'a> {
+    User(i64),
+    Token(i64),
+    /// An OAuth client, by its public client ID
+    Client(&'a str),
+    DeviceAuthorization(i64),
+}
+
+impl Target<'_> {
+    const fn target_type(&self) -> &'static str {
+        match self {
+            Self::User(_) => "user",
+            Self::Token(_) => "token",
+            Self::Client(_) => "client",
+            Self::DeviceAuthorization(_) => "device_authorization",
+        }
+    }
+
+    fn target_id(&self) -> String {
+        match self {
+            Self::User(id)
+            | Self::Token(id)
+            | Self::DeviceAuthorization(id) => id.to_string(),
+            Self::Client(client_id) => (*client_id).to_owned(),
+        }
+    }
+}
+
+pub struct Event<'a> {
+    pub event_type: EventType,
+    pub actor: Actor<'a>,
+    pub target: Target<'a>,
+    /// Anything else worth knowing about the event, as a JSON object
+    pub details: Value,
+}
+
+/// Adds an event to the audit log.
+///
+/// Record events on the connection the change was made on, so they're
+/// committed or rolled back along with it.
+///
+/// # Errors
+///
+/// If locking the hash chain or inserting the event fails
+pub fn record_event(
+    connection: &DalConnection,
+    event: &Event<'_>,
+    client_info: &ClientInfo<'_>,
+) -> Result<AuditEvent, CreateAuditEventError> {
+    let actor_id = event.actor.actor_id();
+    let target_id = event.target.target_id();
+    let mut new_event = NewAuditEvent {
+        event_type: event.event_type.code(),
+        actor_type: event.actor.actor_type(),
+        actor_id: actor_id.as_deref(),
+        target_type: event.target.target_type(),
+        target_id: &target_id,
+        ip_address: &client_info.ip_address,
+        user_agent: client_info.user_agent,
+        details: &event.details,
+        date_created: Utc::now(),
+        previous_hash: None,
+        row_hash: None,
+    };
+    let link = match hash_chain::next_link(
+        connection,
+        Chain::AuditEvents,
+        &new_event,
+    ) {
+        Ok(link) => link,
+        Err(LinkError::OtherDbError(db_error)) => {
+            return Err(CreateAuditEventError::OtherDbError(db_error));
+        }
+    };
+    new_event.previous_hash = link.previous_hash.as_deref();
+    new_event.row_hash = Some(&link.row_hash);
+    dal::audit::create_audit_event(connection, &new_event)
+}
+
+/// Gets a page of audit events matching a filter, most recent first
+///
+/// # Errors
+///
+/// `InvalidCursor` if the cursor isn't one a previous page returned, or if the
+/// query fails
+pub fn search_audit_events(
+    connection: &DalConnection,
+    filter: &AuditEventFilter<'_>,
+    cursor: Option<&str>,
+    limit: i64,
+) -> Result<Page<AuditEvent>, SearchPageError> {
+    pagination::fetch_page(
+        cursor,
+        limit,
+        |event: &AuditEvent| event.id,
+        |before_id, limit| {
+            match dal::audit::search_audit_events(
+                connection, filter, before_id, limit,
+            ) {
+                Ok(events) => Ok(events),
+                Err(GetAuditEventError::OtherDbError(db_error)) => {
+                    Err(db_error)
+                }
+            }
+        },
+    )
+}
@@ -0,0 +1,250 @@
+extern crate percent_encoding;
+
+use self::percent_encoding::percent_decode_str;
+use base64;
+use config;
+use dal::{self, auth::GetAuthTokenError, roles::GetRolesError, DalConnection};
+use diesel;
+use handlers::user::{verify_token, Principal, VerifyTokenError};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+pub struct Identity {
+    pub principal: Principal,
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum CheckAccessError {
+    Unauthenticated(VerifyTokenError),
+    Forbidden,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Normalises the path of a URI the way the upstream server will see it, so
+/// rules can't be bypassed with `//admin`, `/./admin` or `/%61dmin`. The
+/// query and fragment are dropped, percent-encoding is decoded, repeated
+/// slashes are collapsed and `.` and `..` segments are resolved.
+fn normalize_path(uri: &str) -> String {
+    let path = uri.split(['?', '#']).next().unwrap_or_default();
+    let path = percent_decode_str(path).decode_utf8_lossy();
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+/// Whether a normalised path is under a rule's prefix. Only path segment
+/// boundaries match, so "/admin" doesn't match "/administrator".
+fn prefix_matches(prefix: &str, path: &str) -> bool {
+    path.strip_prefix(prefix.trim_end_matches('/'))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The roles the longest rule matching `uri` requires
+fn matching_rule<'a>(
+    rules: &'a BTreeMap<String, Vec<String>>,
+    uri: &str,
+) -> Option<&'a [String]> {
+    let path = normalize_path(uri);
+    rules
+        .iter()
+        .filter(|(prefix, _)| prefix_matches(prefix, &path))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, roles)| roles.as_slice())
+}
+
+/// Looks up the roles required for `uri` in `auth_check.rules`. Holding any
+/// one of the listed roles grants access, and the longest matching prefix
+/// wins.
+#[must_use]
+pub fn required_roles(uri: &str) -> Option<&'static [String]> {
+    matching_rule(&config::get().auth_check.rules, uri)
+}
+
+/// The roles a token carries. Scoped tokens, such as personal access tokens
+/// and OAuth access tokens, only carry the user's roles that were also
+/// granted as scopes, so an admin's `read` token can't be used as an admin.
+fn granted_roles(
+    roles: Vec<String>,
+    scopes: Option<&[String]>,
+) -> Vec<String> {
+    match scopes {
+        None => roles,
+        Some(scopes) => {
+            roles.into_iter().filter(|role| scopes.contains(role)).collect()
+        }
+    }
+}
+
+/// Verifies a token and looks up the roles its holder has
+///
+/// # Errors
+///
+/// `Unauthenticated` if the token isn't valid, or if a query fails
+pub fn identify(
+    connection: &DalConnection,
+    token_string: &str,
+) -> Result<Identity, CheckAccessError> {
+    let principal = match verify_token(connection, token_string) {
+        Ok(principal) => principal,
+        Err(VerifyTokenError::GetAuthTokenError(
+            GetAuthTokenError::OtherDbError(db_error),
+        )) => {
+            return Err(CheckAccessError::OtherDbError(db_error));
+        }
+        Err(error) => return Err(CheckAccessError::Unauthenticated(error)),
+    };
+
+    // Roles are only granted to users, never service clients
+    let roles = match &principal {
+        Principal::User {
+            user_id, scopes, ..
+        } => match dal::roles::get_roles_for_user(connection, *user_id) {
+            Ok(roles) => granted_roles(roles, scopes.as_deref()),
+            Err(GetRolesError::OtherDbError(db_error)) => {
+                return Err(CheckAccessError::OtherDbError(db_error));
+            }
+        },
+        Principal::Service { .. } => Vec::new(),
+    };
+
+    Ok(Identity { principal, roles })
+}
+
+/// Identifies the token's holder and checks they have the roles the auth check
+/// rules require for `uri`
+///
+/// # Errors
+///
+/// `Unauthenticated` if the token isn't valid, `Forbidden` if a required role
+/// is missing or there are rules but no URI, or if a query fails
+pub fn check_access(
+    connection: &DalConnection,
+    token_string: &str,
+    uri: Option<&str>,
+) -> Result<Identity, CheckAccessError> {
+    let identity = identify(connection, token_string)?;
+
+    let required = match uri {
+        Some(uri) => required_roles(uri),
+        // Without the original URI there's no telling which rules apply
+        None if !config::get().auth_check.rules.is_empty() => {
+            return Err(CheckAccessError::Forbidden);
+        }
+        None => None,
+    };
+    if let Some(required) = required {
+        if !required.iter().any(|role| identity.roles.contains(role)) {
+            return Err(CheckAccessError::Forbidden);
+        }
+    }
+
+    Ok(identity)
+}
+
+/// Identifies the token's holder and checks they have `role`
+///
+/// # Errors
+///
+/// `Unauthenticated` if the token isn't valid, `Forbidden` if the role is
+/// missing, or if a query fails
+pub fn require_role(
+    connection: &DalConnection,
+    token_string: &str,
+    role: &str,
+) -> Result<Identity, CheckAccessError> {
+    let identity = identify(connection, token_string)?;
+
+    if identity.roles.iter().any(|held| held == role) {
+        Ok(identity)
+    } else {
+        Err(CheckAccessError::Forbidden)
+    }
+}
+
+/// Derives the CSRF token for a session from its token. Only someone who can
+/// read the session cookie can work it out, and it changes with every
+/// session.
+#[must_use]
+pub fn csrf_token(session_token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(b"login_api csrf\0");
+    hasher.input(session_token.as_bytes());
+    base64::encode_config(&hasher.result(), base64::URL_SAFE_NO_PAD)
+}
+
+#[must_use]
+pub fn verify_csrf_token(session_token: &str, provided: &str) -> bool {
+    let expected = csrf_token(session_token);
+    // Compare in constant time so the token can't be guessed byte by byte
+    expected.len() == provided.len()
+        && expected
+            .bytes()
+            .zip(provided.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scoped_tokens_only_carry_granted_roles() {
+        let roles = || vec!["admin".to_owned(), "auditor".to_owned()];
+        let scopes = |scopes: &[&str]| -> Vec<String> {
+            scopes.iter().map(|scope| (*scope).to_owned()).collect()
+        };
+        assert_eq!(granted_roles(roles(), None), roles());
+        assert!(granted_roles(roles(), Some(&scopes(&["read"]))).is_empty());
+        assert!(granted_roles(roles(), Some(&[])).is_empty());
+        assert_eq!(
+            granted_roles(roles(), Some(&scopes(&["read", "auditor"]))),
+            ["auditor"]
+        );
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        for (uri, path) in [
+            ("/admin", "/admin"),
+            ("//admin", "/admin"),
+            ("/./admin", "/admin"),
+            ("/%61dmin", "/admin"),
+            ("/public/../admin/", "/admin"),
+            ("/../../admin", "/admin"),
+            ("/admin%2Fusers?x=1", "/admin/users"),
+            ("/public?next=/admin", "/public"),
+            ("", "/"),
+        ] {
+            assert_eq!(normalize_path(uri), path, "{uri}");
+        }
+    }
+
+    #[test]
+    fn matches_rules_on_segment_boundaries() {
+        let rules: BTreeMap<String, Vec<String>> = vec![
+            ("/admin".to_owned(), vec!["admin".to_owned()]),
+            ("/admin/reports/".to_owned(), vec!["auditor".to_owned()]),
+        ]
+        .into_iter()
+        .collect();
+        let rule = |uri| matching_rule(&rules, uri).map(|roles| &roles[0][..]);
+        assert_eq!(rule("/admin"), Some("admin"));
+        assert_eq!(rule("//admin/users"), Some("admin"));
+        assert_eq!(rule("/%61dmin"), Some("admin"));
+        assert_eq!(rule("/x/../admin"), Some("admin"));
+        assert_eq!(rule("/administrator"), None);
+        assert_eq!(rule("/admin/reports"), Some("auditor"));
+        assert_eq!(rule("/admin/./reports/daily"), Some("auditor"));
+        assert_eq!(rule("/public"), None);
+    }
+}
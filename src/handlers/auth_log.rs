@@ -0,0 +1,89 @@
+use chrono::{DateTime, Duration, Utc};
+use dal::{
+    self,
+    auth::{
+        AuthLog,
+        AuthLogFilter,
+        GetAuthLogError,
+        IpFailureCount,
+        TargetedAccount,
+    },
+    DalConnection,
+};
+use handlers::pagination::{self, Page, SearchPageError};
+
+/// How far back reports look when no start date is given
+const DEFAULT_REPORT_HOURS: i64 = 24;
+
+/// Gets a page of auth log entries matching a filter, most recent first
+///
+/// # Errors
+///
+/// `InvalidCursor` for a malformed cursor, or if the query fails
+pub fn search_auth_log(
+    connection: &DalConnection,
+    filter: &AuthLogFilter<'_>,
+    cursor: Option<&str>,
+    limit: i64,
+) -> Result<Page<AuthLog>, SearchPageError> {
+    pagination::fetch_page(
+        cursor,
+        limit,
+        |entry: &AuthLog| entry.id,
+        |before_id, limit| {
+            match dal::auth::search_auth_logs(
+                connection, filter, before_id, limit,
+            ) {
+                Ok(entries) => Ok(entries),
+                Err(GetAuthLogError::OtherDbError(db_error)) => Err(db_error),
+            }
+        },
+    )
+}
+
+/// Fills in the default date range for reports, ending now
+pub fn report_range(
+    date_from: Option<DateTime<Utc>>,
+    date_to: Option<DateTime<Utc>>,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    let date_to = date_to.unwrap_or_else(Utc::now);
+    let date_from = date_from
+        .unwrap_or_else(|| date_to - Duration::hours(DEFAULT_REPORT_HOURS));
+    (date_from, date_to)
+}
+
+/// Failed logins per IP address per hour, to spot brute force and password
+/// spraying attacks
+///
+/// # Errors
+///
+/// If the query fails
+pub fn failures_by_ip(
+    connection: &DalConnection,
+    date_from: DateTime<Utc>,
+    date_to: DateTime<Utc>,
+    min_failures: i64,
+    limit: i64,
+) -> Result<Vec<IpFailureCount>, GetAuthLogError> {
+    dal::auth::count_failures_by_ip_hour(
+        connection,
+        date_from,
+        date_to,
+        min_failures,
+        limit,
+    )
+}
+
+/// The accounts with the most failed logins, to spot credential stuffing
+///
+/// # Errors
+///
+/// If the query fails
+pub fn targeted_accounts(
+    connection: &DalConnection,
+    date_from: DateTime<Utc>,
+    date_to: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<TargetedAccount>, GetAuthLogError> {
+    dal::auth::get_targeted_accounts(connection, date_from, date_to, limit)
+}
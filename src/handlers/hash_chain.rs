@@ -0,0 +1,370 @@
+use chrono::{DateTime, Utc};
+use config;
+use dal::{
+    self,
+    audit::{AuditEvent, GetAuditEventError, NewAuditEvent},
+    auth::{AuthLog, GetAuthLogError, NewAuthLog},
+    DalConnection,
+    LockError,
+};
+use diesel;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{convert::TryFrom, fmt::Write};
+
+/// How many rows are loaded at a time while verifying a chain
+const VERIFY_BATCH_SIZE: i64 = 1000;
+
+/// A table whose rows are hash chained
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chain {
+    AuthLog,
+    AuditEvents,
+}
+
+impl Chain {
+    pub const ALL: [Self; 2] = [Self::AuthLog, Self::AuditEvents];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::AuthLog => "auth_log",
+            Self::AuditEvents => "audit_events",
+        }
+    }
+
+    /// Advisory lock serializing writes to the chain
+    const fn lock_key(self) -> i64 {
+        match self {
+            Self::AuthLog => 0x6c61_6368_0001,
+            Self::AuditEvents => 0x6c61_6368_0002,
+        }
+    }
+}
+
+/// A record whose content is covered by its row hash
+pub trait ChainContent {
+    /// The record's fields in the order they're hashed. IDs aren't included,
+    /// as rows are linked by their hashes instead.
+    fn content(&self) -> Vec<Option<String>>;
+}
+
+/// Timestamps are hashed as whole microseconds, as that's all the database
+/// stores
+fn timestamp(date: DateTime<Utc>) -> String {
+    (date.timestamp() * 1_000_000 + i64::from(date.timestamp_subsec_micros()))
+        .to_string()
+}
+
+impl ChainContent for NewAuthLog<'_> {
+    fn content(&self) -> Vec<Option<String>> {
+        vec![
+            Some(self.email.to_owned()),
+            Some(self.success.to_string()),
+            Some(self.ip_address.to_owned()),
+            Some(self.user_agent.to_owned()),
+            Some(timestamp(self.date_created)),
+            Some(self.flagged.to_string()),
+            self.flag_reasons.map(str::to_owned),
+        ]
+    }
+}
+
+impl ChainContent for AuthLog {
+    fn content(&self) -> Vec<Option<String>> {
+        vec![
+            Some(self.email.clone()),
+            Some(self.success.to_string()),
+            Some(self.ip_address.clone()),
+            Some(self.user_agent.clone()),
+            Some(timestamp(self.date_created)),
+            Some(self.flagged.to_string()),
+            self.flag_reasons.clone(),
+        ]
+    }
+}
+
+// Details are hashed as serialized by serde_json, which sorts object keys, so
+// they hash the same however the database orders them
+impl ChainContent for NewAuditEvent<'_> {
+    fn content(&self) -> Vec<Option<String>> {
+        vec![
+            Some(self.event_type.to_owned()),
+            Some(self.actor_type.to_owned()),
+            self.actor_id.map(str::to_owned),
+            Some(self.target_type.to_owned()),
+            Some(self.target_id.to_owned()),
+            Some(self.ip_address.to_owned()),
+            Some(self.user_agent.to_owned()),
+            Some(self.details.to_string()),
+            Some(timestamp(self.date_created)),
+        ]
+    }
+}
+
+impl ChainContent for AuditEvent {
+    fn content(&self) -> Vec<Option<String>> {
+        vec![
+            Some(self.event_type.clone()),
+            Some(self.actor_type.clone()),
+            self.actor_id.clone(),
+            Some(self.target_type.clone()),
+            Some(self.target_id.clone()),
+            Some(self.ip_address.clone()),
+            Some(self.user_agent.clone()),
+            Some(self.details.to_string()),
+            Some(timestamp(self.date_created)),
+        ]
+    }
+}
+
+/// HMACs the previous row's hash and the record's content. Every value is
+/// length prefixed so different contents can't run together the same way.
+fn row_hash(
+    previous_hash: Option<&[u8]>,
+    record: &impl ChainContent,
+) -> Vec<u8> {
+    // Keyed so the hashes can't be recomputed by someone who can only write
+    // to the database
+    let key = &config::resources().hash_chain_key;
+    let mut mac = Hmac::<Sha256>::new_varkey(key.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    let mut input = |value: Option<&[u8]>| match value {
+        None => mac.input(&[0]),
+        Some(value) => {
+            mac.input(&[1]);
+            mac.input(&(value.len() as u64).to_be_bytes());
+            mac.input(value);
+        }
+    };
+    input(previous_hash);
+    for value in record.content() {
+        input(value.as_deref().map(str::as_bytes));
+    }
+    mac.result().code().to_vec()
+}
+
+#[must_use]
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// The hashes to store with a new row
+pub struct Link {
+    pub previous_hash: Option<Vec<u8>>,
+    pub row_hash: Vec<u8>,
+}
+
+pub enum LinkError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Works out the hashes for a new row at the end of a chain.
+///
+/// The chain stays locked until the transaction ends, so the row must be
+/// inserted on the same transaction. Every other write to the chain waits for
+/// that, so this should be left until just before the transaction commits,
+/// after any slow work such as password hashing or sending emails.
+///
+/// # Errors
+///
+/// If the chain can't be locked or its last hash can't be read
+pub fn next_link(
+    connection: &DalConnection,
+    chain: Chain,
+    record: &impl ChainContent,
+) -> Result<Link, LinkError> {
+    match dal::lock_for_transaction(connection, chain.lock_key()) {
+        Ok(()) => (),
+        Err(LockError::OtherDbError(db_error)) => {
+            return Err(LinkError::OtherDbError(db_error));
+        }
+    }
+    let previous_hash = match chain {
+        Chain::AuthLog => match dal::auth::get_last_auth_log_hash(connection)
+        {
+            Ok(hash) => hash,
+            Err(GetAuthLogError::OtherDbError(db_error)) => {
+                return Err(LinkError::OtherDbError(db_error));
+            }
+        },
+        Chain::AuditEvents => {
+            match dal::audit::get_last_audit_event_hash(connection) {
+                Ok(hash) => hash,
+                Err(GetAuditEventError::OtherDbError(db_error)) => {
+                    return Err(LinkError::OtherDbError(db_error));
+                }
+            }
+        }
+    };
+
+    let row_hash = row_hash(previous_hash.as_deref(), record);
+    Ok(Link {
+        previous_hash,
+        row_hash,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrokenLinkReason {
+    /// A row after the start of the chain has no hash
+    MissingHash,
+    /// The row's previous hash isn't the hash of the row before it, so rows
+    /// were removed, inserted or reordered
+    PreviousHashMismatch,
+    /// The row's content doesn't match its hash, so it was edited
+    ContentMismatch,
+}
+
+impl BrokenLinkReason {
+    #[must_use]
+    pub const fn code(self) -> &'static str {
+        match self {
+            Self::MissingHash => "missing_hash",
+            Self::PreviousHashMismatch => "previous_hash_mismatch",
+            Self::ContentMismatch => "content_mismatch",
+        }
+    }
+}
+
+pub struct BrokenLink {
+    pub id: i64,
+    pub reason: BrokenLinkReason,
+}
+
+pub struct ChainReport {
+    pub chain: Chain,
+    /// Rows from before chaining was introduced, which can't be verified
+    pub unchained_rows: i64,
+    pub verified_rows: i64,
+    /// The oldest chained row. Its previous hash is trusted, as older rows
+    /// may have been removed by retention.
+    pub first_id: Option<i64>,
+    /// The newest row verified, and its hash. Keeping these somewhere else
+    /// means rows removed from the end of the chain can be noticed later.
+    pub last_id: Option<i64>,
+    pub last_hash: Option<Vec<u8>>,
+    /// Where verification stopped, if the chain is broken
+    pub broken_link: Option<BrokenLink>,
+}
+
+impl ChainReport {
+    /// Checks the next row, returning false if it breaks the chain
+    fn check(
+        &mut self,
+        id: i64,
+        previous_hash: Option<&[u8]>,
+        stored_hash: Option<&[u8]>,
+        record: &impl ChainContent,
+    ) -> bool {
+        let reason = match stored_hash {
+            None if self.first_id.is_none() => {
+                self.unchained_rows += 1;
+                return true;
+            }
+            None => Some(BrokenLinkReason::MissingHash),
+            Some(_)
+                if self.first_id.is_some()
+                    && previous_hash != self.last_hash.as_deref() =>
+            {
+                Some(BrokenLinkReason::PreviousHashMismatch)
+            }
+            Some(stored_hash)
+                if row_hash(previous_hash, record) != stored_hash =>
+            {
+                Some(BrokenLinkReason::ContentMismatch)
+            }
+            Some(_) => None,
+        };
+        if let Some(reason) = reason {
+            self.broken_link = Some(BrokenLink { id, reason });
+            return false;
+        }
+
+        self.first_id = self.first_id.or(Some(id));
+        self.verified_rows += 1;
+        self.last_id = Some(id);
+        self.last_hash = stored_hash.map(<[u8]>::to_vec);
+        true
+    }
+}
+
+pub enum VerifyChainError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Walks a chain from its oldest row, stopping at the first broken link
+///
+/// # Errors
+///
+/// If reading the rows fails. Broken links are reported rather than returned as
+/// errors.
+pub fn verify_chain(
+    connection: &DalConnection,
+    chain: Chain,
+) -> Result<ChainReport, VerifyChainError> {
+    let mut report = ChainReport {
+        chain,
+        unchained_rows: 0,
+        verified_rows: 0,
+        first_id: None,
+        last_id: None,
+        last_hash: None,
+        broken_link: None,
+    };
+    let mut after_id = 0;
+    loop {
+        let (batch_size, last_id, intact) = match chain {
+            Chain::AuthLog => {
+                let logs = match dal::auth::get_auth_logs_after(
+                    connection,
+                    after_id,
+                    VERIFY_BATCH_SIZE,
+                ) {
+                    Ok(logs) => logs,
+                    Err(GetAuthLogError::OtherDbError(db_error)) => {
+                        return Err(VerifyChainError::OtherDbError(db_error));
+                    }
+                };
+                let intact = logs.iter().all(|log| {
+                    report.check(
+                        log.id,
+                        log.previous_hash.as_deref(),
+                        log.row_hash.as_deref(),
+                        log,
+                    )
+                });
+                (logs.len(), logs.last().map(|log| log.id), intact)
+            }
+            Chain::AuditEvents => {
+                let events = match dal::audit::get_audit_events_after(
+                    connection,
+                    after_id,
+                    VERIFY_BATCH_SIZE,
+                ) {
+                    Ok(events) => events,
+                    Err(GetAuditEventError::OtherDbError(db_error)) => {
+                        return Err(VerifyChainError::OtherDbError(db_error));
+                    }
+                };
+                let intact = events.iter().all(|event| {
+                    report.check(
+                        event.id,
+                        event.previous_hash.as_deref(),
+                        event.row_hash.as_deref(),
+                        event,
+                    )
+                });
+                (events.len(), events.last().map(|event| event.id), intact)
+            }
+        };
+        let full_batch = usize::try_from(VERIFY_BATCH_SIZE) == Ok(batch_size);
+        match last_id {
+            Some(last_id) if intact && full_batch => after_id = last_id,
+            _ => return Ok(report),
+        }
+    }
+}
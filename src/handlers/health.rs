@@ -0,0 +1,56 @@
+use config;
+use dal::{self, DalConnection, DalPool};
+use handlers::oidc;
+use std::time::Duration;
+
+/// How long a readiness check waits for a database connection
+const READY_TIMEOUT_SECS: u64 = 2;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const COMMIT: &str = env!("GIT_COMMIT");
+pub const PROFILE: &str = env!("BUILD_PROFILE");
+/// The newest migration this build expects the database to have
+pub const LATEST_MIGRATION: &str = env!("LATEST_MIGRATION");
+
+pub struct Readiness {
+    pub database: bool,
+    pub migrations: bool,
+    pub signing_keys: bool,
+}
+
+impl Readiness {
+    #[must_use]
+    pub const fn is_ready(&self) -> bool {
+        self.database && self.migrations && self.signing_keys
+    }
+}
+
+/// The OIDC signing key is only needed if one is configured
+fn signing_keys_loaded() -> bool {
+    !config::resources().jwt_secret.is_empty()
+        && (config::get().oidc.signing_key.is_none()
+            || oidc::signing_key().is_some())
+}
+
+/// Checks whether this instance can serve requests. The pool tests
+/// connections as they're checked out, so getting one means the database is
+/// reachable.
+pub fn check_readiness(pool: &DalPool) -> Readiness {
+    let connection = pool
+        .get_timeout(Duration::from_secs(READY_TIMEOUT_SECS))
+        .ok()
+        .map(DalConnection::new);
+    // The migrations table is missing if diesel has never run
+    let migrations = connection.as_ref().is_some_and(|connection| {
+        matches!(
+            dal::is_migration_applied(connection, LATEST_MIGRATION),
+            Ok(true)
+        )
+    });
+
+    Readiness {
+        database: connection.is_some(),
+        migrations,
+        signing_keys: signing_keys_loaded(),
+    }
+}
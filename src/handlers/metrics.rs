@@ -0,0 +1,290 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{LazyLock, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Prometheus' default buckets, in seconds
+const REQUEST_BUCKETS: &[f64] =
+    &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const PASSWORD_HASH_BUCKETS: &[f64] =
+    &[0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+const CONNECTION_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0,
+    30.0,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Counter {
+    HttpRequests,
+    Logins,
+    TokenValidations,
+    Signups,
+}
+
+impl Counter {
+    const ALL: [Self; 4] = [
+        Self::HttpRequests,
+        Self::Logins,
+        Self::TokenValidations,
+        Self::Signups,
+    ];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::HttpRequests => "http_requests_total",
+            Self::Logins => "logins_total",
+            Self::TokenValidations => "token_validations_total",
+            Self::Signups => "signups_total",
+        }
+    }
+
+    const fn help(self) -> &'static str {
+        match self {
+            Self::HttpRequests => "HTTP requests handled",
+            Self::Logins => "Password logins for a token, by result",
+            Self::TokenValidations => "Tokens checked, by outcome",
+            Self::Signups => "Users created",
+        }
+    }
+
+    const fn labels(self) -> &'static [&'static str] {
+        match self {
+            Self::HttpRequests => &["method", "route", "status"],
+            Self::Logins => &["result"],
+            Self::TokenValidations => &["outcome"],
+            Self::Signups => &[],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Histogram {
+    HttpRequestDuration,
+    PasswordHashDuration,
+    DbConnectionWait,
+}
+
+impl Histogram {
+    const ALL: [Self; 3] = [
+        Self::HttpRequestDuration,
+        Self::PasswordHashDuration,
+        Self::DbConnectionWait,
+    ];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::HttpRequestDuration => "http_request_duration_seconds",
+            Self::PasswordHashDuration => "password_hash_duration_seconds",
+            Self::DbConnectionWait => "db_connection_acquire_seconds",
+        }
+    }
+
+    const fn help(self) -> &'static str {
+        match self {
+            Self::HttpRequestDuration => "Time taken to handle HTTP requests",
+            Self::PasswordHashDuration => {
+                "Time taken to hash or verify a password"
+            }
+            Self::DbConnectionWait => {
+                "Time taken to get a database connection from the pool"
+            }
+        }
+    }
+
+    const fn labels(self) -> &'static [&'static str] {
+        match self {
+            Self::HttpRequestDuration => &["method", "route", "status"],
+            Self::PasswordHashDuration => &["algorithm", "operation"],
+            Self::DbConnectionWait => &[],
+        }
+    }
+
+    const fn buckets(self) -> &'static [f64] {
+        match self {
+            Self::HttpRequestDuration => REQUEST_BUCKETS,
+            Self::PasswordHashDuration => PASSWORD_HASH_BUCKETS,
+            Self::DbConnectionWait => CONNECTION_BUCKETS,
+        }
+    }
+}
+
+struct Observations {
+    /// How many observations fell in each bucket, not counting lower buckets
+    bucket_counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Observations {
+    fn new(buckets: &[f64]) -> Self {
+        Self {
+            bucket_counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, buckets: &[f64], seconds: f64) {
+        if let Some(bucket) = buckets.iter().position(|&bound| seconds <= bound)
+        {
+            self.bucket_counts[bucket] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<(Counter, Vec<String>), u64>,
+    histograms: BTreeMap<(Histogram, Vec<String>), Observations>,
+}
+
+static REGISTRY: LazyLock<Mutex<Registry>> =
+    LazyLock::new(|| Mutex::new(Registry::default()));
+
+/// A panic while recording can't leave the registry inconsistent enough to
+/// matter, so keep going rather than failing every later request
+fn registry() -> std::sync::MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Adds one to a counter. `labels` are values for the counter's labels, in
+/// order.
+pub fn increment(counter: Counter, labels: &[&str]) {
+    debug_assert_eq!(labels.len(), counter.labels().len());
+    let key = (counter, labels.iter().map(|&label| label.to_owned()).collect());
+    *registry().counters.entry(key).or_insert(0) += 1;
+}
+
+pub fn observe(histogram: Histogram, labels: &[&str], duration: Duration) {
+    debug_assert_eq!(labels.len(), histogram.labels().len());
+    let seconds = duration.as_secs_f64();
+    let buckets = histogram.buckets();
+    let key =
+        (histogram, labels.iter().map(|&label| label.to_owned()).collect());
+    registry()
+        .histograms
+        .entry(key)
+        .or_insert_with(|| Observations::new(buckets))
+        .add(buckets, seconds);
+}
+
+/// Runs `f`, observing how long it took
+pub fn time<T>(
+    histogram: Histogram,
+    labels: &[&str],
+    f: impl FnOnce() -> T,
+) -> T {
+    let started = Instant::now();
+    let result = f();
+    observe(histogram, labels, started.elapsed());
+    result
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+/// Formats label names and values as `{name="value",...}`, with `extra`
+/// label appended
+fn format_labels(
+    names: &[&str],
+    values: &[String],
+    extra: Option<(&str, &str)>,
+) -> String {
+    let labels = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| (*name, value.as_str()))
+        .chain(extra)
+        .map(|(name, value)| format!("{name}=\"{}\"", escape_label(value)))
+        .collect::<Vec<_>>();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+/// Renders every metric in the Prometheus text format. Metrics without
+/// labels are reported as zero until they're first recorded.
+#[must_use]
+pub fn render() -> String {
+    let registry = registry();
+    let mut output = String::new();
+
+    for counter in Counter::ALL {
+        let name = counter.name();
+        let _ = writeln!(output, "# HELP {name} {}", counter.help());
+        let _ = writeln!(output, "# TYPE {name} counter");
+        let series = registry
+            .counters
+            .iter()
+            .filter(|((series_counter, _), _)| *series_counter == counter)
+            .collect::<Vec<_>>();
+        if series.is_empty() && counter.labels().is_empty() {
+            let _ = writeln!(output, "{name} 0");
+        }
+        for ((_, values), count) in series {
+            let labels = format_labels(counter.labels(), values, None);
+            let _ = writeln!(output, "{name}{labels} {count}");
+        }
+    }
+
+    for histogram in Histogram::ALL {
+        let name = histogram.name();
+        let _ = writeln!(output, "# HELP {name} {}", histogram.help());
+        let _ = writeln!(output, "# TYPE {name} histogram");
+        let empty = Observations::new(histogram.buckets());
+        let mut series = registry
+            .histograms
+            .iter()
+            .filter(|((series_histogram, _), _)| {
+                *series_histogram == histogram
+            })
+            .map(|((_, values), observations)| (values.clone(), observations))
+            .collect::<Vec<_>>();
+        if series.is_empty() && histogram.labels().is_empty() {
+            series.push((Vec::new(), &empty));
+        }
+        for (values, observations) in series {
+            let mut cumulative = 0;
+            for (bound, count) in
+                histogram.buckets().iter().zip(&observations.bucket_counts)
+            {
+                cumulative += count;
+                let labels = format_labels(
+                    histogram.labels(),
+                    &values,
+                    Some(("le", &bound.to_string())),
+                );
+                let _ = writeln!(output, "{name}_bucket{labels} {cumulative}");
+            }
+            let labels = format_labels(
+                histogram.labels(),
+                &values,
+                Some(("le", "+Inf")),
+            );
+            let _ = writeln!(
+                output,
+                "{name}_bucket{labels} {}",
+                observations.count
+            );
+            let labels = format_labels(histogram.labels(), &values, None);
+            let _ = writeln!(output, "{name}_sum{labels} {}", observations.sum);
+            let _ = writeln!(
+                output,
+                "{name}_count{labels} {}",
+                observations.count
+            );
+        }
+    }
+    drop(registry);
+    output
+}
@@ -1 +1,15 @@
+pub mod audit;
+pub mod auth;
+pub mod auth_log;
+pub mod hash_chain;
+pub mod health;
+pub mod metrics;
+pub mod notify;
+pub mod oauth;
+pub mod oidc;
+pub mod pagination;
+pub mod password;
+pub mod personal_token;
+pub mod retention;
+pub mod suspicious_login;
 pub mod user;
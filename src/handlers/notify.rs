@@ -0,0 +1,275 @@
+extern crate lettre;
+
+use self::lettre::{
+    message::Mailbox,
+    Message,
+    SendmailTransport,
+    SmtpTransport,
+    Transport,
+};
+use chrono::{DateTime, Utc};
+use config;
+use std::{cell::RefCell, fmt::Display, sync::LazyLock};
+
+pub struct Email<'a> {
+    pub to: &'a str,
+    pub subject: &'a str,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    InvalidAddress(String),
+    SendError(String),
+}
+
+/// A successful login that didn't look like the user's usual ones
+pub struct SuspiciousLogin<'a> {
+    pub email: &'a str,
+    pub ip_address: &'a str,
+    pub user_agent: &'a str,
+    pub date: DateTime<Utc>,
+    /// Why the login looked suspicious
+    pub reasons: Vec<&'static str>,
+}
+
+/// Delivers notifications to users, such as security alerts
+pub trait Notifier: Send + Sync {
+    /// Sends an email from the configured address
+    ///
+    /// # Errors
+    ///
+    /// `InvalidAddress` if either address can't be parsed, or `SendError` if
+    /// the email couldn't be delivered
+    fn send_email(&self, email: &Email<'_>) -> Result<(), NotifyError>;
+
+    /// Warns a user about a suspicious login to their account. Notifiers can
+    /// override this to alert them some other way.
+    ///
+    /// # Errors
+    ///
+    /// If the notification couldn't be sent
+    fn notify_suspicious_login(
+        &self,
+        login: &SuspiciousLogin<'_>,
+    ) -> Result<(), NotifyError> {
+        let reasons = login
+            .reasons
+            .iter()
+            .map(|reason| format!("- {reason}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.send_email(&Email {
+            to: login.email,
+            subject: "New sign-in to your account",
+            body: format!(
+                "Someone signed in to your account at {} from {} using \
+                 \"{}\".\n\n\
+                 This was unusual because:\n{}\n\n\
+                 If this was you, you can ignore this email. Otherwise, \
+                 change your password straight away.",
+                login.date.to_rfc2822(),
+                login.ip_address,
+                login.user_agent,
+                reasons,
+            ),
+        })
+    }
+}
+
+/// Logs emails instead of sending them, for development
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn send_email(&self, email: &Email<'_>) -> Result<(), NotifyError> {
+        info!(
+            "Email to {}\nSubject: {}\n\n{}",
+            email.to, email.subject, email.body
+        );
+        Ok(())
+    }
+}
+
+/// Sends emails through a lettre transport
+pub struct MailNotifier<T> {
+    transport: T,
+    from: Mailbox,
+}
+
+impl<T> Notifier for MailNotifier<T>
+where
+    T: Transport + Send + Sync,
+    T::Error: Display,
+{
+    fn send_email(&self, email: &Email<'_>) -> Result<(), NotifyError> {
+        let to = email
+            .to
+            .parse::<Mailbox>()
+            .map_err(|error| NotifyError::InvalidAddress(error.to_string()))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject)
+            .body(email.body.clone())
+            .map_err(|error| NotifyError::InvalidAddress(error.to_string()))?;
+        match self.transport.send(&message) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(NotifyError::SendError(error.to_string())),
+        }
+    }
+}
+
+fn from_address(config: &config::Notify) -> Mailbox {
+    config
+        .from
+        .as_ref()
+        .and_then(|from| from.parse().ok())
+        .expect("notify.from is checked when loading the configuration")
+}
+
+static NOTIFIER: LazyLock<Box<dyn Notifier>> = LazyLock::new(|| {
+    let config = &config::get().notify;
+    match config.notifier {
+        config::Notifier::Log => Box::new(LogNotifier),
+        // Without TLS support this is only suitable for a local relay
+        config::Notifier::Smtp => Box::new(MailNotifier {
+            transport: SmtpTransport::builder_dangerous(&config.smtp_host)
+                .port(config.smtp_port)
+                .build(),
+            from: from_address(config),
+        }),
+        config::Notifier::Sendmail => Box::new(MailNotifier {
+            transport: config.sendmail_command.as_ref().map_or_else(
+                SendmailTransport::new,
+                SendmailTransport::new_with_command,
+            ),
+            from: from_address(config),
+        }),
+    }
+});
+
+pub fn notifier() -> &'static dyn Notifier { NOTIFIER.as_ref() }
+
+/// An email waiting for the request's transaction to commit
+struct QueuedEmail {
+    to: String,
+    subject: String,
+    body: String,
+}
+
+thread_local! {
+    /// Emails queued by the request being handled on this thread
+    static OUTBOX: RefCell<Vec<QueuedEmail>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+/// Queues an email to send once the request's transaction commits, so nobody
+/// is emailed about changes that are rolled back, and the transaction isn't
+/// held open while it's sent
+pub fn queue_email(email: &Email<'_>) {
+    OUTBOX.with(|outbox| {
+        outbox.borrow_mut().push(QueuedEmail {
+            to: email.to.to_owned(),
+            subject: email.subject.to_owned(),
+            body: email.body.clone(),
+        });
+    });
+}
+
+/// Runs `transaction`, then sends the emails it queued if it committed.
+/// They're dropped if it fails or panics.
+///
+/// # Errors
+///
+/// If `transaction` fails. The changes are committed by the time emails are
+/// sent, so failures to send are only logged.
+pub fn send_after_commit<T, E>(
+    transaction: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    send_after_commit_with(notifier(), transaction)
+}
+
+fn send_after_commit_with<T, E>(
+    notifier: &dyn Notifier,
+    transaction: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    // Anything left by a request that panicked was never committed
+    OUTBOX.with(|outbox| outbox.borrow_mut().clear());
+    let result = transaction();
+    let queued = OUTBOX.with(RefCell::take);
+    if result.is_ok() {
+        for email in queued {
+            let sent = notifier.send_email(&Email {
+                to: &email.to,
+                subject: &email.subject,
+                body: email.body,
+            });
+            if let Err(error) = sent {
+                error!("Error sending email to {}: {error:?}", email.to);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{panic, sync::Mutex};
+
+    /// Keeps who each email was sent to
+    #[derive(Default)]
+    struct RecordingNotifier {
+        sent: Mutex<Vec<String>>,
+    }
+
+    impl Notifier for RecordingNotifier {
+        fn send_email(&self, email: &Email<'_>) -> Result<(), NotifyError> {
+            self.sent.lock().unwrap().push(email.to.to_owned());
+            Ok(())
+        }
+    }
+
+    fn queue(to: &str) {
+        queue_email(&Email {
+            to,
+            subject: "Test",
+            body: String::new(),
+        });
+    }
+
+    #[test]
+    fn sends_queued_emails_once_committed() {
+        let notifier = RecordingNotifier::default();
+        let result = send_after_commit_with(&notifier, || {
+            queue("first@example.com");
+            queue("second@example.com");
+            Ok::<_, ()>(())
+        });
+        assert!(result.is_ok());
+        assert_eq!(
+            *notifier.sent.lock().unwrap(),
+            ["first@example.com", "second@example.com"]
+        );
+    }
+
+    #[test]
+    fn drops_emails_from_rolled_back_transactions() {
+        let notifier = RecordingNotifier::default();
+        let result = send_after_commit_with(&notifier, || {
+            queue("failed@example.com");
+            Err::<(), _>(())
+        });
+        assert!(result.is_err());
+        let result = panic::catch_unwind(|| {
+            send_after_commit_with(&notifier, || -> Result<(), ()> {
+                queue("panicked@example.com");
+                panic!("The transaction should be rolled back");
+            })
+        });
+        assert!(result.is_err());
+        let result = send_after_commit_with(&notifier, || Ok::<_, ()>(()));
+        assert!(result.is_ok());
+        assert!(notifier.sent.lock().unwrap().is_empty());
+    }
+}
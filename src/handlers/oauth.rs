@@ -0,0 +1,810 @@
+use base64;
+use chrono::{prelude::*, Duration};
+use config;
+use dal::{
+    self,
+    auth::{
+        AuthToken,
+        CreateAuthTokenError,
+        CreateDeviceAuthorizationError,
+        DeviceAuthorization,
+        GetAuthTokenError,
+        GetDeviceAuthorizationError,
+        NewDeviceAuthorization,
+        RevokeAuthTokenError,
+        UpdateDeviceAuthorizationError,
+    },
+    oauth::{
+        CreateAuthorizationCodeError,
+        CreateOAuthClientError,
+        GetOAuthClientError,
+        NewAuthorizationCode,
+        NewOAuthClient,
+        OAuthClient,
+        RedeemAuthorizationCodeError,
+    },
+    users::{GetUserError, User},
+    DalConnection,
+};
+use diesel;
+use handlers::{
+    oidc,
+    personal_token,
+    user::{
+        issue_service_token,
+        issue_token,
+        token_lifetime,
+        verify_token_record,
+        VerifyTokenError,
+    },
+};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+/// Consonants only, so user codes can't spell words and avoid characters
+/// that are easily confused
+const USER_CODE_CHARACTERS: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+
+pub const AUTHORIZATION_CODE_GRANT: &str = "authorization_code";
+pub const CLIENT_CREDENTIALS_GRANT: &str = "client_credentials";
+pub const DEVICE_CODE_GRANT: &str =
+    "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Generates a random 256 bit value encoded as URL safe base64
+#[must_use]
+pub fn random_string() -> String {
+    base64::encode_config(
+        &rand::thread_rng().gen::<[u8; 32]>(),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+#[must_use]
+pub fn sha256(value: &str) -> Vec<u8> {
+    Sha256::digest(value.as_bytes()).to_vec()
+}
+
+pub enum RegisterClientError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Registers a new client, returning the plain text secret for confidential
+/// clients. The secret is only stored hashed so can't be retrieved again.
+///
+/// # Errors
+///
+/// If the insert fails
+pub fn register_client(
+    connection: &DalConnection,
+    name: &str,
+    redirect_uris: &[String],
+    grant_types: &[String],
+    allowed_scopes: &[String],
+    confidential: bool,
+) -> Result<(OAuthClient, Option<String>), RegisterClientError> {
+    let client_id = random_string();
+    let client_secret = if confidential {
+        Some(random_string())
+    } else {
+        None
+    };
+
+    let new_client = NewOAuthClient {
+        client_id: &client_id,
+        client_secret: client_secret.as_ref().map(|secret| sha256(secret)),
+        name,
+        redirect_uris,
+        grant_types,
+        allowed_scopes,
+    };
+    match dal::oauth::create_client(connection, &new_client) {
+        Ok(client) => Ok((client, client_secret)),
+        Err(CreateOAuthClientError::OtherDbError(db_error)) => {
+            Err(RegisterClientError::OtherDbError(db_error))
+        }
+    }
+}
+
+/// Gets a client by its public client ID
+///
+/// # Errors
+///
+/// `ClientNotFound` if there's no such client, or if the query fails
+pub fn get_client(
+    connection: &DalConnection,
+    client_id: &str,
+) -> Result<OAuthClient, GetOAuthClientError> {
+    dal::oauth::get_client_by_client_id(connection, client_id)
+}
+
+pub enum AuthenticateClientError {
+    InvalidClient,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Confidential clients must present their secret, public clients must not
+///
+/// # Errors
+///
+/// `InvalidClient` if the client doesn't exist or the secret is wrong, missing
+/// or unexpected, or if the query fails
+pub fn authenticate_client(
+    connection: &DalConnection,
+    client_id: &str,
+    client_secret: Option<&str>,
+) -> Result<OAuthClient, AuthenticateClientError> {
+    let client = match get_client(connection, client_id) {
+        Ok(client) => client,
+        Err(GetOAuthClientError::ClientNotFound) => {
+            return Err(AuthenticateClientError::InvalidClient);
+        }
+        Err(GetOAuthClientError::OtherDbError(db_error)) => {
+            return Err(AuthenticateClientError::OtherDbError(db_error));
+        }
+    };
+
+    let secret_valid = match (&client.client_secret, client_secret) {
+        (Some(stored), Some(provided)) => *stored == sha256(provided),
+        (None, None) => true,
+        _ => false,
+    };
+    if secret_valid {
+        Ok(client)
+    } else {
+        Err(AuthenticateClientError::InvalidClient)
+    }
+}
+
+/// Stores a single use code for the user's authorization of `client`, returning
+/// the code to redirect back with
+///
+/// # Errors
+///
+/// If the insert fails
+pub fn create_authorization_code(
+    connection: &DalConnection,
+    client: &OAuthClient,
+    user: &User,
+    redirect_uri: &str,
+    scope: &str,
+    code_challenge: &str,
+    nonce: Option<&str>,
+) -> Result<String, CreateAuthorizationCodeError> {
+    let code = random_string();
+    let date_created = Utc::now();
+    let lifetime = Duration::minutes(
+        config::get().tokens.authorization_code_lifetime_minutes,
+    );
+    let new_code = NewAuthorizationCode {
+        code: sha256(&code),
+        client_id: client.id,
+        user_id: user.id,
+        redirect_uri,
+        scope,
+        code_challenge,
+        date_created,
+        date_expired: date_created + lifetime,
+        nonce,
+    };
+    dal::oauth::create_authorization_code(connection, &new_code)?;
+    Ok(code)
+}
+
+/// Checks a PKCE code verifier against an S256 code challenge
+fn verify_code_challenge(code_verifier: &str, code_challenge: &str) -> bool {
+    (43..=128).contains(&code_verifier.len())
+        && base64::encode_config(
+            &Sha256::digest(code_verifier.as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        ) == code_challenge
+}
+
+pub struct TokenGrant {
+    pub access_token: String,
+    pub expires_in: i64,
+    pub scope: String,
+    pub id_token: Option<String>,
+}
+
+#[must_use]
+pub fn allows_grant(client: &OAuthClient, grant_type: &str) -> bool {
+    client.grant_types.iter().any(|allowed| allowed == grant_type)
+}
+
+/// Normalises a space separated scope, or returns `None` if it includes any
+/// scope the client isn't allowed
+#[must_use]
+pub fn allowed_scope(client: &OAuthClient, scope: &str) -> Option<String> {
+    let scopes: Vec<&str> =
+        scope.split(' ').filter(|scope| !scope.is_empty()).collect();
+    if scopes.iter().all(|scope| {
+        client.allowed_scopes.iter().any(|allowed| allowed == scope)
+    }) {
+        Some(scopes.join(" "))
+    } else {
+        None
+    }
+}
+
+pub enum ExchangeCodeError {
+    UnauthorizedClient,
+    InvalidGrant,
+    InvalidScope,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Redeems an authorization code for a token, checking it was issued to
+/// `client` for `redirect_uri` and that the PKCE code verifier matches
+///
+/// # Errors
+///
+/// `UnauthorizedClient` if the client can't use the grant, `InvalidGrant` if
+/// the code is unknown, used, expired or doesn't match the request,
+/// `InvalidScope` if the client is no longer allowed the scope, or if a query
+/// fails
+pub fn exchange_authorization_code(
+    connection: &DalConnection,
+    client: &OAuthClient,
+    code: &str,
+    redirect_uri: &str,
+    code_verifier: &str,
+) -> Result<TokenGrant, ExchangeCodeError> {
+    if !allows_grant(client, AUTHORIZATION_CODE_GRANT) {
+        return Err(ExchangeCodeError::UnauthorizedClient);
+    }
+
+    let authorization_code =
+        match dal::oauth::redeem_authorization_code(connection, &sha256(code)) {
+            Ok(authorization_code) => authorization_code,
+            Err(RedeemAuthorizationCodeError::CodeNotFound) => {
+                return Err(ExchangeCodeError::InvalidGrant);
+            }
+            Err(RedeemAuthorizationCodeError::OtherDbError(db_error)) => {
+                return Err(ExchangeCodeError::OtherDbError(db_error));
+            }
+        };
+
+    if authorization_code.client_id != client.id
+        || authorization_code.redirect_uri != redirect_uri
+        || authorization_code.date_expired < Utc::now()
+        || !verify_code_challenge(
+            code_verifier,
+            &authorization_code.code_challenge,
+        )
+    {
+        return Err(ExchangeCodeError::InvalidGrant);
+    }
+    // The client's allowed scopes may have been narrowed since the user
+    // authorized it
+    if allowed_scope(client, &authorization_code.scope).is_none() {
+        return Err(ExchangeCodeError::InvalidScope);
+    }
+
+    let user =
+        match dal::users::get_user_by_id(connection, authorization_code.user_id)
+        {
+            Ok(user) => user,
+            Err(GetUserError::UserNotFound) => {
+                return Err(ExchangeCodeError::InvalidGrant);
+            }
+            Err(GetUserError::OtherDbError(db_error)) => {
+                return Err(ExchangeCodeError::OtherDbError(db_error));
+            }
+        };
+
+    match issue_token(
+        connection,
+        &user,
+        Some(client),
+        Some(&authorization_code.scope),
+    ) {
+        Ok(access_token) => Ok(TokenGrant {
+            access_token,
+            expires_in: token_lifetime().num_seconds(),
+            id_token: if oidc::has_openid_scope(&authorization_code.scope) {
+                // The user always signs in when authorizing, so the code's
+                // creation time is when they authenticated
+                oidc::create_id_token(
+                    client,
+                    &user,
+                    authorization_code.nonce,
+                    authorization_code.date_created,
+                )
+            } else {
+                None
+            },
+            scope: authorization_code.scope,
+        }),
+        Err(CreateAuthTokenError::OtherDbError(db_error)) => {
+            Err(ExchangeCodeError::OtherDbError(db_error))
+        }
+    }
+}
+
+pub enum ClientCredentialsError {
+    UnauthorizedClient,
+    InvalidScope,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Issues a token to a confidential client acting on its own behalf. The
+/// requested scope must be within the client's allowed scopes, and defaults to
+/// all of them.
+///
+/// # Errors
+///
+/// `UnauthorizedClient` for public clients or clients without the grant,
+/// `InvalidScope` if the scope isn't allowed, or if the insert fails
+pub fn client_credentials_grant(
+    connection: &DalConnection,
+    client: &OAuthClient,
+    requested_scope: Option<&str>,
+) -> Result<TokenGrant, ClientCredentialsError> {
+    if client.client_secret.is_none()
+        || !allows_grant(client, CLIENT_CREDENTIALS_GRANT)
+    {
+        return Err(ClientCredentialsError::UnauthorizedClient);
+    }
+
+    let scope = match requested_scope {
+        Some(requested_scope) => match allowed_scope(client, requested_scope) {
+            Some(scope) => scope,
+            None => return Err(ClientCredentialsError::InvalidScope),
+        },
+        None => client.allowed_scopes.join(" "),
+    };
+
+    match issue_service_token(connection, client, &scope) {
+        Ok(access_token) => Ok(TokenGrant {
+            access_token,
+            expires_in: token_lifetime().num_seconds(),
+            scope,
+            id_token: None,
+        }),
+        Err(CreateAuthTokenError::OtherDbError(db_error)) => {
+            Err(ClientCredentialsError::OtherDbError(db_error))
+        }
+    }
+}
+
+pub struct Introspection {
+    pub sub: String,
+    pub exp: Option<i64>,
+    pub iat: i64,
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+}
+
+pub enum IntrospectTokenError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Describes an active token, or returns `None` for tokens that are invalid,
+/// expired or revoked
+///
+/// # Errors
+///
+/// If looking the token up fails
+pub fn introspect_token(
+    connection: &DalConnection,
+    token_string: &str,
+) -> Result<Option<Introspection>, IntrospectTokenError> {
+    if personal_token::is_personal_access_token(token_string) {
+        return match personal_token::verify_personal_token(
+            connection,
+            token_string,
+        ) {
+            Ok(auth_token) => Ok(Some(Introspection {
+                sub: auth_token
+                    .user_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_default(),
+                exp: auth_token.date_expired.map(|date| date.timestamp()),
+                iat: auth_token.date_created.timestamp(),
+                scope: auth_token.scope,
+                client_id: None,
+                username: None,
+            })),
+            Err(VerifyTokenError::GetAuthTokenError(
+                GetAuthTokenError::OtherDbError(db_error),
+            )) => Err(IntrospectTokenError::OtherDbError(db_error)),
+            Err(_) => Ok(None),
+        };
+    }
+
+    let (auth_token, claims) =
+        match verify_token_record(connection, token_string) {
+            Ok(verified) => verified,
+            Err(VerifyTokenError::GetAuthTokenError(
+                GetAuthTokenError::OtherDbError(db_error),
+            )) => {
+                return Err(IntrospectTokenError::OtherDbError(db_error));
+            }
+            Err(_) => return Ok(None),
+        };
+    if auth_token
+        .date_expired
+        .is_some_and(|date_expired| date_expired < Utc::now())
+    {
+        return Ok(None);
+    }
+
+    let client_id = match auth_token
+        .client_id
+        .map(|client_id| dal::oauth::get_client_by_id(connection, client_id))
+    {
+        Some(Ok(client)) => Some(client.client_id),
+        Some(Err(GetOAuthClientError::ClientNotFound)) => return Ok(None),
+        Some(Err(GetOAuthClientError::OtherDbError(db_error))) => {
+            return Err(IntrospectTokenError::OtherDbError(db_error));
+        }
+        None => None,
+    };
+    // Tokens issued before the sub claim was added only have the user ID
+    let sub = if claims.sub.is_empty() {
+        auth_token.user_id.map(|id| id.to_string()).unwrap_or_default()
+    } else {
+        claims.sub
+    };
+
+    Ok(Some(Introspection {
+        sub,
+        exp: auth_token.date_expired.map(|date| date.timestamp()),
+        iat: auth_token.date_created.timestamp(),
+        scope: auth_token.scope,
+        client_id,
+        username: claims.email,
+    }))
+}
+
+pub enum RevokeTokenError {
+    UnauthorizedClient,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Revokes a token issued to `client`, returning it if it was revoked.
+/// Tokens that are already invalid are ignored, as RFC 7009 requires.
+///
+/// # Errors
+///
+/// `UnauthorizedClient` if the token was issued to another client, or if a
+/// query fails
+pub fn revoke_client_token(
+    connection: &DalConnection,
+    client: &OAuthClient,
+    token_string: &str,
+) -> Result<Option<AuthToken>, RevokeTokenError> {
+    let auth_token = match verify_token_record(connection, token_string) {
+        Ok((auth_token, _)) => auth_token,
+        Err(VerifyTokenError::GetAuthTokenError(
+            GetAuthTokenError::OtherDbError(db_error),
+        )) => {
+            return Err(RevokeTokenError::OtherDbError(db_error));
+        }
+        Err(_) => return Ok(None),
+    };
+    if auth_token.client_id != Some(client.id) {
+        return Err(RevokeTokenError::UnauthorizedClient);
+    }
+
+    match dal::auth::revoke_auth_token(connection, auth_token.id) {
+        Ok(true) => Ok(Some(auth_token)),
+        Ok(false) => Ok(None),
+        Err(RevokeAuthTokenError::OtherDbError(db_error)) => {
+            Err(RevokeTokenError::OtherDbError(db_error))
+        }
+    }
+}
+
+pub struct DeviceAuthorizationGrant {
+    pub device_code: String,
+    pub user_code: String,
+    pub expires_in: i64,
+    pub interval: i32,
+}
+
+pub enum DeviceAuthorizationError {
+    UnauthorizedClient,
+    InvalidScope,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Formats a user code for display, e.g. `BDFG-HJKL`
+#[must_use]
+pub fn format_user_code(user_code: &str) -> String {
+    let (first, second) = user_code.split_at(user_code.len() / 2);
+    format!("{first}-{second}")
+}
+
+/// Normalises a user code as typed by the user, ignoring case, separators
+/// and whitespace
+pub fn normalize_user_code(user_code: &str) -> String {
+    user_code
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|character| character.to_ascii_uppercase())
+        .collect()
+}
+
+/// Starts the device flow, returning the device code the client polls with
+/// and the user code the user enters on the verification page
+///
+/// # Errors
+///
+/// `UnauthorizedClient` if the client can't use the device flow, `InvalidScope`
+/// if the scope isn't allowed or asks for an ID token without a signing key, or
+/// if the insert fails
+pub fn create_device_authorization(
+    connection: &DalConnection,
+    client: &OAuthClient,
+    scope: Option<&str>,
+) -> Result<DeviceAuthorizationGrant, DeviceAuthorizationError> {
+    if !allows_grant(client, DEVICE_CODE_GRANT) {
+        return Err(DeviceAuthorizationError::UnauthorizedClient);
+    }
+    let Some(scope) = allowed_scope(client, scope.unwrap_or("")) else {
+        return Err(DeviceAuthorizationError::InvalidScope);
+    };
+    if oidc::has_openid_scope(&scope) && oidc::signing_key().is_none() {
+        return Err(DeviceAuthorizationError::InvalidScope);
+    }
+
+    let device_code = random_string();
+    let mut rng = rand::thread_rng();
+    let user_code: String = (0..USER_CODE_LENGTH)
+        .map(|_| {
+            let index = rng.gen_range(0, USER_CODE_CHARACTERS.len());
+            char::from(USER_CODE_CHARACTERS[index])
+        })
+        .collect();
+    let config = config::get();
+    let lifetime =
+        Duration::minutes(config.tokens.device_code_lifetime_minutes);
+    let poll_interval = config.throttling.device_poll_interval_secs;
+    let date_created = Utc::now();
+    let new_authorization = NewDeviceAuthorization {
+        device_code: sha256(&device_code),
+        user_code: &user_code,
+        client_id: client.id,
+        scope: &scope,
+        poll_interval,
+        date_created,
+        date_expired: date_created + lifetime,
+    };
+    match dal::auth::create_device_authorization(connection, &new_authorization)
+    {
+        Ok(_) => Ok(DeviceAuthorizationGrant {
+            device_code,
+            user_code: format_user_code(&user_code),
+            expires_in: lifetime.num_seconds(),
+            interval: poll_interval,
+        }),
+        Err(CreateDeviceAuthorizationError::OtherDbError(db_error)) => {
+            Err(DeviceAuthorizationError::OtherDbError(db_error))
+        }
+    }
+}
+
+pub enum GetPendingDeviceAuthorizationError {
+    InvalidUserCode,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Looks up a request awaiting the user's decision, along with the client
+/// that made it
+///
+/// # Errors
+///
+/// `InvalidUserCode` if no pending request has the code, or if a query fails
+pub fn get_pending_device_authorization(
+    connection: &DalConnection,
+    user_code: &str,
+) -> Result<
+    (DeviceAuthorization, OAuthClient),
+    GetPendingDeviceAuthorizationError,
+> {
+    let authorization = match dal::auth::get_pending_device_authorization(
+        connection,
+        &normalize_user_code(user_code),
+    ) {
+        Ok(authorization) => authorization,
+        Err(GetDeviceAuthorizationError::DeviceAuthorizationNotFound) => {
+            return Err(GetPendingDeviceAuthorizationError::InvalidUserCode);
+        }
+        Err(GetDeviceAuthorizationError::OtherDbError(db_error)) => {
+            return Err(GetPendingDeviceAuthorizationError::OtherDbError(
+                db_error,
+            ));
+        }
+    };
+    match dal::oauth::get_client_by_id(connection, authorization.client_id) {
+        Ok(client) => Ok((authorization, client)),
+        Err(GetOAuthClientError::ClientNotFound) => {
+            Err(GetPendingDeviceAuthorizationError::InvalidUserCode)
+        }
+        Err(GetOAuthClientError::OtherDbError(db_error)) => {
+            Err(GetPendingDeviceAuthorizationError::OtherDbError(db_error))
+        }
+    }
+}
+
+pub enum DecideDeviceAuthorizationError {
+    InvalidUserCode,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Approves the pending request on behalf of `user`, or denies it if
+/// `approved` is false
+///
+/// # Errors
+///
+/// `InvalidUserCode` if the request was decided or expired in the meantime, or
+/// if the update fails
+pub fn decide_device_authorization(
+    connection: &DalConnection,
+    authorization: &DeviceAuthorization,
+    user: &User,
+    approved: bool,
+) -> Result<(), DecideDeviceAuthorizationError> {
+    match dal::auth::decide_device_authorization(
+        connection,
+        authorization.id,
+        if approved { Some(user.id) } else { None },
+    ) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(DecideDeviceAuthorizationError::InvalidUserCode),
+        Err(UpdateDeviceAuthorizationError::OtherDbError(db_error)) => {
+            Err(DecideDeviceAuthorizationError::OtherDbError(db_error))
+        }
+    }
+}
+
+pub enum DeviceCodeError {
+    UnauthorizedClient,
+    AuthorizationPending,
+    SlowDown,
+    AccessDenied,
+    ExpiredToken,
+    InvalidGrant,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Handles a poll from the device. Devices polling faster than their interval
+/// are told to slow down, and their interval grows by the configured poll
+/// interval each time.
+///
+/// # Errors
+///
+/// `AuthorizationPending` until the user decides, `SlowDown` when polling too
+/// fast, `AccessDenied` if the user denied it, `ExpiredToken` once the code
+/// expires, `InvalidGrant` for unknown or used codes, `UnauthorizedClient` if
+/// the client can't use the grant, or if a query fails
+pub fn exchange_device_code(
+    connection: &DalConnection,
+    client: &OAuthClient,
+    device_code: &str,
+) -> Result<TokenGrant, DeviceCodeError> {
+    if !allows_grant(client, DEVICE_CODE_GRANT) {
+        return Err(DeviceCodeError::UnauthorizedClient);
+    }
+
+    let authorization =
+        match dal::auth::get_device_authorization_by_device_code(
+            connection,
+            &sha256(device_code),
+        ) {
+            Ok(authorization) => authorization,
+            Err(GetDeviceAuthorizationError::DeviceAuthorizationNotFound) => {
+                return Err(DeviceCodeError::InvalidGrant);
+            }
+            Err(GetDeviceAuthorizationError::OtherDbError(db_error)) => {
+                return Err(DeviceCodeError::OtherDbError(db_error));
+            }
+        };
+    if authorization.client_id != client.id
+        || authorization.date_used.is_some()
+    {
+        return Err(DeviceCodeError::InvalidGrant);
+    }
+    let now = Utc::now();
+    if authorization.date_expired < now {
+        return Err(DeviceCodeError::ExpiredToken);
+    }
+
+    let too_fast = authorization.date_last_polled.is_some_and(|last_polled| {
+        now < last_polled
+            + Duration::seconds(i64::from(authorization.poll_interval))
+    });
+    let poll_interval = if too_fast {
+        authorization.poll_interval
+            + config::get().throttling.device_poll_interval_secs
+    } else {
+        authorization.poll_interval
+    };
+    if let Err(UpdateDeviceAuthorizationError::OtherDbError(db_error)) =
+        dal::auth::record_device_poll(
+            connection,
+            authorization.id,
+            now,
+            poll_interval,
+        )
+    {
+        return Err(DeviceCodeError::OtherDbError(db_error));
+    }
+    if too_fast {
+        return Err(DeviceCodeError::SlowDown);
+    }
+    if authorization.date_denied.is_some() {
+        return Err(DeviceCodeError::AccessDenied);
+    }
+    let (Some(user_id), Some(date_approved)) =
+        (authorization.user_id, authorization.date_approved)
+    else {
+        return Err(DeviceCodeError::AuthorizationPending);
+    };
+    match dal::auth::redeem_device_authorization(connection, authorization.id)
+    {
+        Ok(true) => (),
+        Ok(false) => return Err(DeviceCodeError::InvalidGrant),
+        Err(UpdateDeviceAuthorizationError::OtherDbError(db_error)) => {
+            return Err(DeviceCodeError::OtherDbError(db_error));
+        }
+    }
+
+    let user = match dal::users::get_user_by_id(connection, user_id) {
+        Ok(user) => user,
+        Err(GetUserError::UserNotFound) => {
+            return Err(DeviceCodeError::InvalidGrant);
+        }
+        Err(GetUserError::OtherDbError(db_error)) => {
+            return Err(DeviceCodeError::OtherDbError(db_error));
+        }
+    };
+    match issue_token(
+        connection,
+        &user,
+        Some(client),
+        Some(&authorization.scope),
+    ) {
+        Ok(access_token) => Ok(TokenGrant {
+            access_token,
+            expires_in: token_lifetime().num_seconds(),
+            id_token: if oidc::has_openid_scope(&authorization.scope) {
+                oidc::create_id_token(client, &user, None, date_approved)
+            } else {
+                None
+            },
+            scope: authorization.scope,
+        }),
+        Err(CreateAuthTokenError::OtherDbError(db_error)) => {
+            Err(DeviceCodeError::OtherDbError(db_error))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_allows_the_clients_scopes() {
+        let client = OAuthClient {
+            id: 1,
+            client_id: "client".to_owned(),
+            client_secret: None,
+            name: "Client".to_owned(),
+            redirect_uris: vec![],
+            date_created: Utc::now(),
+            grant_types: vec![AUTHORIZATION_CODE_GRANT.to_owned()],
+            allowed_scopes: vec!["openid".to_owned(), "read".to_owned()],
+        };
+        assert_eq!(allowed_scope(&client, "").as_deref(), Some(""));
+        assert_eq!(
+            allowed_scope(&client, " read  openid").as_deref(),
+            Some("read openid")
+        );
+        assert_eq!(allowed_scope(&client, "read admin"), None);
+    }
+}
@@ -0,0 +1,264 @@
+use base64;
+use chrono::{DateTime, Utc};
+use config;
+use dal::{
+    self,
+    auth::GetAuthTokenError,
+    oauth::OAuthClient,
+    users::{GetUserError, User},
+    DalConnection,
+};
+use diesel;
+use handlers::user::{token_lifetime, verify_token_record, VerifyTokenError};
+use jwt;
+use sha2::{Digest, Sha256};
+use std::{fs, path::Path};
+
+pub struct SigningKey {
+    der: Vec<u8>,
+    pub kid: String,
+    pub modulus: Vec<u8>,
+    pub exponent: Vec<u8>,
+}
+
+impl SigningKey {
+    /// Reads a PKCS#1 DER RSA private key
+    ///
+    /// # Errors
+    ///
+    /// If the file can't be read or isn't an RSA key
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let der = fs::read(path).map_err(|error| {
+            format!(
+                "Error reading OIDC signing key {}: {error}",
+                path.display()
+            )
+        })?;
+        let (modulus, exponent) = rsa_public_components(&der).ok_or_else(
+            || {
+                format!(
+                    "OIDC signing key {} must be a PKCS#1 DER RSA key",
+                    path.display()
+                )
+            },
+        )?;
+        let kid = base64::encode_config(
+            &Sha256::digest(&[&modulus[..], &exponent[..]].concat())[..12],
+            base64::URL_SAFE_NO_PAD,
+        );
+        Ok(Self {
+            der,
+            kid,
+            modulus,
+            exponent,
+        })
+    }
+}
+
+/// Reads the next DER element, returning its tag, its contents and the rest
+/// of the input
+fn read_der(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&length_byte, rest) = rest.split_first()?;
+    let (length, rest) = if length_byte < 0x80 {
+        (usize::from(length_byte), rest)
+    } else {
+        let length_bytes = usize::from(length_byte & 0x7f);
+        if length_bytes > 4 || rest.len() < length_bytes {
+            return None;
+        }
+        let length = rest[..length_bytes]
+            .iter()
+            .fold(0, |length, &byte| (length << 8) | usize::from(byte));
+        (length, &rest[length_bytes..])
+    };
+    if rest.len() < length {
+        return None;
+    }
+    Some((tag, &rest[..length], &rest[length..]))
+}
+
+/// Extracts the modulus and public exponent from a PKCS#1 RSA private key
+fn rsa_public_components(der: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    const SEQUENCE: u8 = 0x30;
+    const INTEGER: u8 = 0x02;
+
+    let (SEQUENCE, key, _) = read_der(der)? else {
+        return None;
+    };
+    let (INTEGER, _version, rest) = read_der(key)? else {
+        return None;
+    };
+    let (INTEGER, modulus, rest) = read_der(rest)? else {
+        return None;
+    };
+    let (INTEGER, exponent, _) = read_der(rest)? else {
+        return None;
+    };
+    let strip_sign = |integer: &[u8]| match integer.split_first() {
+        Some((0, rest)) => rest.to_vec(),
+        _ => integer.to_vec(),
+    };
+    Some((strip_sign(modulus), strip_sign(exponent)))
+}
+
+#[must_use]
+pub fn signing_key() -> Option<&'static SigningKey> {
+    config::resources().signing_key.as_ref()
+}
+
+#[must_use]
+pub fn issuer() -> String { config::get().oidc.issuer.clone() }
+
+#[must_use]
+pub fn has_openid_scope(scope: &str) -> bool {
+    scope.split(' ').any(|scope| scope == "openid")
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub auth_time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    pub email: String,
+    /// Always false, as email addresses aren't verified
+    pub email_verified: bool,
+}
+
+/// Creates an ID token for `user`, or `None` if no signing key is configured
+///
+/// # Panics
+///
+/// If the signing key can't sign the token, which loading it rules out
+#[must_use]
+pub fn create_id_token(
+    client: &OAuthClient,
+    user: &User,
+    nonce: Option<String>,
+    auth_time: DateTime<Utc>,
+) -> Option<String> {
+    let signing_key = signing_key()?;
+    let now = Utc::now();
+    let header = jwt::Header {
+        kid: Some(signing_key.kid.clone()),
+        ..jwt::Header::new(jwt::Algorithm::RS256)
+    };
+    let claims = IdTokenClaims {
+        iss: issuer(),
+        sub: user.id.to_string(),
+        aud: client.client_id.clone(),
+        exp: (now + token_lifetime()).timestamp(),
+        iat: now.timestamp(),
+        auth_time: auth_time.timestamp(),
+        nonce,
+        email: user.email.clone(),
+        email_verified: false,
+    };
+    Some(
+        jwt::encode(&header, &claims, &signing_key.der)
+            .expect("Signing key should be valid"),
+    )
+}
+
+pub struct UserInfo {
+    pub sub: String,
+    pub email: String,
+}
+
+#[derive(Debug)]
+pub enum UserInfoError {
+    InvalidToken(VerifyTokenError),
+    InsufficientScope,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Gets the claims about the user a token with the `openid` scope may see
+///
+/// # Errors
+///
+/// `InvalidToken` if the token isn't valid, `InsufficientScope` without the
+/// `openid` scope, or if a query fails
+pub fn user_info(
+    connection: &DalConnection,
+    token_string: &str,
+) -> Result<UserInfo, UserInfoError> {
+    let auth_token = match verify_token_record(connection, token_string) {
+        Ok((auth_token, _)) => auth_token,
+        Err(VerifyTokenError::GetAuthTokenError(
+            GetAuthTokenError::OtherDbError(db_error),
+        )) => {
+            return Err(UserInfoError::OtherDbError(db_error));
+        }
+        Err(error) => return Err(UserInfoError::InvalidToken(error)),
+    };
+    if !auth_token.scope.as_ref().is_some_and(|scope| has_openid_scope(scope))
+    {
+        return Err(UserInfoError::InsufficientScope);
+    }
+
+    let Some(user_id) = auth_token.user_id else {
+        return Err(UserInfoError::InvalidToken(VerifyTokenError::UserMismatch));
+    };
+
+    match dal::users::get_user_by_id(connection, user_id) {
+        Ok(user) => Ok(UserInfo {
+            sub: user.id.to_string(),
+            email: user.email,
+        }),
+        Err(GetUserError::UserNotFound) => Err(UserInfoError::InvalidToken(
+            VerifyTokenError::UserMismatch,
+        )),
+        Err(GetUserError::OtherDbError(db_error)) => {
+            Err(UserInfoError::OtherDbError(db_error))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use handlers::hash_chain::to_hex;
+
+    /// A 512 bit PKCS#1 DER key, too small for real use
+    const KEY: &str = "\
+        MIIBOwIBAAJBAL8v++Jp/wEUx+iYwDl0GDnv8UGBlEjQzqvgY1EGnV9tSYC3\
+        BAa36imz+47cTR6BvvXw78C5BCV3gb7gc4VQAwsCAwEAAQJBAIWE9Es+LUnL\
+        AoWtT+mP629i48EUxsNHmF4umg23Kea+y2sed72rVhc7PCbRYnlxYAVagCDe\
+        xKm0OHwAA318faECIQD8u/7ZEMPOy5IOfBuVqKhY1KcFJ8455Ww71l0PqnPC\
+        2wIhAMGoZrDf+h2Onnsy59zQO3a6FW/zattiqtDCxb2efH+RAiEAv6dIcQ8X\
+        jaAuGDHhMTJ+xy4roes3pgvmcdHzLEgUFPUCICsVP+NRsN8qf2+BH7wsR05e\
+        j65cRQWRqdbPvhY1UBVBAiAPQwevg8kB+yG3nqE/INdvFDFwjYp8VvAFwlFe\
+        Jdunrg==";
+
+    /// The key's modulus, as `openssl rsa -noout -modulus` prints it
+    const MODULUS: &str = "\
+        bf2ffbe269ff0114c7e898c039741839eff141819448d0ceabe06351069d5f6d\
+        4980b70406b7ea29b3fb8edc4d1e81bef5f0efc0b904257781bee0738550030b";
+
+    #[test]
+    fn reads_the_public_components_of_pkcs1_keys() {
+        let der = base64::decode(KEY).unwrap();
+        // The modulus is stored with a leading zero, as its top bit is set
+        assert_eq!(&der[8..11], [0x41, 0x00, 0xbf]);
+
+        let (modulus, exponent) = rsa_public_components(&der).unwrap();
+        assert_eq!(to_hex(&modulus), MODULUS);
+        assert_eq!(exponent, [0x01, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn rejects_truncated_and_non_rsa_keys() {
+        let der = base64::decode(KEY).unwrap();
+        assert!(rsa_public_components(&der[..40]).is_none());
+        assert!(rsa_public_components(&der[1..]).is_none());
+        assert!(rsa_public_components(&[]).is_none());
+        // A SEQUENCE holding a string rather than integers
+        assert!(rsa_public_components(&[0x30, 0x03, 0x04, 0x01, 0x00])
+            .is_none());
+    }
+}
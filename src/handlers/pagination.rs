@@ -0,0 +1,104 @@
+use diesel;
+use std::convert::TryFrom;
+
+/// A page of rows, most recent first
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Gets the next page when passed back to the search, if there are more
+    /// rows
+    pub next_cursor: Option<String>,
+}
+
+pub enum SearchPageError {
+    InvalidCursor,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Gets a page of rows, most recent first. Pages are fetched by ID, so rows
+/// added while paging don't shift them.
+///
+/// `fetch` is given the ID rows must be below, if any, and how many to get.
+///
+/// # Errors
+///
+/// `InvalidCursor` if the cursor isn't an ID, or if `fetch` fails
+pub fn fetch_page<T>(
+    cursor: Option<&str>,
+    limit: i64,
+    id: impl Fn(&T) -> i64,
+    fetch: impl FnOnce(
+        Option<i64>,
+        i64,
+    ) -> Result<Vec<T>, diesel::result::Error>,
+) -> Result<Page<T>, SearchPageError> {
+    let before_id = match cursor.map(str::parse::<i64>) {
+        None => None,
+        Some(Ok(before_id)) => Some(before_id),
+        Some(Err(_)) => return Err(SearchPageError::InvalidCursor),
+    };
+
+    // Fetch one more than asked for to find out if there's another page
+    let mut items = match fetch(before_id, limit + 1) {
+        Ok(items) => items,
+        Err(db_error) => return Err(SearchPageError::OtherDbError(db_error)),
+    };
+    let limit = usize::try_from(limit).unwrap_or(0);
+    let next_cursor = if items.len() > limit {
+        items.pop();
+        items.last().map(|item| id(item).to_string())
+    } else {
+        None
+    };
+
+    Ok(Page { items, next_cursor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fetches from IDs 1-5, most recent first
+    fn fetch(before_id: Option<i64>, limit: i64) -> Vec<i64> {
+        (1..=5)
+            .rev()
+            .filter(|id| before_id.is_none_or(|before_id| *id < before_id))
+            .take(usize::try_from(limit).unwrap())
+            .collect()
+    }
+
+    fn page(cursor: Option<&str>, limit: i64) -> Page<i64> {
+        fetch_page(cursor, limit, |id| *id, |before_id, limit| {
+            Ok(fetch(before_id, limit))
+        })
+        .unwrap_or_else(|_| panic!("The page should be fetched"))
+    }
+
+    #[test]
+    fn pages_through_every_row() {
+        let first = page(None, 2);
+        assert_eq!(first.items, [5, 4]);
+        assert_eq!(first.next_cursor.as_deref(), Some("4"));
+        let second = page(first.next_cursor.as_deref(), 2);
+        assert_eq!(second.items, [3, 2]);
+        let last = page(second.next_cursor.as_deref(), 2);
+        assert_eq!(last.items, [1]);
+        assert_eq!(last.next_cursor, None);
+    }
+
+    #[test]
+    fn exactly_full_last_page_has_no_cursor() {
+        let all = page(None, 5);
+        assert_eq!(all.items.len(), 5);
+        assert_eq!(all.next_cursor, None);
+    }
+
+    #[test]
+    fn rejects_invalid_cursor() {
+        assert!(matches!(
+            fetch_page(Some("abc"), 2, |id| *id, |before_id, limit| {
+                Ok(fetch(before_id, limit))
+            }),
+            Err(SearchPageError::InvalidCursor)
+        ));
+    }
+}
@@ -0,0 +1,895 @@
+extern crate argon2;
+extern crate easy_password;
+
+use self::{
+    argon2::{
+        password_hash::{
+            PasswordHash,
+            PasswordHasher,
+            PasswordVerifier,
+            Salt,
+            SaltString,
+        },
+        Algorithm,
+        Argon2,
+        Params,
+        Version,
+    },
+    easy_password::bcrypt,
+};
+use config;
+use dal::{
+    users::{self, CountUsersError},
+    DalConnection,
+};
+use diesel;
+use handlers::{
+    metrics::{self, Histogram},
+    oauth::random_string,
+};
+use rand::Rng;
+use secrets;
+use sha1::Sha1;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    env,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+use telemetry;
+
+/// Passwords so common they're guessed first whatever else they contain
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456", "password", "12345678", "qwerty", "123456789", "12345",
+    "1234", "111111", "1234567", "dragon", "123123", "baseball", "abc123",
+    "football", "monkey", "letmein", "696969", "shadow", "master", "666666",
+    "qwertyuiop", "123321", "mustang", "1234567890", "michael", "654321",
+    "superman", "1qaz2wsx", "7777777", "121212", "000000", "qazwsx",
+    "123qwe", "killer", "trustno1", "jordan", "jennifer", "zxcvbnm",
+    "asdfgh", "hunter", "buster", "soccer", "harley", "batman", "andrew",
+    "tigger", "sunshine", "iloveyou", "charlie", "robert", "thomas",
+    "hockey", "ranger", "daniel", "starwars", "klaster", "112233", "george",
+    "computer", "michelle", "jessica", "pepper", "1111", "zxcvbn", "555555",
+    "11111111", "131313", "freedom", "777777", "pass", "maggie", "159753",
+    "aaaaaa", "ginger", "princess", "joshua", "cheese", "amanda", "summer",
+    "love", "ashley", "nicole", "chelsea", "biteme", "matthew", "access",
+    "yankees", "987654321", "dallas", "austin", "thunder", "taylor",
+    "matrix", "welcome", "admin", "login", "secret", "changeme", "passw0rd",
+];
+
+const KEYBOARD_ROWS: &[&str] =
+    &["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// The length of the hash prefixes the k-anonymity range API groups SHA-1
+/// hashes by
+const RANGE_PREFIX_LENGTH: usize = 5;
+
+/// The hash at the start of a breached password list entry, `<hash>` or
+/// `<hash>:<count>`, if it's `length` hex characters
+fn entry_hash(line: &str, length: usize) -> Option<String> {
+    let hash = line.split(':').next().unwrap_or("").trim();
+    (hash.len() == length && hash.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| hash.to_ascii_uppercase())
+}
+
+/// The range prefix a file or directory is named after, if any
+fn range_prefix(path: &Path) -> Option<String> {
+    let name = path.file_stem()?.to_str()?;
+    entry_hash(name, RANGE_PREFIX_LENGTH)
+}
+
+/// Whether the first entry of a list has a hash of `length` characters
+fn first_entry_valid(path: &Path, length: usize) -> io::Result<bool> {
+    let mut line = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut line)?;
+    Ok(entry_hash(&line, length).is_some())
+}
+
+/// The first line starting at or after `offset`, with where it starts
+fn line_from(
+    reader: &mut BufReader<File>,
+    offset: u64,
+) -> io::Result<Option<(u64, String)>> {
+    let start = if offset > 0 {
+        reader.seek(SeekFrom::Start(offset - 1))?;
+        let mut skipped = Vec::new();
+        offset - 1 + reader.read_until(b'\n', &mut skipped)? as u64
+    } else {
+        reader.seek(SeekFrom::Start(0))?;
+        0
+    };
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some((start, line)))
+}
+
+/// Binary searches a list sorted by hash for an entry with `hash`, so only a
+/// few lines of even the full list have to be read
+fn sorted_list_contains(path: &Path, hash: &str) -> io::Result<bool> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut low = 0;
+    let mut high = reader.get_ref().metadata()?.len();
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let Some((start, line)) = line_from(&mut reader, middle)? else {
+            high = middle;
+            continue;
+        };
+        if start >= high {
+            high = middle;
+            continue;
+        }
+        let entry = line.split(':').next().unwrap_or("").trim();
+        match entry.to_ascii_uppercase().as_str().cmp(hash) {
+            Ordering::Equal => return Ok(true),
+            Ordering::Less => low = start + line.len() as u64,
+            Ordering::Greater => high = middle,
+        }
+    }
+    Ok(false)
+}
+
+/// A list of SHA-1 hashes of breached passwords, sorted by hash like the
+/// downloadable Have I Been Pwned lists. Lists are searched on disk rather
+/// than loaded, as the full list is tens of gigabytes.
+pub enum BreachedPasswords {
+    /// One `<hash>[:<count>]` entry per line
+    Hashes(PathBuf),
+    /// A range API response, with one `<suffix>[:<count>]` entry per line for
+    /// the hashes starting with the prefix the file is named after
+    Range { prefix: String, path: PathBuf },
+    /// A directory of range files, one per prefix, named `<prefix>.txt` or
+    /// just `<prefix>`
+    Ranges(PathBuf),
+}
+
+impl BreachedPasswords {
+    /// Works out which kind of list `path` is, failing if it has no entries
+    ///
+    /// # Errors
+    ///
+    /// If `path` can't be read or has no entries
+    pub fn load(path: &str) -> Result<Self, String> {
+        let error = |error: io::Error| {
+            format!("Error reading breached password list {path}: {error}")
+        };
+        let no_entries = || {
+            format!(
+                "Breached password list {path} has no SHA-1 hashes, or \
+                 <suffix>:<count> entries in files named after their prefix"
+            )
+        };
+        let path = PathBuf::from(path);
+        let suffix_length = 40 - RANGE_PREFIX_LENGTH;
+
+        if path.is_dir() {
+            for entry in fs::read_dir(&path).map_err(error)? {
+                let entry_path = entry.map_err(error)?.path();
+                if range_prefix(&entry_path).is_some()
+                    && first_entry_valid(&entry_path, suffix_length)
+                        .map_err(error)?
+                {
+                    return Ok(Self::Ranges(path));
+                }
+            }
+            return Err(no_entries());
+        }
+        if first_entry_valid(&path, 40).map_err(error)? {
+            return Ok(Self::Hashes(path));
+        }
+        match range_prefix(&path) {
+            Some(prefix)
+                if first_entry_valid(&path, suffix_length).map_err(error)? =>
+            {
+                Ok(Self::Range { prefix, path })
+            }
+            _ => Err(no_entries()),
+        }
+    }
+
+    fn search(&self, hash: &str) -> io::Result<bool> {
+        let (prefix, suffix) = hash.split_at(RANGE_PREFIX_LENGTH);
+        match self {
+            Self::Hashes(path) => sorted_list_contains(path, hash),
+            Self::Range {
+                prefix: range,
+                path,
+            } => {
+                if range == prefix {
+                    sorted_list_contains(path, suffix)
+                } else {
+                    Ok(false)
+                }
+            }
+            Self::Ranges(directory) => {
+                let range = [format!("{prefix}.txt"), prefix.to_owned()]
+                    .iter()
+                    .map(|name| directory.join(name))
+                    .find(|path| path.is_file());
+                range.map_or(Ok(false), |path| {
+                    sorted_list_contains(&path, suffix)
+                })
+            }
+        }
+    }
+
+    /// Whether the password is on the list. Passwords are allowed if the list
+    /// can't be read, so an unreadable list doesn't block every password.
+    #[must_use]
+    pub fn contains(&self, password: &str) -> bool {
+        let hash =
+            Sha1::from(password).digest().to_string().to_ascii_uppercase();
+        self.search(&hash).unwrap_or_else(|error| {
+            error!("Error reading the breached password list: {error}");
+            false
+        })
+    }
+}
+
+/// The peppers passwords are hashed with. `HMAC_HASH` is the current one,
+/// with the version `password.pepper_id`, and retired versions are kept in
+/// `HMAC_HASH_<version>` until no hashes use them.
+///
+/// They're read as secrets, so they can also come from files or Vault.
+pub struct Peppers {
+    pub current_id: i32,
+    peppers: HashMap<i32, String>,
+}
+
+impl Peppers {
+    pub fn get(&self, pepper_id: i32) -> Option<&str> {
+        self.peppers.get(&pepper_id).map(String::as_str)
+    }
+
+    #[must_use]
+    pub fn is_configured(&self, pepper_id: i32) -> bool {
+        self.peppers.contains_key(&pepper_id)
+    }
+
+    /// Reads `HMAC_HASH` as the pepper `current_id`, along with any retired
+    /// versions
+    ///
+    /// # Errors
+    ///
+    /// If `HMAC_HASH` is missing, a versioned secret clashes with the current
+    /// pepper, or a secret can't be read
+    pub fn load(current_id: i32) -> Result<Self, String> {
+        // Secret stores can't be listed, so retired versions are looked for
+        // below the current one, as well as any named in the environment
+        let named_versions = env::vars().filter_map(|(name, _)| {
+            name.strip_prefix("HMAC_HASH_")
+                .map(|version| version.trim_end_matches("_FILE"))
+                .and_then(|version| version.parse().ok())
+        });
+        let mut peppers = HashMap::new();
+        for pepper_id in (1..=current_id).chain(named_versions) {
+            let name = format!("HMAC_HASH_{pepper_id}");
+            match secrets::get(&name) {
+                Ok(Some(_)) if pepper_id == current_id => {
+                    return Err(format!(
+                        "{name} can't be set as it's the current \
+                         password.pepper_id"
+                    ));
+                }
+                Ok(Some(pepper)) => {
+                    peppers.insert(pepper_id, pepper);
+                }
+                Ok(None) => {}
+                Err(error) => return Err(error.to_string()),
+            }
+        }
+        match secrets::get("HMAC_HASH") {
+            Ok(Some(pepper)) => peppers.insert(current_id, pepper),
+            Ok(None) => return Err("HMAC_HASH must be set".to_owned()),
+            Err(error) => return Err(error.to_string()),
+        };
+        Ok(Self {
+            current_id,
+            peppers,
+        })
+    }
+}
+
+static ARGON2_PARAMS: LazyLock<Params> = LazyLock::new(|| {
+    let config = &config::get().password;
+    Params::new(
+        config.argon2_memory_kib,
+        config.argon2_iterations,
+        config.argon2_parallelism,
+        None,
+    )
+    .expect("password.argon2_* are checked when loading the configuration")
+});
+
+#[must_use]
+pub fn peppers() -> &'static Peppers { &config::resources().peppers }
+
+/// Bits of entropy a character adds given the one before it. Repeats,
+/// sequences and keyboard neighbours add very little.
+fn character_bits(
+    previous: Option<char>,
+    character: char,
+    charset: f64,
+) -> f64 {
+    let Some(previous) = previous else {
+        return charset.log2();
+    };
+    let (previous, lower) =
+        (previous.to_ascii_lowercase(), character.to_ascii_lowercase());
+    let step = i64::from(u32::from(lower)) - i64::from(u32::from(previous));
+    let keyboard_neighbours = KEYBOARD_ROWS.iter().any(|row| {
+        row.find(previous).is_some_and(|position| {
+            row.find(lower).is_some_and(|other| position.abs_diff(other) == 1)
+        })
+    });
+    if step == 0 {
+        1.0
+    } else if step.abs() == 1 {
+        1.5
+    } else if keyboard_neighbours {
+        2.0
+    } else {
+        charset.log2()
+    }
+}
+
+/// Estimates how hard a password is to guess, from 0 (trivially guessable)
+/// to 4 (very unguessable), in the style of zxcvbn.
+///
+/// Common passwords and `user_inputs` such as the user's email are treated
+/// as a single guess wherever they appear.
+#[must_use]
+pub fn strength_score(password: &str, user_inputs: &[&str]) -> u8 {
+    let lowercase = password.to_lowercase();
+    if COMMON_PASSWORDS.contains(&lowercase.as_str()) {
+        return 0;
+    }
+
+    let charset = [
+        (password.chars().any(|c| c.is_ascii_lowercase()), 26.0),
+        (password.chars().any(|c| c.is_ascii_uppercase()), 26.0),
+        (password.chars().any(|c| c.is_ascii_digit()), 10.0),
+        (password.chars().any(|c| c.is_ascii_punctuation() || c == ' '), 33.0),
+        (!password.is_ascii(), 100.0),
+    ]
+    .iter()
+    .filter(|(present, _)| *present)
+    .map(|(_, size)| size)
+    .sum::<f64>();
+
+    // Blank out known words, each costing a lookup in their list instead
+    let mut remaining = lowercase;
+    let mut bits = 0.0;
+    let words = COMMON_PASSWORDS
+        .iter()
+        .map(|word| (*word, COMMON_PASSWORDS.len()))
+        .chain(user_inputs.iter().map(|input| (*input, user_inputs.len())));
+    for (word, list_size) in words {
+        let word = word.to_lowercase();
+        if word.chars().count() >= 4 && remaining.contains(&word) {
+            remaining = remaining.replace(&word, "\0");
+            #[allow(clippy::cast_precision_loss)]
+            let list_bits = (list_size as f64).log2().max(1.0);
+            bits += list_bits;
+        }
+    }
+
+    let mut previous = None;
+    for character in remaining.chars().filter(|&c| c != '\0') {
+        bits += character_bits(previous, character, charset);
+        previous = Some(character);
+    }
+
+    // The guesses zxcvbn's scores correspond to: 10^3, 10^6, 10^8 and 10^10
+    match bits {
+        bits if bits < 10.0 => 0,
+        bits if bits < 20.0 => 1,
+        bits if bits < 26.6 => 2,
+        bits if bits < 33.2 => 3,
+        _ => 4,
+    }
+}
+
+#[derive(Debug)]
+pub enum PasswordViolation {
+    TooShort(usize),
+    TooLong(usize),
+    TooWeak(u8),
+    ContainsEmail,
+    Breached,
+}
+
+/// Checks a new password against the password policy, returning every rule
+/// it breaks
+pub fn check_password(
+    password: &str,
+    email: Option<&str>,
+) -> Vec<PasswordViolation> {
+    let policy = &config::get().password;
+    let mut violations = Vec::new();
+
+    let length = password.chars().count();
+    if length < policy.min_length {
+        violations.push(PasswordViolation::TooShort(policy.min_length));
+    }
+    if length > policy.max_length {
+        violations.push(PasswordViolation::TooLong(policy.max_length));
+    }
+
+    let email = email.map(str::to_lowercase);
+    let local_part = email
+        .as_ref()
+        .and_then(|email| email.split('@').next())
+        .filter(|local_part| local_part.len() >= 3);
+    let lowercase = password.to_lowercase();
+    if email.as_ref().is_some_and(|email| lowercase.contains(email.as_str()))
+        || local_part.is_some_and(|local_part| lowercase.contains(local_part))
+    {
+        violations.push(PasswordViolation::ContainsEmail);
+    }
+
+    let user_inputs: Vec<&str> =
+        email.iter().map(String::as_str).chain(local_part).collect();
+    if strength_score(password, &user_inputs) < policy.min_strength {
+        violations.push(PasswordViolation::TooWeak(policy.min_strength));
+    }
+
+    if config::resources()
+        .breached_passwords
+        .as_ref()
+        .is_some_and(|breached| breached.contains(password))
+    {
+        violations.push(PasswordViolation::Breached);
+    }
+
+    violations
+}
+
+/// Argon2id with the configured parameters, keyed with a pepper so hashes
+/// can't be cracked without it
+fn argon2(secret: &[u8]) -> Argon2<'_> {
+    Argon2::new_with_secret(
+        secret,
+        Algorithm::Argon2id,
+        Version::V0x13,
+        ARGON2_PARAMS.clone(),
+    )
+    .expect("Peppers should be valid Argon2 secrets")
+}
+
+pub struct HashedPassword {
+    /// PHC format string
+    pub hash: String,
+    pub pepper_id: i32,
+}
+
+/// Hashes a password with Argon2id and the current pepper
+#[must_use]
+pub fn hash_password(password: &str) -> HashedPassword {
+    hash_with_peppers(password, peppers())
+}
+
+fn hash_with_peppers(password: &str, peppers: &Peppers) -> HashedPassword {
+    let salt_bytes: [u8; Salt::RECOMMENDED_LENGTH] = rand::thread_rng().gen();
+    let salt =
+        SaltString::encode_b64(&salt_bytes).expect("Salt should be valid");
+    let pepper = peppers
+        .get(peppers.current_id)
+        .expect("The current pepper should be configured");
+    let hash = metrics::time(
+        Histogram::PasswordHashDuration,
+        &["argon2id", "hash"],
+        || {
+            let mut span = telemetry::span("handlers::password::hash");
+            span.set_string("password.algorithm", "argon2id");
+            argon2(pepper.as_bytes())
+                .hash_password(password.as_bytes(), &salt)
+                .expect("Parameters should be valid")
+                .to_string()
+        },
+    );
+    HashedPassword {
+        hash,
+        pepper_id: peppers.current_id,
+    }
+}
+
+/// Hashes a random password, for logins for unknown users to be checked
+/// against so they take as long as logins for real users
+#[must_use]
+pub fn dummy_password_hash(peppers: &Peppers) -> HashedPassword {
+    hash_with_peppers(&random_string(), peppers)
+}
+
+/// Does the work of checking a password for a user that doesn't exist
+pub fn verify_dummy_password(password: &str) {
+    let dummy = &config::resources().dummy_password_hash;
+    let _ = verify_password(password, &dummy.hash, dummy.pepper_id);
+}
+
+/// Checks a password against an Argon2 PHC string or a legacy bcrypt hash.
+/// Hashes using a pepper that's no longer configured, or that can't be
+/// parsed, never match.
+#[must_use]
+pub fn verify_password(
+    password: &str,
+    password_hash: &str,
+    pepper_id: i32,
+) -> bool {
+    verify_with_peppers(password, password_hash, pepper_id, peppers())
+}
+
+fn verify_with_peppers(
+    password: &str,
+    password_hash: &str,
+    pepper_id: i32,
+    peppers: &Peppers,
+) -> bool {
+    let Some(pepper) = peppers.get(pepper_id) else {
+        verify_dummy_password(password);
+        return false;
+    };
+    if password_hash.starts_with("$2") {
+        return metrics::time(
+            Histogram::PasswordHashDuration,
+            &["bcrypt", "verify"],
+            || {
+                let mut span = telemetry::span("handlers::password::verify");
+                span.set_string("password.algorithm", "bcrypt");
+                bcrypt::verify_password(
+                    password,
+                    password_hash,
+                    pepper.as_bytes(),
+                )
+                .unwrap_or_else(|error| {
+                    error!("Error verifying a bcrypt password hash: {error:?}");
+                    false
+                })
+            },
+        );
+    }
+
+    let parsed_hash = match PasswordHash::new(password_hash) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(error) => {
+            error!("Error parsing a password hash: {error}");
+            return false;
+        }
+    };
+    metrics::time(
+        Histogram::PasswordHashDuration,
+        &["argon2id", "verify"],
+        || {
+            let mut span = telemetry::span("handlers::password::verify");
+            span.set_string("password.algorithm", "argon2id");
+            argon2(pepper.as_bytes())
+                .verify_password(password.as_bytes(), &parsed_hash)
+                .is_ok()
+        },
+    )
+}
+
+/// Whether a hash uses an older algorithm, parameters or pepper than
+/// `hash_password` would, and so should be replaced
+#[must_use]
+pub fn needs_rehash(password_hash: &str, pepper_id: i32) -> bool {
+    needs_rehash_with_peppers(password_hash, pepper_id, peppers())
+}
+
+fn needs_rehash_with_peppers(
+    password_hash: &str,
+    pepper_id: i32,
+    peppers: &Peppers,
+) -> bool {
+    if pepper_id != peppers.current_id {
+        return true;
+    }
+    let Ok(parsed_hash) = PasswordHash::new(password_hash) else {
+        return true;
+    };
+    if parsed_hash.algorithm != Algorithm::Argon2id.ident()
+        || parsed_hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    Params::try_from(&parsed_hash).map_or(true, |params| {
+        params.m_cost() != ARGON2_PARAMS.m_cost()
+            || params.t_cost() != ARGON2_PARAMS.t_cost()
+            || params.p_cost() != ARGON2_PARAMS.p_cost()
+            || params.output_len() != Some(Params::DEFAULT_OUTPUT_LEN)
+    })
+}
+
+pub struct PepperUsage {
+    pub pepper_id: i32,
+    pub users: i64,
+    pub current: bool,
+    pub configured: bool,
+}
+
+pub enum PepperUsageError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// How many users' passwords are hashed with each pepper, including
+/// configured peppers no hashes use any more
+///
+/// # Errors
+///
+/// If counting the users fails
+pub fn pepper_usage(
+    connection: &DalConnection,
+) -> Result<Vec<PepperUsage>, PepperUsageError> {
+    let counts = match users::count_users_by_pepper(connection) {
+        Ok(counts) => counts,
+        Err(CountUsersError::OtherDbError(db_error)) => {
+            return Err(PepperUsageError::OtherDbError(db_error));
+        }
+    };
+
+    let peppers = peppers();
+    let mut usage: BTreeMap<i32, i64> =
+        peppers.peppers.keys().map(|&pepper_id| (pepper_id, 0)).collect();
+    usage.extend(counts);
+    Ok(usage
+        .into_iter()
+        .map(|(pepper_id, users)| PepperUsage {
+            pepper_id,
+            users,
+            current: pepper_id == peppers.current_id,
+            configured: peppers.is_configured(pepper_id),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fmt::Write as _, process};
+    use test_support;
+
+    /// SHA-1 of `password`
+    const PASSWORD_HASH: &str = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8";
+
+    fn temp_path(name: &str) -> PathBuf {
+        let directory =
+            env::temp_dir().join(format!("login_api_test_{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory.join(name)
+    }
+
+    fn sha1(value: &str) -> String {
+        Sha1::from(value).digest().to_string().to_ascii_uppercase()
+    }
+
+    fn load(path: &Path) -> Result<BreachedPasswords, String> {
+        BreachedPasswords::load(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn verifies_hashes_and_only_rehashes_outdated_ones() {
+        test_support::init();
+        let hashed = hash_password("correct horse battery staple");
+        assert!(hashed.hash.starts_with("$argon2id$"));
+        assert_eq!(hashed.pepper_id, peppers().current_id);
+        assert!(verify_password(
+            "correct horse battery staple",
+            &hashed.hash,
+            hashed.pepper_id
+        ));
+        assert!(!verify_password("hunter2", &hashed.hash, hashed.pepper_id));
+        assert!(!needs_rehash(&hashed.hash, hashed.pepper_id));
+
+        // Hashed with more iterations than configured
+        let outdated = hashed.hash.replacen("t=", "t=1", 1);
+        assert!(needs_rehash(&outdated, hashed.pepper_id));
+    }
+
+    #[test]
+    fn verifies_legacy_bcrypt_hashes() {
+        test_support::init();
+        let pepper_id = peppers().current_id;
+        let pepper = peppers().get(pepper_id).unwrap();
+        let hash = bcrypt::hash_password("hunter2", pepper.as_bytes(), 4)
+            .unwrap();
+        assert!(verify_password("hunter2", &hash, pepper_id));
+        assert!(!verify_password("hunter3", &hash, pepper_id));
+        assert!(needs_rehash(&hash, pepper_id));
+    }
+
+    #[test]
+    fn unparsable_hashes_never_match() {
+        test_support::init();
+        let pepper_id = peppers().current_id;
+        for hash in ["", "plaintext", "$argon2id$v=19$broken", "$2b$04$short"] {
+            assert!(!verify_password("plaintext", hash, pepper_id), "{}", hash);
+            assert!(needs_rehash(hash, pepper_id), "{}", hash);
+        }
+    }
+
+    fn violations(password: &str, email: Option<&str>) -> Vec<String> {
+        test_support::init();
+        check_password(password, email)
+            .iter()
+            .map(|violation| format!("{violation:?}"))
+            .collect()
+    }
+
+    #[test]
+    fn scores_common_and_personal_passwords_as_weak() {
+        assert_eq!(strength_score("password", &[]), 0);
+        assert_eq!(strength_score("PassWord", &[]), 0);
+        assert_eq!(strength_score("aaaaaaaaaaaa", &[]), 0);
+        assert_eq!(strength_score("abcdefgh", &[]), 1);
+        assert_eq!(strength_score("correct horse battery staple", &[]), 4);
+        assert_eq!(strength_score("jane.doe1", &["jane.doe"]), 0);
+        assert_eq!(strength_score("jane.doe1", &[]), 4);
+    }
+
+    #[test]
+    fn rejects_common_passwords() {
+        assert_eq!(violations("password", None), ["TooWeak(2)"]);
+        assert_eq!(violations("Password1", None), ["TooWeak(2)"]);
+        assert!(violations("correct horse battery staple", None).is_empty());
+    }
+
+    #[test]
+    fn rejects_passwords_containing_the_email() {
+        let email = Some("Jane.Doe@example.com");
+        assert_eq!(
+            violations("my jane.doe@example.com login", email),
+            ["ContainsEmail"]
+        );
+        assert_eq!(
+            violations("JANE.DOE waves hello", email),
+            ["ContainsEmail"]
+        );
+        assert!(violations("correct horse battery staple", email).is_empty());
+        // Local parts this short would rule out too many passwords
+        assert!(violations("jo correct horse staple", Some("jo@example.com"))
+            .is_empty());
+    }
+
+    #[test]
+    fn counts_length_in_characters() {
+        assert_eq!(violations("Tr0ub4!", None), ["TooShort(8)"]);
+        // Eight characters, but fourteen bytes
+        assert!(!violations("ñandú€ßø", None)
+            .contains(&"TooShort(8)".to_owned()));
+        let longest = "ñ".repeat(72);
+        assert!(
+            !violations(&longest, None).contains(&"TooLong(72)".to_owned())
+        );
+        assert!(violations(&format!("{longest}ñ"), None)
+            .contains(&"TooLong(72)".to_owned()));
+    }
+
+    fn test_peppers(current_id: i32, peppers: &[(i32, &str)]) -> Peppers {
+        Peppers {
+            current_id,
+            peppers: peppers
+                .iter()
+                .map(|&(pepper_id, pepper)| (pepper_id, pepper.to_owned()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn verifies_hashes_with_retired_peppers() {
+        test_support::init();
+        let original = test_peppers(1, &[(1, "first pepper")]);
+        let hashed = hash_with_peppers("hunter2", &original);
+        assert_eq!(hashed.pepper_id, 1);
+
+        // HMAC_HASH is now version 2, with version 1 kept as HMAC_HASH_1
+        let rotated =
+            test_peppers(2, &[(1, "first pepper"), (2, "second pepper")]);
+        assert!(verify_with_peppers("hunter2", &hashed.hash, 1, &rotated));
+        assert!(!verify_with_peppers("hunter3", &hashed.hash, 1, &rotated));
+        assert!(!verify_with_peppers("hunter2", &hashed.hash, 2, &rotated));
+    }
+
+    #[test]
+    fn never_matches_hashes_with_unknown_peppers() {
+        test_support::init();
+        let original = test_peppers(1, &[(1, "first pepper")]);
+        let hashed = hash_with_peppers("hunter2", &original);
+
+        // HMAC_HASH_1 was removed while hashes still used it
+        let rotated = test_peppers(2, &[(2, "second pepper")]);
+        assert!(!verify_with_peppers("hunter2", &hashed.hash, 1, &rotated));
+        assert!(!verify_with_peppers("hunter2", &hashed.hash, 3, &rotated));
+    }
+
+    #[test]
+    fn rehashes_onto_the_current_pepper() {
+        test_support::init();
+        let original = test_peppers(1, &[(1, "first pepper")]);
+        let hashed = hash_with_peppers("hunter2", &original);
+        let rotated =
+            test_peppers(2, &[(1, "first pepper"), (2, "second pepper")]);
+        assert!(!needs_rehash_with_peppers(&hashed.hash, 1, &original));
+        assert!(needs_rehash_with_peppers(&hashed.hash, 1, &rotated));
+
+        let rehashed = hash_with_peppers("hunter2", &rotated);
+        assert_eq!(rehashed.pepper_id, 2);
+        assert!(verify_with_peppers("hunter2", &rehashed.hash, 2, &rotated));
+        assert!(!needs_rehash_with_peppers(&rehashed.hash, 2, &rotated));
+    }
+
+    #[test]
+    fn searches_sorted_hash_lists() {
+        let mut hashes: Vec<String> = (0..500)
+            .map(|n| sha1(&format!("breached {n}")))
+            .chain([sha1("password")])
+            .collect();
+        hashes.sort();
+        let path = temp_path("hashes.txt");
+        let mut contents = String::new();
+        for (count, hash) in hashes.iter().enumerate() {
+            write!(contents, "{hash}:{count}\r\n").unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+
+        let breached = load(&path).unwrap();
+        assert!(matches!(breached, BreachedPasswords::Hashes(_)));
+        for n in [0, 1, 250, 498, 499] {
+            assert!(breached.contains(&format!("breached {n}")));
+        }
+        assert!(breached.contains("password"));
+        assert!(!breached.contains("breached 500"));
+        assert!(!breached.contains("correct horse battery staple"));
+    }
+
+    #[test]
+    fn searches_range_files() {
+        let directory = temp_path("ranges");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("5BAA6.txt"),
+            format!(
+                "003D68EB55068C33ACE09247EE4C639306B:3\n\
+                 {}:9545824\n\
+                 FFF8D6B6FD91E8E8C4A1EC1C4CBD0DE2EB3:1\n",
+                &PASSWORD_HASH[5..]
+            ),
+        )
+        .unwrap();
+        fs::write(directory.join("notes.txt"), "Not a range").unwrap();
+
+        let breached = load(&directory).unwrap();
+        assert!(matches!(breached, BreachedPasswords::Ranges(_)));
+        assert!(breached.contains("password"));
+        assert!(!breached.contains("hunter2"));
+
+        let breached = load(&directory.join("5BAA6.txt")).unwrap();
+        assert!(matches!(breached, BreachedPasswords::Range { .. }));
+        assert!(breached.contains("password"));
+        assert!(!breached.contains("hunter2"));
+    }
+
+    #[test]
+    fn rejects_lists_without_entries() {
+        let empty = temp_path("empty.txt");
+        fs::write(&empty, "").unwrap();
+        assert!(load(&empty).is_err());
+
+        let unnamed_range = temp_path("range.txt");
+        fs::write(&unnamed_range, format!("{}:1\n", &PASSWORD_HASH[5..]))
+            .unwrap();
+        assert!(load(&unnamed_range).is_err());
+
+        let directory = temp_path("no_ranges");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("5BAA6.txt"), "Not a range").unwrap();
+        assert!(load(&directory).is_err());
+
+        assert!(load(&temp_path("missing.txt")).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use chrono::{DateTime, Utc};
+use dal::{
+    self,
+    auth::{
+        AuthToken,
+        CreateAuthTokenError,
+        GetAuthTokenError,
+        NewAuthToken,
+        RevokeAuthTokenError,
+    },
+    DalConnection,
+};
+use diesel;
+use handlers::{
+    oauth::{random_string, sha256},
+    user::{split_scopes, VerifyTokenError},
+};
+
+/// Lets secret scanners recognise leaked tokens
+pub const PERSONAL_ACCESS_TOKEN_PREFIX: &str = "lapat_";
+
+pub const PERSONAL_TOKEN_TYPE: &str = "personal";
+
+pub struct PersonalAccessToken {
+    pub id: i64,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub date_created: DateTime<Utc>,
+    pub date_expired: Option<DateTime<Utc>>,
+}
+
+impl PersonalAccessToken {
+    fn from_auth_token(auth_token: AuthToken) -> Self {
+        Self {
+            id: auth_token.id,
+            name: auth_token.name.unwrap_or_default(),
+            scopes: split_scopes(auth_token.scope.as_deref()),
+            date_created: auth_token.date_created,
+            date_expired: auth_token.date_expired,
+        }
+    }
+}
+
+#[must_use]
+pub fn is_personal_access_token(token_string: &str) -> bool {
+    token_string.starts_with(PERSONAL_ACCESS_TOKEN_PREFIX)
+}
+
+pub enum CreatePersonalTokenError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Creates a personal access token for the user, returning it along with the
+/// secret. The secret is only stored hashed so can't be retrieved again.
+///
+/// # Errors
+///
+/// If the insert fails
+pub fn create_personal_token(
+    connection: &DalConnection,
+    user_id: i64,
+    name: &str,
+    scopes: &[String],
+    date_expired: Option<DateTime<Utc>>,
+) -> Result<(PersonalAccessToken, String), CreatePersonalTokenError> {
+    let secret = format!("{PERSONAL_ACCESS_TOKEN_PREFIX}{}", random_string());
+    let scope = scopes.join(" ");
+    let new_token = NewAuthToken {
+        user_id: Some(user_id),
+        token: sha256(&secret),
+        date_created: Utc::now(),
+        date_expired,
+        token_type: PERSONAL_TOKEN_TYPE,
+        client_id: None,
+        scope: Some(&scope),
+        name: Some(name),
+    };
+    match dal::auth::create_token(connection, &new_token) {
+        Ok(auth_token) => {
+            Ok((PersonalAccessToken::from_auth_token(auth_token), secret))
+        }
+        Err(CreateAuthTokenError::OtherDbError(db_error)) => {
+            Err(CreatePersonalTokenError::OtherDbError(db_error))
+        }
+    }
+}
+
+pub enum ListPersonalTokensError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Lists the user's personal access tokens that haven't been revoked
+///
+/// # Errors
+///
+/// If the query fails
+pub fn list_personal_tokens(
+    connection: &DalConnection,
+    user_id: i64,
+) -> Result<Vec<PersonalAccessToken>, ListPersonalTokensError> {
+    match dal::auth::get_auth_tokens_for_user(
+        connection,
+        user_id,
+        PERSONAL_TOKEN_TYPE,
+    ) {
+        Ok(auth_tokens) => Ok(auth_tokens
+            .into_iter()
+            .map(PersonalAccessToken::from_auth_token)
+            .collect()),
+        Err(GetAuthTokenError::AuthTokenNotFound) => Ok(Vec::new()),
+        Err(GetAuthTokenError::OtherDbError(db_error)) => {
+            Err(ListPersonalTokensError::OtherDbError(db_error))
+        }
+    }
+}
+
+pub enum RevokePersonalTokenError {
+    TokenNotFound,
+    OtherDbError(diesel::result::Error),
+}
+
+/// Revokes one of the user's personal access tokens
+///
+/// # Errors
+///
+/// `TokenNotFound` if the user has no such unrevoked token, or if a query fails
+pub fn revoke_personal_token(
+    connection: &DalConnection,
+    user_id: i64,
+    token_id: i64,
+) -> Result<(), RevokePersonalTokenError> {
+    let auth_token = match dal::auth::get_auth_token(connection, token_id) {
+        Ok(auth_token) => auth_token,
+        Err(GetAuthTokenError::AuthTokenNotFound) => {
+            return Err(RevokePersonalTokenError::TokenNotFound);
+        }
+        Err(GetAuthTokenError::OtherDbError(db_error)) => {
+            return Err(RevokePersonalTokenError::OtherDbError(db_error));
+        }
+    };
+    if auth_token.user_id != Some(user_id)
+        || auth_token.token_type != PERSONAL_TOKEN_TYPE
+    {
+        return Err(RevokePersonalTokenError::TokenNotFound);
+    }
+
+    match dal::auth::revoke_auth_token(connection, auth_token.id) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(RevokePersonalTokenError::TokenNotFound),
+        Err(RevokeAuthTokenError::OtherDbError(db_error)) => {
+            Err(RevokePersonalTokenError::OtherDbError(db_error))
+        }
+    }
+}
+
+/// Verifies a personal access token against the database, returning the
+/// stored token
+///
+/// # Errors
+///
+/// If the token is unknown, not a personal access token, revoked or expired, or
+/// if the query fails
+pub fn verify_personal_token(
+    connection: &DalConnection,
+    token_string: &str,
+) -> Result<AuthToken, VerifyTokenError> {
+    let token_hash = sha256(token_string);
+    let auth_token =
+        match dal::auth::get_auth_token_by_token(connection, &token_hash) {
+            Ok(auth_token) => auth_token,
+            Err(error) => {
+                return Err(VerifyTokenError::GetAuthTokenError(error));
+            }
+        };
+
+    if auth_token.token_type != PERSONAL_TOKEN_TYPE {
+        return Err(VerifyTokenError::TokenMismatch);
+    }
+    if auth_token.date_revoked.is_some() {
+        return Err(VerifyTokenError::Revoked);
+    }
+    if auth_token
+        .date_expired
+        .is_some_and(|date_expired| date_expired < Utc::now())
+    {
+        return Err(VerifyTokenError::Expired);
+    }
+    Ok(auth_token)
+}
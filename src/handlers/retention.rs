@@ -0,0 +1,303 @@
+use chrono::{DateTime, Duration, Utc};
+use config;
+use dal::{
+    self,
+    auth::{AuthLog, DeleteAuthLogError, DeleteAuthTokensError, GetAuthLogError},
+    DalConnection,
+    DalPool,
+    LockError,
+};
+use diesel::{self, Connection};
+use handlers::hash_chain::to_hex;
+use serde_json::json;
+use std::{
+    convert::TryFrom,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    thread,
+    time,
+};
+
+/// Pause between batches, so other queries get a turn
+const BATCH_PAUSE_MILLIS: u64 = 100;
+
+/// Advisory lock held by the instance deleting a batch
+const CLEANUP_LOCK_KEY: i64 = 0x6c61_636c_0001;
+
+fn settings() -> &'static config::Retention { &config::get().retention }
+
+#[derive(Debug)]
+pub enum CleanupError {
+    ArchiveError(io::Error),
+    OtherDbError(diesel::result::Error),
+}
+
+#[derive(Default)]
+pub struct CleanupReport {
+    pub tokens_deleted: usize,
+    pub auth_logs_deleted: usize,
+}
+
+fn pause() {
+    thread::sleep(time::Duration::from_millis(BATCH_PAUSE_MILLIS));
+}
+
+/// Takes the cleanup lock for the current transaction, returning false if
+/// another instance is deleting a batch
+fn try_lock_batch(
+    connection: &DalConnection,
+) -> Result<bool, diesel::result::Error> {
+    match dal::try_lock_for_transaction(connection, CLEANUP_LOCK_KEY) {
+        Ok(true) => Ok(true),
+        Ok(false) => {
+            info!("Another instance is cleaning up, so skipping the batch");
+            Ok(false)
+        }
+        Err(LockError::OtherDbError(db_error)) => Err(db_error),
+    }
+}
+
+/// Deletes tokens that expired or were revoked more than the grace window
+/// ago, a batch per transaction. Stops at a batch another instance is
+/// deleting.
+fn delete_expired_tokens(
+    connection: &DalConnection,
+) -> Result<usize, CleanupError> {
+    let cutoff = Utc::now() - Duration::days(settings().token_grace_days);
+    let mut deleted = 0;
+    let mut after_id = 0;
+    loop {
+        let result =
+            connection
+                .pg_connection
+                .transaction::<_, diesel::result::Error, _>(|| {
+                    if !try_lock_batch(connection)? {
+                        return Ok(Vec::new());
+                    }
+                    match dal::auth::delete_expired_auth_tokens(
+                        connection,
+                        cutoff,
+                        after_id,
+                        settings().batch_size,
+                    ) {
+                        Ok(ids) => Ok(ids),
+                        Err(DeleteAuthTokensError::OtherDbError(db_error)) => {
+                            Err(db_error)
+                        }
+                    }
+                });
+        let ids = match result {
+            Ok(ids) => ids,
+            Err(db_error) => return Err(CleanupError::OtherDbError(db_error)),
+        };
+        deleted += ids.len();
+        match ids.last() {
+            Some(last_id) if is_full_batch(ids.len()) => {
+                after_id = *last_id;
+                pause();
+            }
+            _ => return Ok(deleted),
+        }
+    }
+}
+
+/// Appends entries to the day's archive file as JSON lines, including their
+/// hashes so the archived chain can still be verified
+fn archive_auth_logs(dir: &Path, logs: &[AuthLog]) -> io::Result<()> {
+    let path =
+        dir.join(format!("auth_log-{}.ndjson", Utc::now().format("%Y-%m-%d")));
+    let mut body = String::new();
+    for log in logs {
+        body.push_str(
+            &json!({
+                "id": log.id,
+                "email": log.email,
+                "success": log.success,
+                "ip_address": log.ip_address,
+                "user_agent": log.user_agent,
+                "date_created": log.date_created,
+                "flagged": log.flagged,
+                "flag_reasons": log.flag_reasons,
+                "previous_hash": log.previous_hash.as_deref().map(to_hex),
+                "row_hash": log.row_hash.as_deref().map(to_hex),
+            })
+            .to_string(),
+        );
+        body.push('\n');
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(body.as_bytes())?;
+    file.sync_all()
+}
+
+/// Deletes one batch of auth log entries from before `cutoff`, returning how
+/// many were deleted.
+///
+/// Only the oldest entries are ever deleted, so the rest of the hash chain
+/// stays intact. Entries are archived before the deletion is committed.
+fn delete_auth_log_batch(
+    connection: &DalConnection,
+    cutoff: DateTime<Utc>,
+) -> Result<usize, CleanupError> {
+    let mut archive_error = None;
+    let result = connection.pg_connection.transaction(|| {
+        if !try_lock_batch(connection)? {
+            return Ok(0);
+        }
+        let oldest = match dal::auth::get_oldest_auth_log_dates(
+            connection,
+            settings().batch_size,
+        ) {
+            Ok(oldest) => oldest,
+            Err(GetAuthLogError::OtherDbError(db_error)) => {
+                return Err(db_error);
+            }
+        };
+        // Entries aren't quite in date order, so stop at the first one to
+        // keep rather than skipping over it
+        let Some((last_id, _)) = oldest
+            .iter()
+            .take_while(|(_, date_created)| *date_created < cutoff)
+            .last()
+        else {
+            return Ok(0);
+        };
+
+        let logs =
+            match dal::auth::delete_auth_logs_through(connection, *last_id) {
+                Ok(logs) => logs,
+                Err(DeleteAuthLogError::OtherDbError(db_error)) => {
+                    return Err(db_error);
+                }
+            };
+        if let Some(dir) = &settings().auth_log_archive_dir {
+            if let Err(error) = archive_auth_logs(dir, &logs) {
+                archive_error = Some(error);
+                return Err(diesel::result::Error::RollbackTransaction);
+            }
+        }
+        Ok(logs.len())
+    });
+
+    match (result, archive_error) {
+        (_, Some(error)) => Err(CleanupError::ArchiveError(error)),
+        (Ok(deleted), None) => Ok(deleted),
+        (Err(db_error), None) => Err(CleanupError::OtherDbError(db_error)),
+    }
+}
+
+/// Whether a batch was full, so there may be more to delete
+fn is_full_batch(batch_size: usize) -> bool {
+    usize::try_from(settings().batch_size) == Ok(batch_size)
+}
+
+fn delete_old_auth_logs(
+    connection: &DalConnection,
+) -> Result<usize, CleanupError> {
+    let cutoff = Utc::now() - Duration::days(settings().auth_log_days);
+    let mut deleted = 0;
+    loop {
+        let batch = delete_auth_log_batch(connection, cutoff)?;
+        deleted += batch;
+        if !is_full_batch(batch) {
+            return Ok(deleted);
+        }
+        pause();
+    }
+}
+
+/// Deletes expired tokens and old auth log entries, in batches so the tables
+/// are never locked for long
+///
+/// # Errors
+///
+/// `ArchiveError` if old auth log entries can't be archived, or if a query
+/// fails
+pub fn cleanup(
+    connection: &DalConnection,
+) -> Result<CleanupReport, CleanupError> {
+    Ok(CleanupReport {
+        tokens_deleted: delete_expired_tokens(connection)?,
+        auth_logs_deleted: delete_old_auth_logs(connection)?,
+    })
+}
+
+/// Runs the cleanup on a background thread every `CLEANUP_INTERVAL_MINUTES`,
+/// unless that's 0
+pub fn start_background_cleanup(pool: DalPool) {
+    if settings().interval_minutes == 0 {
+        return;
+    }
+    let interval = time::Duration::from_secs(settings().interval_minutes * 60);
+    thread::spawn(move || loop {
+        match pool.get() {
+            Ok(connection) => match cleanup(&DalConnection::new(connection)) {
+                Ok(report) => info!(
+                    "Cleanup deleted {} tokens and {} auth log entries",
+                    report.tokens_deleted, report.auth_logs_deleted
+                ),
+                Err(error) => error!("Cleanup failed: {error:?}"),
+            },
+            Err(error) => error!("Cleanup couldn't connect: {error}"),
+        }
+        thread::sleep(interval);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dal::auth::NewAuthToken;
+    use test_support;
+
+    #[test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    fn skips_batches_while_another_instance_cleans_up() {
+        let connection = test_support::connection();
+        let user = test_support::create_user(&connection, &[]);
+        let date_created = Utc::now() - Duration::days(365);
+        let Ok(token) = dal::auth::create_token(
+            &connection,
+            &NewAuthToken {
+                user_id: Some(user.id),
+                token: vec![0; 16],
+                date_created,
+                date_expired: Some(date_created + Duration::hours(1)),
+                token_type: "authentication",
+                client_id: None,
+                scope: None,
+                name: None,
+            },
+        ) else {
+            panic!("The expired token should be created");
+        };
+
+        // Outside the test transaction, so the lock is released on commit
+        let pool = dal::create_pool(
+            config::get().database_url(),
+            1,
+            time::Duration::from_secs(5),
+        );
+        let other_instance = DalConnection::new(pool.get().unwrap());
+        let report = other_instance
+            .pg_connection
+            .transaction::<_, diesel::result::Error, _>(|| {
+                assert!(matches!(
+                    dal::try_lock_for_transaction(
+                        &other_instance,
+                        CLEANUP_LOCK_KEY
+                    ),
+                    Ok(true)
+                ));
+                Ok(cleanup(&connection).unwrap())
+            })
+            .unwrap();
+        assert_eq!(report.tokens_deleted, 0);
+        assert_eq!(report.auth_logs_deleted, 0);
+        assert!(dal::auth::get_auth_token(&connection, token.id).is_ok());
+
+        assert_eq!(cleanup(&connection).unwrap().tokens_deleted, 1);
+        assert!(dal::auth::get_auth_token(&connection, token.id).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use chrono::{DateTime, Duration, Utc};
+use config;
+use dal::{
+    self,
+    auth::{AuthLog, GetAuthLogError},
+    DalConnection,
+};
+use ipnet::IpNet;
+use std::{convert::TryFrom, net::IpAddr};
+
+/// How far back logins are compared against
+const HISTORY_DAYS: i64 = 90;
+const HISTORY_LIMIT: i64 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoginFlag {
+    NewNetwork,
+    NewUserAgent,
+    FailureBurst,
+}
+
+impl LoginFlag {
+    /// Identifies the flag in the auth log
+    #[must_use]
+    pub const fn code(self) -> &'static str {
+        match self {
+            Self::NewNetwork => "new_network",
+            Self::NewUserAgent => "new_user_agent",
+            Self::FailureBurst => "failure_burst",
+        }
+    }
+
+    #[must_use]
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::NewNetwork => {
+                "it came from a network you haven't signed in from before"
+            }
+            Self::NewUserAgent => {
+                "it used a browser or device you haven't used before"
+            }
+            Self::FailureBurst => {
+                "it followed several failed attempts to sign in"
+            }
+        }
+    }
+}
+
+/// The network an address belongs to, at the configured prefix lengths
+fn network(
+    ip_address: &str,
+    settings: &config::SuspiciousLogin,
+) -> Option<IpNet> {
+    let address: IpAddr = ip_address.parse().ok()?;
+    let prefix = match address {
+        IpAddr::V4(_) => settings.ipv4_prefix,
+        IpAddr::V6(_) => settings.ipv6_prefix,
+    };
+    IpNet::new(address, prefix).ok().map(|network| network.trunc())
+}
+
+/// Reduces a user agent to the browser and operating system, so updates
+/// don't count as a new device
+#[must_use]
+pub fn user_agent_family(user_agent: &str) -> String {
+    // Checked in order, as user agents also name the browsers they're
+    // compatible with
+    const BROWSERS: &[(&str, &str)] = &[
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ];
+    const SYSTEMS: &[(&str, &str)] = &[
+        ("Windows", "Windows"),
+        ("iPhone", "iOS"),
+        ("iPad", "iOS"),
+        ("Android", "Android"),
+        ("CrOS", "ChromeOS"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ];
+
+    let browser = BROWSERS
+        .iter()
+        .find(|(token, _)| user_agent.contains(token))
+        .map_or_else(
+            || user_agent.split(['/', ' ']).next().unwrap_or(""),
+            |(_, browser)| browser,
+        );
+    SYSTEMS
+        .iter()
+        .find(|(token, _)| user_agent.contains(token))
+        .map_or_else(
+            || browser.to_owned(),
+            |(_, system)| format!("{browser} on {system}"),
+        )
+}
+
+/// Flags a login from a network or user agent none of the user's earlier
+/// successful logins used. With no earlier logins there's nothing to compare
+/// with, so nothing is flagged.
+fn history_flags(
+    successes: &[AuthLog],
+    ip_address: &str,
+    user_agent: &str,
+    settings: &config::SuspiciousLogin,
+) -> Vec<LoginFlag> {
+    let mut flags = Vec::new();
+    if successes.is_empty() {
+        return flags;
+    }
+    if let Some(login_network) = network(ip_address, settings) {
+        if !successes.iter().any(|log| {
+            network(&log.ip_address, settings) == Some(login_network)
+        }) {
+            flags.push(LoginFlag::NewNetwork);
+        }
+    }
+    let family = user_agent_family(user_agent);
+    if !successes
+        .iter()
+        .any(|log| user_agent_family(&log.user_agent) == family)
+    {
+        flags.push(LoginFlag::NewUserAgent);
+    }
+    flags
+}
+
+/// When failures start counting towards a burst: the start of the window
+/// before `date`, or the latest of `successes` if that's more recent, so only
+/// failures since the last successful login count
+fn burst_start(
+    successes: &[AuthLog],
+    date: DateTime<Utc>,
+    window: Duration,
+) -> DateTime<Utc> {
+    let window_start = date - window;
+    successes
+        .first()
+        .map_or(window_start, |log| log.date_created.max(window_start))
+}
+
+/// Compares a successful login with the user's recent ones, returning the
+/// reasons it looks suspicious.
+///
+/// A user's first login is never flagged for its network or user agent, as
+/// there's nothing to compare it with.
+///
+/// # Errors
+///
+/// If reading the user's recent logins fails
+pub fn check_login(
+    connection: &DalConnection,
+    email: &str,
+    ip_address: &str,
+    user_agent: &str,
+    date: DateTime<Utc>,
+) -> Result<Vec<LoginFlag>, GetAuthLogError> {
+    let config = config::get();
+    if !config.features.suspicious_login_detection {
+        return Ok(Vec::new());
+    }
+    // Successes are fetched separately from failures, so a flood of failed
+    // attempts can't push them out of the history
+    let successes = dal::auth::get_auth_logs_for_email(
+        connection,
+        email,
+        true,
+        date - Duration::days(HISTORY_DAYS),
+        HISTORY_LIMIT,
+    )?;
+
+    let mut flags = history_flags(
+        &successes,
+        ip_address,
+        user_agent,
+        &config.suspicious_login,
+    );
+    let failures_since = burst_start(
+        &successes,
+        date,
+        Duration::minutes(config.throttling.login_failure_window_minutes),
+    );
+    let burst = config.throttling.login_failure_burst;
+    let recent_failures = dal::auth::get_auth_logs_for_email(
+        connection,
+        email,
+        false,
+        failures_since,
+        i64::try_from(burst).unwrap_or(i64::MAX),
+    )?;
+    if recent_failures.len() >= burst {
+        flags.push(LoginFlag::FailureBurst);
+    }
+
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHROME_WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) \
+        AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 \
+        Safari/537.36";
+    const FIREFOX_LINUX: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:121.0) \
+        Gecko/20100101 Firefox/121.0";
+
+    fn login(
+        ip_address: &str,
+        user_agent: &str,
+        date: DateTime<Utc>,
+    ) -> AuthLog {
+        AuthLog {
+            id: 1,
+            email: "user@example.com".to_owned(),
+            success: true,
+            ip_address: ip_address.to_owned(),
+            user_agent: user_agent.to_owned(),
+            date_created: date,
+            flagged: false,
+            flag_reasons: None,
+            previous_hash: None,
+            row_hash: None,
+        }
+    }
+
+    #[test]
+    fn reduces_user_agents_to_their_family() {
+        for (user_agent, family) in [
+            (CHROME_WINDOWS, "Chrome on Windows"),
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) \
+                 AppleWebKit/537.36 (KHTML, like Gecko) Chrome/121.0.0.0 \
+                 Safari/537.36",
+                "Chrome on Windows",
+            ),
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) \
+                 AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 \
+                 Safari/537.36 Edg/120.0.0.0",
+                "Edge on Windows",
+            ),
+            (
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) \
+                 AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 \
+                 Safari/537.36 OPR/106.0.0.0",
+                "Opera on macOS",
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 14; Pixel 8) \
+                 AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 \
+                 Mobile Safari/537.36",
+                "Chrome on Android",
+            ),
+            (
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) \
+                 AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 \
+                 Mobile/15E148 Safari/604.1",
+                "Safari on iOS",
+            ),
+            (FIREFOX_LINUX, "Firefox on Linux"),
+            ("curl/8.5.0", "curl"),
+            ("", ""),
+        ] {
+            assert_eq!(user_agent_family(user_agent), family, "{user_agent}");
+        }
+    }
+
+    #[test]
+    fn truncates_addresses_to_their_network() {
+        let settings = config::SuspiciousLogin::default();
+        let network = |ip_address| {
+            network(ip_address, &settings).map(|network| network.to_string())
+        };
+        assert_eq!(network("192.0.2.77").as_deref(), Some("192.0.2.0/24"));
+        assert_eq!(
+            network("2001:db8:1234:5678::1").as_deref(),
+            Some("2001:db8:1234::/48")
+        );
+        assert_eq!(network("unknown"), None);
+    }
+
+    #[test]
+    fn never_flags_a_first_login() {
+        let settings = config::SuspiciousLogin::default();
+        assert!(history_flags(&[], "198.51.100.1", FIREFOX_LINUX, &settings)
+            .is_empty());
+    }
+
+    #[test]
+    fn flags_new_networks_and_user_agents() {
+        let settings = config::SuspiciousLogin::default();
+        let history = [login("192.0.2.10", CHROME_WINDOWS, Utc::now())];
+        let flags = |ip_address, user_agent| {
+            history_flags(&history, ip_address, user_agent, &settings)
+        };
+        assert!(flags("192.0.2.200", CHROME_WINDOWS).is_empty());
+        assert_eq!(
+            flags("198.51.100.1", FIREFOX_LINUX),
+            [LoginFlag::NewNetwork, LoginFlag::NewUserAgent]
+        );
+        // An address that can't be parsed can't be compared
+        assert_eq!(flags("unknown", FIREFOX_LINUX), [LoginFlag::NewUserAgent]);
+    }
+
+    #[test]
+    fn counts_failures_since_the_last_login_within_the_window() {
+        let now = Utc::now();
+        let window = Duration::minutes(15);
+        assert_eq!(burst_start(&[], now, window), now - window);
+        let recent = now - Duration::minutes(5);
+        assert_eq!(
+            burst_start(&[login("192.0.2.10", "", recent)], now, window),
+            recent
+        );
+        let old = now - Duration::hours(1);
+        assert_eq!(
+            burst_start(&[login("192.0.2.10", "", old)], now, window),
+            now - window
+        );
+    }
+}
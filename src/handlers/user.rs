@@ -1,58 +1,159 @@
-extern crate easy_password;
-
-use self::easy_password::bcrypt::{hash_password, verify_password};
 use base64;
 use chrono::{prelude::*, Duration};
+use config;
 use dal::{
     self,
     auth::{
         AuthLog,
+        AuthToken,
         CreateAuthLogError,
         CreateAuthTokenError,
+        GetAuthLogError,
         GetAuthTokenError,
         NewAuthLog,
         NewAuthToken,
+        RevokeAuthTokenError,
+    },
+    oauth::{GetOAuthClientError, OAuthClient},
+    users::{
+        CreateUserError,
+        GetUserError,
+        NewUser,
+        UpdateUserError,
+        User,
     },
-    users::{CreateUserError, GetUserError, NewUser, User},
     DalConnection,
 };
 use diesel;
+use handlers::{
+    hash_chain::{self, Chain, LinkError},
+    metrics::{self, Counter},
+    notify::{self, Email, SuspiciousLogin},
+    password,
+    personal_token,
+    suspicious_login::{self, LoginFlag},
+};
 use jwt;
 use rand::Rng;
-use std::env;
+use telemetry;
 
 #[derive(Deserialize, Serialize)]
 pub struct AuthTokenClaims {
     pub token_id: i64,
-    pub user_id: i64,
-    pub email: String,
+    #[serde(default)]
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
     pub token: String,
     pub exp: usize,
 }
 
+/// Who a verified token was issued to. User tokens with `scopes` are
+/// restricted to those scopes, while login tokens have none.
+#[derive(Debug)]
+pub enum Principal {
+    User {
+        user_id: i64,
+        email: String,
+        scopes: Option<Vec<String>>,
+    },
+    Service {
+        client_id: String,
+        scopes: Vec<String>,
+    },
+}
+
+/// Creates a user with a hash of their password
+///
+/// # Errors
+///
+/// `EmailExists` if the email is already registered, or if a query fails
 pub fn create_user(
     connection: &DalConnection,
     email: &str,
     password: &str,
 ) -> Result<User, CreateUserError> {
+    let _span = telemetry::span("handlers::user::create_user");
     if dal::users::get_user_by_email(connection, email).is_ok() {
         return Err(CreateUserError::EmailExists);
     }
 
-    let hashed_password = hash_password(
-        password,
-        env::var("HMAC_HASH")
-            .expect("HMAC_HASH must be set")
-            .as_bytes(),
-        12,
-    )
-    .expect("Parameters should be valid");
+    let hashed_password = password::hash_password(password);
 
     let new_user = NewUser {
         email,
-        password: &hashed_password,
+        password: &hashed_password.hash,
+        pepper_id: hashed_password.pepper_id,
+    };
+    let result = dal::users::create_user(connection, &new_user);
+    if result.is_ok() {
+        metrics::increment(Counter::Signups, &[]);
+    }
+    result
+}
+
+/// Whether signups should hide which emails are already registered, by
+/// always being accepted and emailing the account holder instead
+#[must_use]
+pub fn signup_enumeration_protection() -> bool {
+    config::get().features.signup_enumeration_protection
+}
+
+pub enum RegisterUserError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Creates a user without revealing whether the email was already registered,
+/// returning the new user if there wasn't one already.
+///
+/// Either way the address is sent an email once the request's transaction
+/// commits, so the owner of an existing account finds out about the attempt
+/// and the responses take as long.
+///
+/// # Errors
+///
+/// If a query fails
+pub fn register_user(
+    connection: &DalConnection,
+    email: &str,
+    password: &str,
+) -> Result<Option<User>, RegisterUserError> {
+    let _span = telemetry::span("handlers::user::register_user");
+    let mut new_user = None;
+    let notification = match create_user(connection, email, password) {
+        Ok(user) => {
+            new_user = Some(user);
+            Email {
+                to: email,
+                subject: "Your account has been created",
+                body: "Welcome! You can now log in with this email address."
+                    .to_owned(),
+            }
+        }
+        Err(CreateUserError::EmailExists) => {
+            // Hash anyway so this takes as long as creating the user
+            let _ = password::hash_password(password);
+            Email {
+                to: email,
+                subject: "Sign up attempt for your account",
+                body: "Someone tried to create an account with this email \
+                       address, but you already have one. If it was you, \
+                       log in with your existing password. Otherwise you can \
+                       ignore this email."
+                    .to_owned(),
+            }
+        }
+        Err(CreateUserError::OtherDbError(db_error)) => {
+            return Err(RegisterUserError::OtherDbError(db_error));
+        }
     };
-    dal::users::create_user(connection, &new_user)
+
+    notify::queue_email(&notification);
+    Ok(new_user)
 }
 
 fn log_auth_attempt(
@@ -61,118 +162,346 @@ fn log_auth_attempt(
     ip_address: &str,
     user_agent: &str,
     success: bool,
+    flags: &[LoginFlag],
 ) -> Result<AuthLog, CreateAuthLogError> {
-    let auth_log = NewAuthLog {
+    let _span = telemetry::span("handlers::user::log_auth_attempt");
+    let flag_reasons = flags
+        .iter()
+        .map(|flag| flag.code())
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut auth_log = NewAuthLog {
         email,
         success,
         ip_address,
         user_agent,
         date_created: Utc::now(),
+        flagged: !flags.is_empty(),
+        flag_reasons: Some(flag_reasons.as_str())
+            .filter(|reasons| !reasons.is_empty()),
+        previous_hash: None,
+        row_hash: None,
     };
+    let link =
+        match hash_chain::next_link(connection, Chain::AuthLog, &auth_log) {
+            Ok(link) => link,
+            Err(LinkError::OtherDbError(db_error)) => {
+                return Err(CreateAuthLogError::OtherDbError(db_error));
+            }
+        };
+    auth_log.previous_hash = link.previous_hash.as_deref();
+    auth_log.row_hash = Some(&link.row_hash);
     dal::auth::create_auth_log(connection, &auth_log)
 }
 
+/// Warns a user about a suspicious login. Their login shouldn't fail if the
+/// warning can't be sent, so failures are only logged.
+fn notify_suspicious_login(
+    email: &str,
+    ip_address: &str,
+    user_agent: &str,
+    flags: &[LoginFlag],
+) {
+    let _span = telemetry::span("handlers::user::notify_suspicious_login");
+    let login = SuspiciousLogin {
+        email,
+        ip_address,
+        user_agent,
+        date: Utc::now(),
+        reasons: flags.iter().map(|flag| flag.description()).collect(),
+    };
+    if let Err(error) = notify::notifier().notify_suspicious_login(&login) {
+        error!("Error notifying {email} of a suspicious login: {error:?}");
+    }
+}
+
 pub enum CreateTokenError {
     UserNotFound,
     WrongPassword,
     OtherDbError(diesel::result::Error),
 }
 
-pub fn create_token(
+/// Looks up the user and checks their password, logging the attempt.
+///
+/// The attempt is logged last, after hashing and notifications, as logging it
+/// locks the auth log's hash chain until the transaction ends.
+///
+/// # Errors
+///
+/// `UserNotFound` if no user has the email, `WrongPassword` if the password
+/// doesn't match, or if a query fails
+pub fn authenticate_user(
     connection: &DalConnection,
     email: &str,
     password: &str,
     ip_address: &str,
     user_agent: &str,
-) -> Result<String, CreateTokenError> {
+) -> Result<User, CreateTokenError> {
+    let _span = telemetry::span("handlers::user::authenticate_user");
     let user = match dal::users::get_user_by_email(connection, email) {
         Ok(user) => user,
-        Err(error) => {
-            match log_auth_attempt(
-                connection, email, ip_address, user_agent, false,
+        Err(GetUserError::UserNotFound) => {
+            // Take as long as a wrong password would, so response times don't
+            // reveal which emails are registered
+            password::verify_dummy_password(password);
+            return match log_auth_attempt(
+                connection, email, ip_address, user_agent, false, &[],
             ) {
-                Ok(_) => (),
-                Err(log_error) => match log_error {
-                    CreateAuthLogError::OtherDbError(db_error) => {
-                        return Err(CreateTokenError::OtherDbError(db_error));
-                    }
-                },
-            }
-            match error {
-                GetUserError::UserNotFound => {
-                    return Err(CreateTokenError::UserNotFound);
-                }
-                GetUserError::OtherDbError(db_error) => {
-                    return Err(CreateTokenError::OtherDbError(db_error));
+                Ok(_) => Err(CreateTokenError::UserNotFound),
+                Err(CreateAuthLogError::OtherDbError(db_error)) => {
+                    Err(CreateTokenError::OtherDbError(db_error))
                 }
-            }
+            };
+        }
+        Err(GetUserError::OtherDbError(db_error)) => {
+            return Err(CreateTokenError::OtherDbError(db_error));
         }
     };
 
-    let password_valid = verify_password(
-        password,
-        &user.password,
-        env::var("HMAC_HASH")
-            .expect("HMAC_HASH must be set")
-            .as_bytes(),
-    )
-    .expect("Parameters should be valid");
-    match log_auth_attempt(
+    if !password::verify_password(password, &user.password, user.pepper_id) {
+        return match log_auth_attempt(
+            connection, email, ip_address, user_agent, false, &[],
+        ) {
+            Ok(_) => Err(CreateTokenError::WrongPassword),
+            Err(CreateAuthLogError::OtherDbError(db_error)) => {
+                Err(CreateTokenError::OtherDbError(db_error))
+            }
+        };
+    }
+
+    let flags = match suspicious_login::check_login(
         connection,
         email,
         ip_address,
         user_agent,
-        password_valid,
+        Utc::now(),
     ) {
-        Ok(_) => (),
-        Err(log_error) => match log_error {
-            CreateAuthLogError::OtherDbError(db_error) => {
+        Ok(flags) => flags,
+        Err(GetAuthLogError::OtherDbError(db_error)) => {
+            return Err(CreateTokenError::OtherDbError(db_error));
+        }
+    };
+    if !flags.is_empty() {
+        notify_suspicious_login(&user.email, ip_address, user_agent, &flags);
+    }
+
+    // Upgrade hashes using outdated settings while we have the password
+    let user = if password::needs_rehash(&user.password, user.pepper_id) {
+        let hashed_password = password::hash_password(password);
+        match dal::users::update_user_password(
+            connection,
+            user.id,
+            &hashed_password.hash,
+            hashed_password.pepper_id,
+        ) {
+            Ok(user) => user,
+            Err(UpdateUserError::UserNotFound) => {
+                return Err(CreateTokenError::UserNotFound);
+            }
+            Err(UpdateUserError::OtherDbError(db_error)) => {
                 return Err(CreateTokenError::OtherDbError(db_error));
             }
-        },
+        }
+    } else {
+        user
+    };
+
+    match log_auth_attempt(
+        connection, email, ip_address, user_agent, true, &flags,
+    ) {
+        Ok(_) => Ok(user),
+        Err(CreateAuthLogError::OtherDbError(db_error)) => {
+            Err(CreateTokenError::OtherDbError(db_error))
+        }
     }
+}
+
+fn jwt_secret() -> &'static [u8] {
+    config::resources().jwt_secret.as_bytes()
+}
+
+#[must_use]
+pub fn token_lifetime() -> Duration {
+    Duration::hours(config::get().tokens.lifetime_hours)
+}
+
+fn sign_token(
+    connection: &DalConnection,
+    new_token: &NewAuthToken<'_>,
+    sub: String,
+    email: Option<String>,
+    client_id: Option<String>,
+) -> Result<String, CreateAuthTokenError> {
+    let token = dal::auth::create_token(connection, new_token)?;
+    // Ignoring clippy rule here only as it was necessary to fit the jwt API
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_possible_truncation)]
+    let exp = new_token
+        .date_expired
+        .expect("Tokens wrapped in a JWT always expire")
+        .timestamp() as usize;
+    Ok(jwt::encode(
+        &jwt::Header::default(),
+        &AuthTokenClaims {
+            token_id: token.id,
+            sub,
+            user_id: token.user_id,
+            email,
+            client_id,
+            token: base64::encode(&new_token.token),
+            exp,
+        },
+        jwt_secret(),
+    )
+    .unwrap())
+}
 
+/// Stores a new token for the user and returns it wrapped in a signed JWT.
+///
+/// Tokens issued to an OAuth client record the client and granted scope.
+///
+/// # Errors
+///
+/// If the insert fails
+pub fn issue_token(
+    connection: &DalConnection,
+    user: &User,
+    client: Option<&OAuthClient>,
+    scope: Option<&str>,
+) -> Result<String, CreateAuthTokenError> {
+    let _span = telemetry::span("handlers::user::issue_token");
     let date_created = Utc::now();
-    let date_expired = Utc::now() + Duration::hours(1);
     let new_token = NewAuthToken {
-        user_id: user.id,
+        user_id: Some(user.id),
         token: rand::thread_rng().gen::<[u8; 16]>().to_vec(),
         date_created,
-        date_expired,
-        token_type: "authentication",
+        date_expired: Some(date_created + token_lifetime()),
+        token_type: if client.is_some() {
+            "access"
+        } else {
+            "authentication"
+        },
+        client_id: client.map(|client| client.id),
+        scope,
+        name: None,
     };
 
-    if password_valid {
-        match dal::auth::create_token(connection, &new_token) {
-            // Ignoring clippy rule here only as it was necessary to fit the jwt
-            // API
-            #[allow(clippy::cast_sign_loss)]
-            #[allow(clippy::cast_possible_truncation)]
-            Ok(token) => Ok(jwt::encode(
-                &jwt::Header::default(),
-                &AuthTokenClaims {
-                    token_id: token.id,
-                    user_id: token.user_id,
-                    email: email.to_string(),
-                    token: base64::encode(&new_token.token),
-                    exp: date_expired.timestamp() as usize,
-                },
-                env::var("JWT_SECRET")
-                    .expect("JWT_SECRET must be set")
-                    .as_bytes(),
-            )
-            .unwrap()),
-            Err(error) => match error {
-                CreateAuthTokenError::OtherDbError(db_error) => {
-                    Err(CreateTokenError::OtherDbError(db_error))
-                }
-            },
+    sign_token(
+        connection,
+        &new_token,
+        user.id.to_string(),
+        Some(user.email.clone()),
+        client.map(|client| client.client_id.clone()),
+    )
+}
+
+/// Stores a new token belonging to a service client rather than a user and
+/// returns it wrapped in a signed JWT
+///
+/// # Errors
+///
+/// If the insert fails
+pub fn issue_service_token(
+    connection: &DalConnection,
+    client: &OAuthClient,
+    scope: &str,
+) -> Result<String, CreateAuthTokenError> {
+    let _span = telemetry::span("handlers::user::issue_service_token");
+    let date_created = Utc::now();
+    let new_token = NewAuthToken {
+        user_id: None,
+        token: rand::thread_rng().gen::<[u8; 16]>().to_vec(),
+        date_created,
+        date_expired: Some(date_created + token_lifetime()),
+        token_type: "service",
+        client_id: Some(client.id),
+        scope: Some(scope),
+        name: None,
+    };
+
+    sign_token(
+        connection,
+        &new_token,
+        client.client_id.clone(),
+        None,
+        Some(client.client_id.clone()),
+    )
+}
+
+/// Logs a user in, returning a login token
+///
+/// # Errors
+///
+/// As `authenticate_user`, or if storing the token fails
+pub fn create_token(
+    connection: &DalConnection,
+    email: &str,
+    password: &str,
+    ip_address: &str,
+    user_agent: &str,
+) -> Result<String, CreateTokenError> {
+    let _span = telemetry::span("handlers::user::create_token");
+    let result =
+        authenticate_user(connection, email, password, ip_address, user_agent);
+    let login_result = match &result {
+        Ok(_) => "success",
+        Err(CreateTokenError::UserNotFound) => "user_not_found",
+        Err(CreateTokenError::WrongPassword) => "wrong_password",
+        Err(CreateTokenError::OtherDbError(_)) => "error",
+    };
+    metrics::increment(Counter::Logins, &[login_result]);
+    let user = result?;
+
+    match issue_token(connection, &user, None, None) {
+        Ok(token) => Ok(token),
+        Err(CreateAuthTokenError::OtherDbError(db_error)) => {
+            Err(CreateTokenError::OtherDbError(db_error))
+        }
+    }
+}
+
+pub enum EndSessionError {
+    OtherDbError(diesel::result::Error),
+}
+
+/// Revokes a login token, returning it if it was revoked. Other kinds of
+/// token, and tokens that are already invalid, are left alone.
+///
+/// # Errors
+///
+/// If a query fails
+pub fn end_session(
+    connection: &DalConnection,
+    token_string: &str,
+) -> Result<Option<AuthToken>, EndSessionError> {
+    let _span = telemetry::span("handlers::user::end_session");
+    let auth_token = match verify_token_record(connection, token_string) {
+        Ok((auth_token, _)) => auth_token,
+        Err(VerifyTokenError::GetAuthTokenError(
+            GetAuthTokenError::OtherDbError(db_error),
+        )) => {
+            return Err(EndSessionError::OtherDbError(db_error));
+        }
+        Err(_) => return Ok(None),
+    };
+    if auth_token.token_type != "authentication" {
+        return Ok(None);
+    }
+
+    match dal::auth::revoke_auth_token(connection, auth_token.id) {
+        Ok(true) => Ok(Some(auth_token)),
+        Ok(false) => Ok(None),
+        Err(RevokeAuthTokenError::OtherDbError(db_error)) => {
+            Err(EndSessionError::OtherDbError(db_error))
         }
-    } else {
-        Err(CreateTokenError::WrongPassword)
     }
 }
 
+/// Checks a JWT's signature and expiry and decodes its claims
+///
+/// # Errors
+///
+/// If the token isn't a valid, unexpired JWT signed with `JWT_SECRET`
 pub fn decode_jwt_token(
     token_string: &str,
 ) -> Result<jwt::TokenData<AuthTokenClaims>, jwt::errors::Error> {
@@ -181,9 +510,7 @@ pub fn decode_jwt_token(
     #[allow(clippy::cast_possible_truncation)]
     jwt::decode::<AuthTokenClaims>(
         token_string,
-        env::var("JWT_SECRET")
-            .expect("JWT_SECRET must be set")
-            .as_bytes(),
+        jwt_secret(),
         &jwt::Validation {
             leeway: 60,
             ..jwt::Validation::default()
@@ -195,14 +522,25 @@ pub fn decode_jwt_token(
 pub enum VerifyTokenError {
     TokenMismatch,
     UserMismatch,
+    ClientMismatch,
+    Revoked,
+    Expired,
     JwtError(jwt::errors::Error),
     GetAuthTokenError(GetAuthTokenError),
 }
 
-pub fn verify_token(
+/// Verifies a token against the database, returning the stored token along
+/// with the JWT claims
+///
+/// # Errors
+///
+/// If the JWT is invalid, doesn't match the stored token, or the token is
+/// revoked or expired, or if a query fails
+pub fn verify_token_record(
     connection: &DalConnection,
     token_string: &str,
-) -> Result<(i64, String), VerifyTokenError> {
+) -> Result<(AuthToken, AuthTokenClaims), VerifyTokenError> {
+    let _span = telemetry::span("handlers::user::verify_token_record");
     let jwt_token = match decode_jwt_token(token_string) {
         Ok(jwt_token) => jwt_token,
         Err(error) => {
@@ -220,14 +558,129 @@ pub fn verify_token(
         }
     };
 
+    if auth_token_from_db.date_revoked.is_some() {
+        return Err(VerifyTokenError::Revoked);
+    }
+
     let encoded_token = base64::encode(&auth_token_from_db.token);
     let user_ids_match = jwt_token.claims.user_id == auth_token_from_db.user_id;
     let tokens_match = jwt_token.claims.token == encoded_token;
     match (user_ids_match, tokens_match) {
-        (true, true) => {
-            Ok((auth_token_from_db.user_id, jwt_token.claims.email))
-        }
+        (true, true) => Ok((auth_token_from_db, jwt_token.claims)),
         (false, _) => Err(VerifyTokenError::UserMismatch),
         (_, false) => Err(VerifyTokenError::TokenMismatch),
     }
 }
+
+/// Splits a space separated scope string into its scopes
+pub fn split_scopes(scope: Option<&str>) -> Vec<String> {
+    scope
+        .unwrap_or_default()
+        .split(' ')
+        .filter(|scope| !scope.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+impl VerifyTokenError {
+    /// Why the token was rejected, as reported in metrics
+    fn outcome(&self) -> &'static str {
+        match self {
+            Self::TokenMismatch | Self::UserMismatch | Self::ClientMismatch => {
+                "mismatch"
+            }
+            Self::Revoked => "revoked",
+            Self::Expired => "expired",
+            Self::JwtError(error) => match error.kind() {
+                jwt::errors::ErrorKind::ExpiredSignature => "expired",
+                jwt::errors::ErrorKind::InvalidSignature => "invalid_signature",
+                _ => "invalid",
+            },
+            Self::GetAuthTokenError(GetAuthTokenError::AuthTokenNotFound) => {
+                "not_found"
+            }
+            Self::GetAuthTokenError(GetAuthTokenError::OtherDbError(_)) => {
+                "error"
+            }
+        }
+    }
+}
+
+/// Verifies a login JWT, OAuth access token or personal access token and
+/// returns who it belongs to
+///
+/// # Errors
+///
+/// As `verify_token_record` or `verify_personal_token`
+pub fn verify_token(
+    connection: &DalConnection,
+    token_string: &str,
+) -> Result<Principal, VerifyTokenError> {
+    let _span = telemetry::span("handlers::user::verify_token");
+    let result = verify_principal(connection, token_string);
+    let outcome = result.as_ref().map_or_else(VerifyTokenError::outcome, |_| {
+        "valid"
+    });
+    metrics::increment(Counter::TokenValidations, &[outcome]);
+    result
+}
+
+fn verify_principal(
+    connection: &DalConnection,
+    token_string: &str,
+) -> Result<Principal, VerifyTokenError> {
+    if personal_token::is_personal_access_token(token_string) {
+        let auth_token =
+            personal_token::verify_personal_token(connection, token_string)?;
+        let Some(user_id) = auth_token.user_id else {
+            return Err(VerifyTokenError::UserMismatch);
+        };
+        return match dal::users::get_user_by_id(connection, user_id) {
+            Ok(user) => Ok(Principal::User {
+                user_id,
+                email: user.email,
+                scopes: Some(split_scopes(auth_token.scope.as_deref())),
+            }),
+            Err(GetUserError::UserNotFound) => {
+                Err(VerifyTokenError::UserMismatch)
+            }
+            Err(GetUserError::OtherDbError(db_error)) => {
+                Err(VerifyTokenError::GetAuthTokenError(
+                    GetAuthTokenError::OtherDbError(db_error),
+                ))
+            }
+        };
+    }
+
+    let (auth_token, claims) = verify_token_record(connection, token_string)?;
+    if let Some(user_id) = auth_token.user_id {
+        return Ok(Principal::User {
+            user_id,
+            email: claims.email.unwrap_or_default(),
+            scopes: auth_token
+                .scope
+                .as_deref()
+                .map(|scope| split_scopes(Some(scope))),
+        });
+    }
+
+    let client = match auth_token
+        .client_id
+        .map(|client_id| dal::oauth::get_client_by_id(connection, client_id))
+    {
+        Some(Ok(client)) => client,
+        Some(Err(GetOAuthClientError::OtherDbError(db_error))) => {
+            return Err(VerifyTokenError::GetAuthTokenError(
+                GetAuthTokenError::OtherDbError(db_error),
+            ));
+        }
+        _ => return Err(VerifyTokenError::ClientMismatch),
+    };
+    if claims.client_id.as_ref() != Some(&client.client_id) {
+        return Err(VerifyTokenError::ClientMismatch);
+    }
+    Ok(Principal::Service {
+        client_id: client.client_id,
+        scopes: split_scopes(auth_token.scope.as_deref()),
+    })
+}
@@ -0,0 +1,66 @@
+use dal::DalPool;
+use handlers::health;
+use metrics;
+use rouille::{Request, Response};
+
+#[derive(Serialize)]
+struct HealthResponse {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct ReadinessChecks {
+    database: bool,
+    migrations: bool,
+    signing_keys: bool,
+}
+
+#[derive(Serialize)]
+struct ReadinessResponse {
+    ready: bool,
+    checks: ReadinessChecks,
+}
+
+#[derive(Serialize)]
+struct VersionResponse {
+    version: &'static str,
+    commit: &'static str,
+    profile: &'static str,
+    migration: &'static str,
+}
+
+/// Answers health checks and metrics scrapes, which are handled before a
+/// database connection or transaction is set up for the request. Returns
+/// `None` for other requests.
+#[must_use]
+pub fn routes(request: &Request, pool: &DalPool) -> Option<Response> {
+    if request.method() != "GET" {
+        return None;
+    }
+    match request.url().as_str() {
+        "/healthz" => Some(Response::json(&HealthResponse { status: "ok" })),
+        "/readyz" => Some(readyz(pool)),
+        "/version" => Some(Response::json(&VersionResponse {
+            version: health::VERSION,
+            commit: health::COMMIT,
+            profile: health::PROFILE,
+            migration: health::LATEST_MIGRATION,
+        })),
+        "/metrics" => Some(metrics::metrics()),
+        _ => None,
+    }
+}
+
+fn readyz(pool: &DalPool) -> Response {
+    let readiness = health::check_readiness(pool);
+    let ready = readiness.is_ready();
+    Response::json(&ReadinessResponse {
+        ready,
+        checks: ReadinessChecks {
+            database: readiness.database,
+            migrations: readiness.migrations,
+            signing_keys: readiness.signing_keys,
+        },
+    })
+    .with_status_code(if ready { 200 } else { 503 })
+}
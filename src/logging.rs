@@ -0,0 +1,167 @@
+use chrono::{SecondsFormat, Utc};
+use client;
+use config;
+use handlers::{hash_chain::to_hex, personal_token};
+use log::{self, Level, Log, Metadata, Record};
+use rand::Rng;
+use rouille::Request;
+use serde_json::{json, Map, Value};
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    panic,
+    time::Duration,
+};
+
+/// Longest `X-Request-Id` accepted from a client
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+thread_local! {
+    /// The ID of the request being handled on this thread, if any
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Writes log records to stdout as JSON lines
+struct JsonLogger;
+
+static LOGGER: JsonLogger = JsonLogger;
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.enabled(record.metadata()) {
+            write_line(
+                record.level(),
+                record.target(),
+                &record.args().to_string(),
+                Map::new(),
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sets up logging at `logging.level`. Panics are logged too, so they're tied
+/// to the request that caused them.
+///
+/// # Panics
+///
+/// If logging has already been set up
+pub fn init() {
+    let level = config::get().log_level();
+    log::set_logger(&LOGGER).expect("The logger should only be set up once");
+    log::set_max_level(level);
+    panic::set_hook(Box::new(|info| error!("{info}")));
+}
+
+/// Whether a word is a secret that mustn't be logged: a JWT, personal access
+/// token or password hash
+fn is_secret(word: &str) -> bool {
+    let word = word.trim_matches(|c: char| {
+        !c.is_ascii_alphanumeric() && !"$_-.".contains(c)
+    });
+    word.starts_with("eyJ")
+        || word.starts_with(personal_token::PERSONAL_ACCESS_TOKEN_PREFIX)
+        || word.starts_with("$argon2")
+        || ["$2a$", "$2b$", "$2y$"]
+            .iter()
+            .any(|prefix| word.starts_with(prefix))
+}
+
+/// Replaces secrets in a message, in case one ends up in an error message.
+/// Messages shouldn't include them in the first place.
+fn redact(message: &str) -> String {
+    message
+        .split_inclusive(char::is_whitespace)
+        .map(|word| {
+            if is_secret(word) {
+                let whitespace = &word[word.trim_end().len()..];
+                format!("[REDACTED]{whitespace}")
+            } else {
+                word.to_owned()
+            }
+        })
+        .collect()
+}
+
+fn write_line(
+    level: Level,
+    target: &str,
+    message: &str,
+    mut fields: Map<String, Value>,
+) {
+    fields.insert(
+        "timestamp".to_owned(),
+        json!(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+    );
+    fields.insert("level".to_owned(), json!(level.to_string()));
+    fields.insert("target".to_owned(), json!(target));
+    fields.insert("message".to_owned(), json!(redact(message)));
+    REQUEST_ID.with(|request_id| {
+        if let Some(request_id) = &*request_id.borrow() {
+            fields.insert("request_id".to_owned(), json!(request_id));
+        }
+    });
+    // Logging shouldn't take the service down if stdout goes away
+    let _ = writeln!(io::stdout().lock(), "{}", Value::Object(fields));
+}
+
+fn is_valid_request_id(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LENGTH
+        && request_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+}
+
+/// The request's `X-Request-Id`, or a new one if it doesn't have a usable
+/// one
+#[must_use]
+pub fn request_id(request: &Request) -> String {
+    match request.header("X-Request-Id") {
+        Some(request_id) if is_valid_request_id(request_id) => {
+            request_id.to_owned()
+        }
+        _ => to_hex(&rand::thread_rng().gen::<[u8; 16]>()),
+    }
+}
+
+/// Runs `f` with every line it logs tagged with `request_id`
+pub fn with_request_id<T>(request_id: &str, f: impl FnOnce() -> T) -> T {
+    let previous = REQUEST_ID
+        .with(|current| current.replace(Some(request_id.to_owned())));
+    let result = f();
+    REQUEST_ID.with(|current| current.replace(previous));
+    result
+}
+
+/// Logs a handled request. Only the path is logged, as query strings can
+/// hold secrets such as authorization codes.
+pub fn log_request(request: &Request, status: u16, duration: Duration) {
+    let level = if status >= 500 { Level::Error } else { Level::Info };
+    if level > log::max_level() {
+        return;
+    }
+    let mut fields = Map::new();
+    fields.insert("method".to_owned(), json!(request.method()));
+    fields.insert("path".to_owned(), json!(request.url()));
+    fields.insert("status".to_owned(), json!(status));
+    fields.insert(
+        "latency_ms".to_owned(),
+        json!(duration.as_secs_f64() * 1000.0),
+    );
+    fields.insert(
+        "client_ip".to_owned(),
+        json!(client::client_ip(request).to_string()),
+    );
+    write_line(
+        level,
+        module_path!(),
+        &format!("{} {} {status}", request.method(), request.url()),
+        fields,
+    );
+}
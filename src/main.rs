@@ -8,55 +8,298 @@ extern crate chrono;
 #[macro_use]
 extern crate diesel;
 extern crate dotenv;
+extern crate hmac;
+extern crate ipnet;
 extern crate jsonwebtoken as jwt;
+#[macro_use]
+extern crate log;
 extern crate rand;
 #[macro_use]
 extern crate rouille;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate sha1;
+extern crate sha2;
+extern crate url;
 extern crate validator;
 #[macro_use]
 extern crate validator_derive;
 
+pub mod client;
+pub mod config;
 pub mod dal;
 pub mod handlers;
+pub mod health;
+pub mod logging;
+pub mod metrics;
+pub mod oauth;
+pub mod secrets;
+pub mod telemetry;
+#[cfg(test)]
+mod test_support;
 pub mod v1;
 
-use dal::DalConnection;
-use diesel::{pg::PgConnection, result::Error, Connection};
+use config::{Config, ConfigError};
+use dal::{DalConnection, DalPool};
+use diesel::{result::Error, Connection};
 use dotenv::dotenv;
+use handlers::{
+    hash_chain::{to_hex, Chain, VerifyChainError},
+    metrics::Histogram,
+};
 use rouille::{Request, Response};
-use std::env;
+use std::{
+    env,
+    panic,
+    process,
+    time::{Duration, Instant},
+};
 
 fn main() {
     dotenv().ok();
-    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    env::var("HMAC_HASH").expect("HMAC_HASH must be set");
-    env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    if let Err(error) = secrets::init() {
+        eprintln!("{error}");
+        process::exit(2);
+    }
+    let args = config::parse_args(env::args().skip(1))
+        .unwrap_or_else(|error| exit_with_config_error(&error));
+    let config = config::load(&args)
+        .unwrap_or_else(|error| exit_with_config_error(&error));
+    if args.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+    logging::init();
+    telemetry::init();
 
-    rouille::start_server("localhost:8000", move |request| {
-        let connection = DalConnection::new(
-            PgConnection::establish(&db_url).expect("Error connecting to DB!"),
-        );
+    match args.command.as_deref() {
+        None | Some("serve") => serve(config),
+        Some("verify-chains") => process::exit(verify_chains(config)),
+        Some("cleanup") => process::exit(cleanup(config)),
+        Some(command) => {
+            eprintln!(
+                "Unknown command {command}, expected serve, verify-chains or \
+                 cleanup"
+            );
+            process::exit(2);
+        }
+    }
+}
+
+fn exit_with_config_error(error: &ConfigError) -> ! {
+    eprintln!("{error}");
+    process::exit(2);
+}
+
+fn create_pool(config: &Config) -> DalPool {
+    dal::create_pool(
+        config.database_url(),
+        config.database.max_connections,
+        Duration::from_secs(config.database.connection_timeout_secs),
+    )
+}
+
+fn serve(config: &Config) {
+    let pool = create_pool(config);
+    handlers::retention::start_background_cleanup(pool.clone());
+
+    let address = config.address();
+    let workers = Some(config.server.workers);
+    info!("Listening on {address} with {} workers", config.server.workers);
+    rouille::start_server_with_pool(address, workers, move |request| {
+        let request_id = logging::request_id(request);
+        let response = logging::with_request_id(&request_id, || {
+            let started = Instant::now();
+            let mut span = telemetry::request_span(
+                request.method(),
+                request.header("traceparent"),
+            );
+            // The panic has already been logged by the panic hook
+            let response = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                handle_request(request, &pool)
+            }))
+            .unwrap_or_else(|_| internal_error_response());
+            let status = response.status_code;
+            let duration = started.elapsed();
+            trace_request(&mut span, request, status, &request_id);
+            drop(span);
+            metrics::record_request(request, status, duration);
+            logging::log_request(request, status, duration);
+            response
+        });
+        response.with_unique_header("X-Request-Id", request_id)
+    });
+}
+
+/// The response for a request that panicked, as rouille would send
+fn internal_error_response() -> Response {
+    Response::html(
+        "<h1>Internal Server Error</h1>\
+         <p>An internal error has occurred on the server.</p>",
+    )
+    .with_status_code(500)
+}
+
+/// Names the request's span after its route, and describes the request and
+/// response as OpenTelemetry's HTTP conventions ask
+fn trace_request(
+    span: &mut telemetry::Span,
+    request: &Request,
+    status: u16,
+    request_id: &str,
+) {
+    let route = metrics::route_label(request);
+    span.set_name(&format!("{} {route}", metrics::method_label(request)));
+    span.set_string("http.request.method", request.method());
+    span.set_string("http.route", route);
+    span.set_string("url.path", &request.url());
+    span.set_string("client.address", &client::client_ip(request).to_string());
+    span.set_string("http.request.header.x-request-id", request_id);
+    span.set_int("http.response.status_code", i64::from(status));
+    if status >= 500 {
+        span.set_error(&format!("HTTP {status}"));
+    }
+}
 
+fn handle_request(request: &Request, pool: &DalPool) -> Response {
+    if let Some(response) = health::routes(request, pool) {
+        return response;
+    }
+
+    let is_v1 = request.remove_prefix("/v1").is_some();
+    if is_v1 && v1::cors::is_preflight(request) {
+        return v1::cors::preflight(request);
+    }
+
+    let connection = DalConnection::new(
+        handlers::metrics::time(Histogram::DbConnectionWait, &[], || {
+            let _span = telemetry::db_span("dal::pool::get");
+            pool.get()
+        })
+        .expect("Error connecting to DB!"),
+    );
+
+    let response = handlers::notify::send_after_commit(|| {
         connection
             .pg_connection
             .transaction::<Response, Error, _>(|| {
                 Ok(routes(request, &connection))
             })
-            .unwrap()
-    });
+    })
+    .unwrap();
+    if is_v1 {
+        v1::cors::with_cors_headers(request, response)
+    } else {
+        response
+    }
+}
+
+/// Verifies every hash chain, printing a report of each. Returns the exit
+/// code, which is 1 if any chain is broken.
+fn verify_chains(config: &Config) -> i32 {
+    let pool = create_pool(config);
+    let connection =
+        DalConnection::new(pool.get().expect("Error connecting to DB!"));
+
+    // Verify a snapshot, so rows written meanwhile don't affect the result
+    let reports = connection
+        .pg_connection
+        .build_transaction()
+        .read_only()
+        .repeatable_read()
+        .run::<_, Error, _>(|| {
+            Ok(Chain::ALL
+                .iter()
+                .map(|chain| {
+                    match handlers::hash_chain::verify_chain(
+                        &connection,
+                        *chain,
+                    ) {
+                        Ok(report) => report,
+                        Err(VerifyChainError::OtherDbError(err)) => {
+                            panic!("Unexpected database error: {}", err);
+                        }
+                    }
+                })
+                .collect::<Vec<_>>())
+        })
+        .unwrap();
+
+    let mut exit_code = 0;
+    for report in reports {
+        println!(
+            "{}: {} rows verified from ID {}, {} unchained",
+            report.chain.name(),
+            report.verified_rows,
+            report
+                .first_id
+                .map_or_else(|| "-".to_owned(), |id| id.to_string()),
+            report.unchained_rows,
+        );
+        if let (Some(last_id), Some(last_hash)) =
+            (report.last_id, &report.last_hash)
+        {
+            println!("  last ID {last_id}, hash {}", to_hex(last_hash));
+        }
+        if let Some(broken_link) = report.broken_link {
+            println!(
+                "  BROKEN at ID {}: {}",
+                broken_link.id,
+                broken_link.reason.code()
+            );
+            exit_code = 1;
+        }
+    }
+    exit_code
+}
+
+/// Runs the retention cleanup once, for running it on a schedule instead of
+/// in the server. Returns the exit code.
+fn cleanup(config: &Config) -> i32 {
+    let pool = create_pool(config);
+    let connection =
+        DalConnection::new(pool.get().expect("Error connecting to DB!"));
+
+    match handlers::retention::cleanup(&connection) {
+        Ok(report) => {
+            println!(
+                "Deleted {} tokens and {} auth log entries",
+                report.tokens_deleted, report.auth_logs_deleted
+            );
+            0
+        }
+        Err(error) => {
+            eprintln!("Cleanup failed: {error:?}");
+            1
+        }
+    }
 }
 
 fn routes(request: &Request, connection: &DalConnection) -> Response {
     router!(
         request,
-        (GET) (/) => {
+        (GET) ["/"] => {
             Response::empty_404()
         },
+        (GET) ["/.well-known/openid-configuration"] => {
+            oauth::oidc::openid_configuration()
+        },
+        (GET) ["/.well-known/jwks.json"] => {
+            oauth::oidc::jwks()
+        },
+        (GET) ["/userinfo"] => {
+            oauth::oidc::userinfo(request, connection)
+        },
+        (POST) ["/userinfo"] => {
+            oauth::oidc::userinfo(request, connection)
+        },
         _ => {
             if let Some(v1_request) = request.remove_prefix("/v1") {
                 v1::routes(&v1_request, connection)
+            } else if let Some(oauth_request) = request.remove_prefix("/oauth") {
+                oauth::routes(&oauth_request, connection)
             } else {
                 Response::empty_404()
             }
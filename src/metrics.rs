@@ -0,0 +1,136 @@
+use handlers::metrics::{self, Counter, Histogram};
+use rouille::{Request, Response};
+use std::time::Duration;
+
+/// Every route, as the method and path template. The routers don't report
+/// which route they matched, so this must be kept in step with them.
+const ROUTES: &[(&str, &str)] = &[
+    ("GET", "/healthz"),
+    ("GET", "/readyz"),
+    ("GET", "/version"),
+    ("GET", "/metrics"),
+    ("GET", "/.well-known/openid-configuration"),
+    ("GET", "/.well-known/jwks.json"),
+    ("GET", "/userinfo"),
+    ("POST", "/userinfo"),
+    ("GET", "/oauth/authorize"),
+    ("POST", "/oauth/authorize"),
+    ("POST", "/oauth/token"),
+    ("POST", "/oauth/device_authorization"),
+    ("GET", "/oauth/device"),
+    ("POST", "/oauth/device"),
+    ("POST", "/oauth/introspect"),
+    ("POST", "/oauth/revoke"),
+    ("POST", "/v1/admin/clients"),
+    ("GET", "/v1/admin/peppers"),
+    ("GET", "/v1/admin/auth_log"),
+    ("GET", "/v1/admin/auth_log/failures_by_ip"),
+    ("GET", "/v1/admin/auth_log/targeted_accounts"),
+    ("GET", "/v1/admin/audit_events"),
+    ("GET", "/v1/admin/hash_chains"),
+    ("GET", "/v1/auth/check"),
+    ("POST", "/v1/user"),
+    ("PATCH", "/v1/user/{id}"),
+    ("POST", "/v1/token"),
+    ("POST", "/v1/token/validate"),
+    ("POST", "/v1/token/logout"),
+    ("POST", "/v1/token/personal"),
+    ("GET", "/v1/token/personal"),
+    ("DELETE", "/v1/token/personal/{id}"),
+];
+
+/// The request's method, with any that aren't standard grouped as `other`
+#[must_use]
+pub fn method_label(request: &Request) -> &'static str {
+    match request.method() {
+        "GET" => "GET",
+        "HEAD" => "HEAD",
+        "POST" => "POST",
+        "PUT" => "PUT",
+        "PATCH" => "PATCH",
+        "DELETE" => "DELETE",
+        "OPTIONS" => "OPTIONS",
+        _ => "other",
+    }
+}
+
+fn matches_template(template: &str, path: &str) -> bool {
+    let mut segments = path.split('/');
+    template.split('/').all(|template_segment| {
+        segments.next().is_some_and(|segment| {
+            if template_segment == "{id}" {
+                segment.parse::<i64>().is_ok()
+            } else {
+                segment == template_segment
+            }
+        })
+    }) && segments.next().is_none()
+}
+
+/// The template of the route a request matched, such as `/v1/user/{id}`.
+///
+/// Anything that didn't match a route, including requests rejected before
+/// routing, is grouped under `unmatched`, so scanning random URLs can't
+/// create endless series.
+#[must_use]
+pub fn route_label(request: &Request) -> &'static str {
+    if request.method() == "OPTIONS" {
+        return "preflight";
+    }
+    let path = request.url();
+    ROUTES
+        .iter()
+        .find(|(method, template)| {
+            *method == request.method() && matches_template(template, &path)
+        })
+        .map_or("unmatched", |(_, template)| template)
+}
+
+pub fn record_request(request: &Request, status: u16, duration: Duration) {
+    let status = status.to_string();
+    let labels =
+        [method_label(request), route_label(request), status.as_str()];
+    metrics::increment(Counter::HttpRequests, &labels);
+    metrics::observe(Histogram::HttpRequestDuration, &labels, duration);
+}
+
+#[must_use]
+pub fn metrics() -> Response {
+    Response::text(metrics::render()).with_unique_header(
+        "Content-Type",
+        "text/plain; version=0.0.4; charset=utf-8",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(method: &str, url: &str) -> (&'static str, &'static str) {
+        let request = Request::fake_http(method, url, vec![], vec![]);
+        (method_label(&request), route_label(&request))
+    }
+
+    #[test]
+    fn labels_requests_with_their_route() {
+        assert_eq!(labels("POST", "/v1/token"), ("POST", "/v1/token"));
+        assert_eq!(
+            labels("PATCH", "/v1/user/42"),
+            ("PATCH", "/v1/user/{id}")
+        );
+        assert_eq!(
+            labels("GET", "/v1/admin/auth_log?limit=5"),
+            ("GET", "/v1/admin/auth_log")
+        );
+        assert_eq!(labels("OPTIONS", "/v1/token"), ("OPTIONS", "preflight"));
+    }
+
+    #[test]
+    fn groups_unmatched_requests() {
+        assert_eq!(labels("GET", "/v1/admin/1234"), ("GET", "unmatched"));
+        assert_eq!(labels("GET", "/v1/user/42"), ("GET", "unmatched"));
+        assert_eq!(labels("PATCH", "/v1/user/abc"), ("PATCH", "unmatched"));
+        assert_eq!(labels("POST", "/v1/token/"), ("POST", "unmatched"));
+        assert_eq!(labels("BREW", "/v1/token"), ("other", "unmatched"));
+    }
+}
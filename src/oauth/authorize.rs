@@ -0,0 +1,304 @@
+use client;
+use dal::{
+    oauth::{CreateAuthorizationCodeError, GetOAuthClientError, OAuthClient},
+    DalConnection,
+};
+use handlers::{
+    self,
+    audit::{Actor, Event, EventType, Target},
+    user::CreateTokenError,
+};
+use oauth::query_params;
+use rouille::{input::post::raw_urlencoded_post_input, Request, Response};
+use serde_json::json;
+use std::{collections::HashMap, fmt::Write};
+use telemetry;
+use url::Url;
+use v1::audit::record_event;
+
+struct AuthorizeRequest {
+    client: OAuthClient,
+    redirect_uri: String,
+    scope: String,
+    state: Option<String>,
+    code_challenge: String,
+    nonce: Option<String>,
+}
+
+#[must_use]
+pub fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#x27;")
+}
+
+fn error_page(message: &str) -> Response {
+    let mut response = Response::html(format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <title>Authorization error</title></head>\n\
+         <body><h1>Authorization error</h1><p>{}</p></body></html>",
+        escape_html(message)
+    ));
+    response.status_code = 400;
+    response.with_no_cache()
+}
+
+/// Sends the user back to the client with `params` and the request's `state`
+/// added to the redirect URI's query string
+fn client_redirect(
+    redirect_uri: &str,
+    state: Option<&str>,
+    params: &[(&str, &str)],
+) -> Response {
+    let mut url = Url::parse(redirect_uri)
+        .expect("Redirect URIs should be validated on registration");
+    {
+        let mut query = url.query_pairs_mut();
+        for (name, value) in params {
+            query.append_pair(name, value);
+        }
+        if let Some(state) = state {
+            query.append_pair("state", state);
+        }
+    }
+    Response::redirect_303(url.to_string())
+}
+
+/// Validates the authorization request parameters. Problems with the client
+/// or redirect URI are shown to the user, anything else is reported back to
+/// the client.
+fn parse_request(
+    connection: &DalConnection,
+    params: &HashMap<String, String>,
+) -> Result<AuthorizeRequest, Response> {
+    let client = match params
+        .get("client_id")
+        .map(|client_id| handlers::oauth::get_client(connection, client_id))
+    {
+        Some(Ok(client)) => client,
+        Some(Err(GetOAuthClientError::OtherDbError(err))) => {
+            panic!("Unexpected database error: {}", err);
+        }
+        _ => return Err(error_page("Unknown client.")),
+    };
+    let redirect_uri = match params.get("redirect_uri") {
+        Some(uri) if client.redirect_uris.contains(uri) => uri.clone(),
+        _ => return Err(error_page("Invalid redirect URI.")),
+    };
+    let state = params.get("state").cloned();
+
+    if !handlers::oauth::allows_grant(
+        &client,
+        handlers::oauth::AUTHORIZATION_CODE_GRANT,
+    ) {
+        return Err(client_redirect(&redirect_uri, state.as_deref(), &[
+            ("error", "unauthorized_client"),
+        ]));
+    }
+    if params.get("response_type").map(String::as_str) != Some("code") {
+        return Err(client_redirect(&redirect_uri, state.as_deref(), &[
+            ("error", "unsupported_response_type"),
+            ("error_description", "Only the code response type is supported"),
+        ]));
+    }
+    let code_challenge = match (
+        params.get("code_challenge"),
+        params.get("code_challenge_method").map(String::as_str),
+    ) {
+        (Some(challenge), Some("S256")) if !challenge.is_empty() => {
+            challenge.clone()
+        }
+        _ => {
+            return Err(client_redirect(&redirect_uri, state.as_deref(), &[
+                ("error", "invalid_request"),
+                ("error_description", "PKCE with the S256 method is required"),
+            ]));
+        }
+    };
+
+    let Some(scope) = handlers::oauth::allowed_scope(
+        &client,
+        params.get("scope").map_or("", String::as_str),
+    ) else {
+        return Err(client_redirect(&redirect_uri, state.as_deref(), &[
+            ("error", "invalid_scope"),
+            ("error_description", "The client may not request this scope"),
+        ]));
+    };
+    if handlers::oidc::has_openid_scope(&scope)
+        && handlers::oidc::signing_key().is_none()
+    {
+        return Err(client_redirect(&redirect_uri, state.as_deref(), &[
+            ("error", "invalid_scope"),
+            ("error_description", "OpenID Connect is not enabled"),
+        ]));
+    }
+
+    Ok(AuthorizeRequest {
+        client,
+        redirect_uri,
+        scope,
+        state,
+        code_challenge,
+        nonce: params.get("nonce").cloned(),
+    })
+}
+
+fn login_page(
+    authorize_request: &AuthorizeRequest,
+    error: Option<&str>,
+) -> Response {
+    let hidden_fields: String = [
+        ("client_id", authorize_request.client.client_id.as_str()),
+        ("redirect_uri", &authorize_request.redirect_uri),
+        ("response_type", "code"),
+        ("scope", &authorize_request.scope),
+        ("state", authorize_request.state.as_deref().unwrap_or("")),
+        ("code_challenge", &authorize_request.code_challenge),
+        ("code_challenge_method", "S256"),
+        ("nonce", authorize_request.nonce.as_deref().unwrap_or("")),
+    ]
+    .iter()
+    .fold(String::new(), |mut fields, (name, value)| {
+        let _ = writeln!(
+            fields,
+            "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+            name,
+            escape_html(value)
+        );
+        fields
+    });
+    let client_name = escape_html(&authorize_request.client.name);
+    let scope = if authorize_request.scope.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<p>Requested access: {}</p>\n",
+            escape_html(&authorize_request.scope)
+        )
+    };
+    let error = error.map_or_else(String::new, |error| {
+        format!("<p><strong>{}</strong></p>\n", escape_html(error))
+    });
+
+    Response::html(format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <title>Sign in</title></head>\n<body>\n\
+         <h1>Sign in to continue to {client_name}</h1>\n\
+         <p>{client_name} is requesting access to your account.</p>\n\
+         {scope}{error}\
+         <form method=\"post\" action=\"/oauth/authorize\">\n\
+         {hidden_fields}\
+         <p><label>Email <input type=\"email\" name=\"email\" \
+         required></label></p>\n\
+         <p><label>Password <input type=\"password\" name=\"password\" \
+         required></label></p>\n\
+         <button type=\"submit\" name=\"consent\" value=\"allow\">\
+         Allow</button>\n\
+         <button type=\"submit\" name=\"consent\" value=\"deny\" \
+         formnovalidate>Deny</button>\n\
+         </form>\n</body></html>"
+    ))
+    .with_no_cache()
+    .with_unique_header("X-Frame-Options", "DENY")
+}
+
+pub fn authorize_page(
+    request: &Request,
+    connection: &DalConnection,
+) -> Response {
+    let _span = telemetry::span("oauth::authorize::authorize_page");
+    match parse_request(connection, &query_params(request)) {
+        Ok(authorize_request) => login_page(&authorize_request, None),
+        Err(response) => response,
+    }
+}
+
+/// Handles the login form, redirecting back to the client with a code if the
+/// user signs in and allows access
+///
+/// # Panics
+///
+/// On unexpected database errors
+pub fn authorize(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("oauth::authorize::authorize");
+    let params: HashMap<String, String> =
+        match raw_urlencoded_post_input(request) {
+            Ok(fields) => fields.into_iter().collect(),
+            Err(_) => return error_page("Body format error."),
+        };
+    let authorize_request = match parse_request(connection, &params) {
+        Ok(authorize_request) => authorize_request,
+        Err(response) => return response,
+    };
+
+    if params.get("consent").map(String::as_str) != Some("allow") {
+        return client_redirect(
+            &authorize_request.redirect_uri,
+            authorize_request.state.as_deref(),
+            &[
+                ("error", "access_denied"),
+                ("error_description", "The user denied the request"),
+            ],
+        );
+    }
+
+    let client_info = client::client_info(request);
+    let user = match handlers::user::authenticate_user(
+        connection,
+        params.get("email").map_or("", String::as_str),
+        params.get("password").map_or("", String::as_str),
+        &client_info.ip_address,
+        client_info.user_agent,
+    ) {
+        Ok(user) => user,
+        Err(CreateTokenError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+        Err(_) => {
+            let mut response = login_page(
+                &authorize_request,
+                Some("Incorrect email or password."),
+            );
+            response.status_code = 401;
+            return response;
+        }
+    };
+
+    match handlers::oauth::create_authorization_code(
+        connection,
+        &authorize_request.client,
+        &user,
+        &authorize_request.redirect_uri,
+        &authorize_request.scope,
+        &authorize_request.code_challenge,
+        authorize_request.nonce.as_deref(),
+    ) {
+        Ok(code) => {
+            record_event(
+                request,
+                connection,
+                &Event {
+                    event_type: EventType::AuthorizationGranted,
+                    actor: Actor::User(user.id),
+                    target: Target::Client(&authorize_request.client.client_id),
+                    details: json!({
+                        "scope": authorize_request.scope,
+                        "redirect_uri": authorize_request.redirect_uri,
+                    }),
+                },
+            );
+            client_redirect(
+                &authorize_request.redirect_uri,
+                authorize_request.state.as_deref(),
+                &[("code", &code)],
+            )
+        }
+        Err(CreateAuthorizationCodeError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use client;
+use dal::{auth::DeviceAuthorization, oauth::OAuthClient, DalConnection};
+use handlers::{
+    self,
+    audit::{Actor, Event, EventType, Target},
+    oauth::{
+        DecideDeviceAuthorizationError,
+        DeviceAuthorizationError,
+        GetPendingDeviceAuthorizationError,
+    },
+    user::CreateTokenError,
+};
+use oauth::{
+    authenticate_client,
+    authorize::escape_html,
+    error_response,
+    form_params,
+    models::DeviceAuthorizationResponse,
+    query_params,
+};
+use rouille::{input::post::raw_urlencoded_post_input, Request, Response};
+use serde_json::json;
+use std::collections::HashMap;
+use telemetry;
+use url::Url;
+use v1::audit::record_event;
+
+/// Starts the device flow for a client that can't handle browser redirects
+///
+/// # Panics
+///
+/// If `OIDC_ISSUER` isn't a URL, or on unexpected database errors
+pub fn device_authorization(
+    request: &Request,
+    connection: &DalConnection,
+) -> Response {
+    let _span = telemetry::span("oauth::device::device_authorization");
+    let params = match form_params(request) {
+        Ok(params) => params,
+        Err(response) => return response,
+    };
+    let client = match authenticate_client(request, connection, &params) {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    match handlers::oauth::create_device_authorization(
+        connection,
+        &client,
+        params.get("scope").map(String::as_str),
+    ) {
+        Ok(grant) => {
+            let verification_uri =
+                format!("{}/oauth/device", handlers::oidc::issuer());
+            let mut verification_uri_complete = Url::parse(&verification_uri)
+                .expect("OIDC_ISSUER should be a valid URL");
+            verification_uri_complete
+                .query_pairs_mut()
+                .append_pair("user_code", &grant.user_code);
+            Response::json(&DeviceAuthorizationResponse {
+                device_code: grant.device_code,
+                user_code: grant.user_code,
+                verification_uri,
+                verification_uri_complete: verification_uri_complete
+                    .to_string(),
+                expires_in: grant.expires_in,
+                interval: grant.interval,
+            })
+            .with_no_cache()
+        }
+        Err(DeviceAuthorizationError::UnauthorizedClient) => {
+            error_response(400, "unauthorized_client", None)
+        }
+        Err(DeviceAuthorizationError::InvalidScope) => {
+            error_response(400, "invalid_scope", None)
+        }
+        Err(DeviceAuthorizationError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+fn html_page(title: &str, body: &str, status_code: u16) -> Response {
+    let mut response = Response::html(format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <title>{title}</title></head>\n<body>\n{body}</body></html>"
+    ));
+    response.status_code = status_code;
+    response
+        .with_no_cache()
+        .with_unique_header("X-Frame-Options", "DENY")
+}
+
+fn error_paragraph(error: Option<&str>) -> String {
+    error.map_or_else(String::new, |error| {
+        format!("<p><strong>{}</strong></p>\n", escape_html(error))
+    })
+}
+
+/// Asks the user for the code shown on their device
+fn code_entry_page(error: Option<&str>, status_code: u16) -> Response {
+    html_page(
+        "Connect a device",
+        &format!(
+            "<h1>Connect a device</h1>\n\
+             <p>Enter the code shown on your device.</p>\n\
+             {}\
+             <form method=\"get\" action=\"/oauth/device\">\n\
+             <p><label>Code <input type=\"text\" name=\"user_code\" \
+             autocomplete=\"off\" required></label></p>\n\
+             <button type=\"submit\">Continue</button>\n\
+             </form>\n",
+            error_paragraph(error)
+        ),
+        status_code,
+    )
+}
+
+/// Asks the user to sign in and approve the device's request
+fn confirm_page(
+    authorization: &DeviceAuthorization,
+    client: &OAuthClient,
+    error: Option<&str>,
+    status_code: u16,
+) -> Response {
+    let client_name = escape_html(&client.name);
+    let scope = if authorization.scope.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<p>Requested access: {}</p>\n",
+            escape_html(&authorization.scope)
+        )
+    };
+    html_page(
+        "Connect a device",
+        &format!(
+            "<h1>Connect {client_name}</h1>\n\
+             <p>Check that <strong>{user_code}</strong> is the code shown on \
+             your device. {client_name} is requesting access to your \
+             account.</p>\n\
+             {scope}{error}\
+             <form method=\"post\" action=\"/oauth/device\">\n\
+             <input type=\"hidden\" name=\"user_code\" value=\"{user_code}\">\n\
+             <p><label>Email <input type=\"email\" name=\"email\" \
+             required></label></p>\n\
+             <p><label>Password <input type=\"password\" name=\"password\" \
+             required></label></p>\n\
+             <button type=\"submit\" name=\"consent\" value=\"allow\">\
+             Allow</button>\n\
+             <button type=\"submit\" name=\"consent\" value=\"deny\">\
+             Deny</button>\n\
+             </form>\n",
+            user_code = handlers::oauth::format_user_code(
+                &authorization.user_code
+            ),
+            error = error_paragraph(error),
+        ),
+        status_code,
+    )
+}
+
+fn pending_authorization(
+    connection: &DalConnection,
+    params: &HashMap<String, String>,
+) -> Result<(DeviceAuthorization, OAuthClient), Response> {
+    let user_code = match params.get("user_code") {
+        Some(user_code) if !user_code.trim().is_empty() => user_code,
+        _ => return Err(code_entry_page(None, 200)),
+    };
+    match handlers::oauth::get_pending_device_authorization(
+        connection, user_code,
+    ) {
+        Ok(pending) => Ok(pending),
+        Err(GetPendingDeviceAuthorizationError::InvalidUserCode) => Err(
+            code_entry_page(Some("That code is invalid or has expired."), 400),
+        ),
+        Err(GetPendingDeviceAuthorizationError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+pub fn verification_page(
+    request: &Request,
+    connection: &DalConnection,
+) -> Response {
+    let _span = telemetry::span("oauth::device::verification_page");
+    match pending_authorization(connection, &query_params(request)) {
+        Ok((authorization, client)) => {
+            confirm_page(&authorization, &client, None, 200)
+        }
+        Err(response) => response,
+    }
+}
+
+/// Handles the verification form, approving or denying the device once the user
+/// signs in
+///
+/// # Panics
+///
+/// On unexpected database errors
+pub fn verify(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("oauth::device::verify");
+    let params: HashMap<String, String> =
+        match raw_urlencoded_post_input(request) {
+            Ok(fields) => fields.into_iter().collect(),
+            Err(_) => return code_entry_page(Some("Body format error."), 400),
+        };
+    let (authorization, client) =
+        match pending_authorization(connection, &params) {
+            Ok(pending) => pending,
+            Err(response) => return response,
+        };
+
+    let client_info = client::client_info(request);
+    let user = match handlers::user::authenticate_user(
+        connection,
+        params.get("email").map_or("", String::as_str),
+        params.get("password").map_or("", String::as_str),
+        &client_info.ip_address,
+        client_info.user_agent,
+    ) {
+        Ok(user) => user,
+        Err(CreateTokenError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+        Err(_) => {
+            return confirm_page(
+                &authorization,
+                &client,
+                Some("Incorrect email or password."),
+                401,
+            );
+        }
+    };
+
+    let approved = params.get("consent").map(String::as_str) == Some("allow");
+    match handlers::oauth::decide_device_authorization(
+        connection,
+        &authorization,
+        &user,
+        approved,
+    ) {
+        Ok(()) => {
+            record_event(
+                request,
+                connection,
+                &Event {
+                    event_type: if approved {
+                        EventType::DeviceAuthorizationApproved
+                    } else {
+                        EventType::DeviceAuthorizationDenied
+                    },
+                    actor: Actor::User(user.id),
+                    target: Target::DeviceAuthorization(authorization.id),
+                    details: json!({
+                        "client_id": client.client_id,
+                        "scope": authorization.scope,
+                    }),
+                },
+            );
+            if approved {
+                html_page(
+                    "Device connected",
+                    &format!(
+                        "<h1>Device connected</h1>\n<p>{} can now access \
+                         your account. You can return to your device.</p>\n",
+                        escape_html(&client.name)
+                    ),
+                    200,
+                )
+            } else {
+                html_page(
+                    "Request denied",
+                    "<h1>Request denied</h1>\n<p>The device has not been \
+                     given access to your account.</p>\n",
+                    200,
+                )
+            }
+        }
+        Err(DecideDeviceAuthorizationError::InvalidUserCode) => {
+            code_entry_page(Some("That code is invalid or has expired."), 400)
+        }
+        Err(DecideDeviceAuthorizationError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+pub mod authorize;
+pub mod device;
+pub mod models;
+pub mod oidc;
+pub mod token;
+
+use dal::{oauth::OAuthClient, DalConnection};
+use handlers::{self, oauth::AuthenticateClientError};
+use oauth::models::OAuthErrorResponse;
+use rouille::{
+    input::{basic_http_auth, post::raw_urlencoded_post_input},
+    Request,
+    Response,
+};
+use std::collections::HashMap;
+use telemetry;
+use url::form_urlencoded;
+
+pub fn routes(request: &Request, connection: &DalConnection) -> Response {
+    router!(
+        request,
+        (GET) ["/authorize"] => authorize::authorize_page(request, connection),
+        (POST) ["/authorize"] => authorize::authorize(request, connection),
+        (POST) ["/token"] => token::token(request, connection),
+        (POST) ["/device_authorization"] => {
+            device::device_authorization(request, connection)
+        },
+        (GET) ["/device"] => device::verification_page(request, connection),
+        (POST) ["/device"] => device::verify(request, connection),
+        (POST) ["/introspect"] => token::introspect(request, connection),
+        (POST) ["/revoke"] => token::revoke(request, connection),
+        _ => Response::empty_404(),
+    )
+}
+
+#[must_use]
+pub fn query_params(request: &Request) -> HashMap<String, String> {
+    form_urlencoded::parse(request.raw_query_string().as_bytes())
+        .into_owned()
+        .collect()
+}
+
+pub fn error_response(
+    status_code: u16,
+    error: &str,
+    error_description: Option<&str>,
+) -> Response {
+    let mut response = Response::json(&OAuthErrorResponse {
+        error: error.to_owned(),
+        error_description: error_description.map(str::to_owned),
+    });
+    response.status_code = status_code;
+    response.with_no_cache()
+}
+
+#[must_use]
+pub fn invalid_client_response() -> Response {
+    error_response(401, "invalid_client", Some("Client authentication failed"))
+        .with_unique_header("WWW-Authenticate", "Basic")
+}
+
+/// Reads an `application/x-www-form-urlencoded` request body
+///
+/// # Errors
+///
+/// A 400 response if the body isn't a form
+pub fn form_params(
+    request: &Request,
+) -> Result<HashMap<String, String>, Response> {
+    raw_urlencoded_post_input(request)
+        .map(|fields| fields.into_iter().collect())
+        .map_err(|_| {
+            error_response(400, "invalid_request", Some("Body format error"))
+        })
+}
+
+/// Authenticates the calling client with HTTP basic auth or credentials in
+/// the request body
+///
+/// # Errors
+///
+/// The `invalid_client` response if the client can't be authenticated
+///
+/// # Panics
+///
+/// On unexpected database errors
+#[allow(clippy::implicit_hasher)]
+pub fn authenticate_client(
+    request: &Request,
+    connection: &DalConnection,
+    params: &HashMap<String, String>,
+) -> Result<OAuthClient, Response> {
+    let _span = telemetry::span("oauth::authenticate_client");
+    let (client_id, client_secret) = match basic_http_auth(request) {
+        Some(credentials) => {
+            (Some(credentials.login), Some(credentials.password))
+        }
+        None => (
+            params.get("client_id").cloned(),
+            params.get("client_secret").cloned(),
+        ),
+    };
+    let Some(client_id) = client_id else {
+        return Err(invalid_client_response());
+    };
+    match handlers::oauth::authenticate_client(
+        connection,
+        &client_id,
+        client_secret.as_deref(),
+    ) {
+        Ok(client) => Ok(client),
+        Err(AuthenticateClientError::InvalidClient) => {
+            Err(invalid_client_response())
+        }
+        Err(AuthenticateClientError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
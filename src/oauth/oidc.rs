@@ -0,0 +1,121 @@
+use base64;
+use dal::DalConnection;
+use handlers::{self, oidc::UserInfoError};
+use oauth::models::{Jwk, JwkSet, OpenIdConfiguration, UserInfoResponse};
+use rouille::{Request, Response};
+use telemetry;
+use v1::auth::request_token;
+
+#[must_use]
+pub fn openid_configuration() -> Response {
+    let _span = telemetry::span("oauth::oidc::openid_configuration");
+    if handlers::oidc::signing_key().is_none() {
+        return Response::empty_404();
+    }
+    let issuer = handlers::oidc::issuer();
+    Response::json(&OpenIdConfiguration {
+        authorization_endpoint: format!("{issuer}/oauth/authorize"),
+        token_endpoint: format!("{issuer}/oauth/token"),
+        userinfo_endpoint: format!("{issuer}/userinfo"),
+        jwks_uri: format!("{issuer}/.well-known/jwks.json"),
+        introspection_endpoint: format!("{issuer}/oauth/introspect"),
+        revocation_endpoint: format!("{issuer}/oauth/revoke"),
+        device_authorization_endpoint: format!(
+            "{issuer}/oauth/device_authorization"
+        ),
+        issuer,
+        scopes_supported: vec!["openid", "email"],
+        response_types_supported: vec!["code"],
+        grant_types_supported: vec![
+            "authorization_code",
+            "client_credentials",
+            handlers::oauth::DEVICE_CODE_GRANT,
+        ],
+        subject_types_supported: vec!["public"],
+        id_token_signing_alg_values_supported: vec!["RS256"],
+        token_endpoint_auth_methods_supported: vec![
+            "client_secret_basic",
+            "client_secret_post",
+            "none",
+        ],
+        code_challenge_methods_supported: vec!["S256"],
+        claims_supported: vec![
+            "iss",
+            "sub",
+            "aud",
+            "exp",
+            "iat",
+            "auth_time",
+            "nonce",
+            "email",
+            "email_verified",
+        ],
+    })
+    .with_public_cache(3600)
+}
+
+#[must_use]
+pub fn jwks() -> Response {
+    let _span = telemetry::span("oauth::oidc::jwks");
+    let Some(signing_key) = handlers::oidc::signing_key() else {
+        return Response::empty_404();
+    };
+    Response::json(&JwkSet {
+        keys: vec![Jwk {
+            kty: "RSA".to_owned(),
+            key_use: "sig".to_owned(),
+            alg: "RS256".to_owned(),
+            kid: signing_key.kid.clone(),
+            n: base64::encode_config(
+                &signing_key.modulus,
+                base64::URL_SAFE_NO_PAD,
+            ),
+            e: base64::encode_config(
+                &signing_key.exponent,
+                base64::URL_SAFE_NO_PAD,
+            ),
+        }],
+    })
+    .with_public_cache(3600)
+}
+
+fn bearer_error_response(status_code: u16, error: &str) -> Response {
+    let mut response = Response::empty_204();
+    response.status_code = status_code;
+    response.with_unique_header(
+        "WWW-Authenticate",
+        format!("Bearer error=\"{error}\""),
+    )
+}
+
+/// Returns the claims about the user the access token is for
+///
+/// # Panics
+///
+/// On unexpected database errors
+pub fn userinfo(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("oauth::oidc::userinfo");
+    let Some(token) = request_token(request) else {
+        let mut response = Response::empty_204();
+        response.status_code = 401;
+        return response.with_unique_header("WWW-Authenticate", "Bearer");
+    };
+
+    match handlers::oidc::user_info(connection, &token) {
+        Ok(user_info) => Response::json(&UserInfoResponse {
+            sub: user_info.sub,
+            email: user_info.email,
+            email_verified: false,
+        })
+        .with_no_cache(),
+        Err(UserInfoError::InvalidToken(_)) => {
+            bearer_error_response(401, "invalid_token")
+        }
+        Err(UserInfoError::InsufficientScope) => {
+            bearer_error_response(403, "insufficient_scope")
+        }
+        Err(UserInfoError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use dal::{oauth::OAuthClient, DalConnection};
+use handlers::{
+    self,
+    audit::{Actor, Event, EventType, Target},
+    oauth::{
+        ClientCredentialsError,
+        DeviceCodeError,
+        ExchangeCodeError,
+        IntrospectTokenError,
+        RevokeTokenError,
+        TokenGrant,
+    },
+};
+use oauth::{
+    authenticate_client,
+    error_response,
+    form_params,
+    invalid_client_response,
+    models::{IntrospectionResponse, TokenResponse},
+};
+use rouille::{Request, Response};
+use serde_json::json;
+use std::collections::HashMap;
+use telemetry;
+use v1::audit::record_event;
+
+fn token_response(grant: TokenGrant) -> Response {
+    Response::json(&TokenResponse {
+        access_token: grant.access_token,
+        token_type: "Bearer".to_owned(),
+        expires_in: grant.expires_in,
+        scope: grant.scope,
+        id_token: grant.id_token,
+    })
+    .with_no_cache()
+}
+
+pub fn token(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("oauth::token::token");
+    let params = match form_params(request) {
+        Ok(params) => params,
+        Err(response) => return response,
+    };
+    let client = match authenticate_client(request, connection, &params) {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    match params.get("grant_type").map(String::as_str) {
+        Some("authorization_code") => {
+            authorization_code_grant(connection, &client, &params)
+        }
+        Some("client_credentials") => {
+            client_credentials_grant(connection, &client, &params)
+        }
+        Some(handlers::oauth::DEVICE_CODE_GRANT) => {
+            device_code_grant(connection, &client, &params)
+        }
+        Some(_) => error_response(400, "unsupported_grant_type", None),
+        None => {
+            error_response(400, "invalid_request", Some("grant_type missing"))
+        }
+    }
+}
+
+fn authorization_code_grant(
+    connection: &DalConnection,
+    client: &OAuthClient,
+    params: &HashMap<String, String>,
+) -> Response {
+    let _span = telemetry::span("oauth::token::authorization_code_grant");
+    let (Some(code), Some(redirect_uri), Some(code_verifier)) = (
+        params.get("code"),
+        params.get("redirect_uri"),
+        params.get("code_verifier"),
+    ) else {
+        return error_response(
+            400,
+            "invalid_request",
+            Some("code, redirect_uri and code_verifier are required"),
+        );
+    };
+
+    match handlers::oauth::exchange_authorization_code(
+        connection,
+        client,
+        code,
+        redirect_uri,
+        code_verifier,
+    ) {
+        Ok(grant) => token_response(grant),
+        Err(ExchangeCodeError::UnauthorizedClient) => {
+            error_response(400, "unauthorized_client", None)
+        }
+        Err(ExchangeCodeError::InvalidGrant) => {
+            error_response(400, "invalid_grant", None)
+        }
+        Err(ExchangeCodeError::InvalidScope) => {
+            error_response(400, "invalid_scope", None)
+        }
+        Err(ExchangeCodeError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+fn client_credentials_grant(
+    connection: &DalConnection,
+    client: &OAuthClient,
+    params: &HashMap<String, String>,
+) -> Response {
+    let _span = telemetry::span("oauth::token::client_credentials_grant");
+    match handlers::oauth::client_credentials_grant(
+        connection,
+        client,
+        params.get("scope").map(String::as_str),
+    ) {
+        Ok(grant) => token_response(grant),
+        Err(ClientCredentialsError::UnauthorizedClient) => {
+            error_response(400, "unauthorized_client", None)
+        }
+        Err(ClientCredentialsError::InvalidScope) => {
+            error_response(400, "invalid_scope", None)
+        }
+        Err(ClientCredentialsError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+fn device_code_grant(
+    connection: &DalConnection,
+    client: &OAuthClient,
+    params: &HashMap<String, String>,
+) -> Response {
+    let _span = telemetry::span("oauth::token::device_code_grant");
+    let Some(device_code) = params.get("device_code") else {
+        return error_response(
+            400,
+            "invalid_request",
+            Some("device_code missing"),
+        );
+    };
+
+    match handlers::oauth::exchange_device_code(connection, client, device_code)
+    {
+        Ok(grant) => token_response(grant),
+        Err(DeviceCodeError::UnauthorizedClient) => {
+            error_response(400, "unauthorized_client", None)
+        }
+        Err(DeviceCodeError::AuthorizationPending) => {
+            error_response(400, "authorization_pending", None)
+        }
+        Err(DeviceCodeError::SlowDown) => {
+            error_response(400, "slow_down", None)
+        }
+        Err(DeviceCodeError::AccessDenied) => {
+            error_response(400, "access_denied", None)
+        }
+        Err(DeviceCodeError::ExpiredToken) => {
+            error_response(400, "expired_token", None)
+        }
+        Err(DeviceCodeError::InvalidGrant) => {
+            error_response(400, "invalid_grant", None)
+        }
+        Err(DeviceCodeError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+/// Token introspection as described by RFC 7662. Only confidential clients
+/// may introspect tokens.
+///
+/// # Panics
+///
+/// On unexpected database errors
+pub fn introspect(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("oauth::token::introspect");
+    let params = match form_params(request) {
+        Ok(params) => params,
+        Err(response) => return response,
+    };
+    match authenticate_client(request, connection, &params) {
+        Ok(ref client) if client.client_secret.is_some() => (),
+        Ok(_) => return invalid_client_response(),
+        Err(response) => return response,
+    }
+    let Some(token) = params.get("token") else {
+        return error_response(400, "invalid_request", Some("token missing"));
+    };
+
+    match handlers::oauth::introspect_token(connection, token) {
+        Ok(Some(introspection)) => Response::json(&IntrospectionResponse {
+            active: true,
+            sub: Some(introspection.sub),
+            exp: introspection.exp,
+            iat: Some(introspection.iat),
+            scope: introspection.scope,
+            token_type: Some("Bearer".to_owned()),
+            client_id: introspection.client_id,
+            username: introspection.username,
+        })
+        .with_no_cache(),
+        Ok(None) => Response::json(&IntrospectionResponse {
+            active: false,
+            sub: None,
+            exp: None,
+            iat: None,
+            scope: None,
+            token_type: None,
+            client_id: None,
+            username: None,
+        })
+        .with_no_cache(),
+        Err(IntrospectTokenError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+/// Token revocation as described by RFC 7009. Clients can only revoke tokens
+/// that were issued to them.
+///
+/// # Panics
+///
+/// On unexpected database errors
+pub fn revoke(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("oauth::token::revoke");
+    let params = match form_params(request) {
+        Ok(params) => params,
+        Err(response) => return response,
+    };
+    let client = match authenticate_client(request, connection, &params) {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+    let Some(token) = params.get("token") else {
+        return error_response(400, "invalid_request", Some("token missing"));
+    };
+
+    match handlers::oauth::revoke_client_token(connection, &client, token) {
+        Ok(revoked) => {
+            if let Some(auth_token) = revoked {
+                record_event(
+                    request,
+                    connection,
+                    &Event {
+                        event_type: EventType::TokenRevoked,
+                        actor: Actor::Client(&client.client_id),
+                        target: Target::Token(auth_token.id),
+                        details: json!({
+                            "token_type": auth_token.token_type,
+                            "reason": "revoked",
+                        }),
+                    },
+                );
+            }
+            Response::text("").with_no_cache()
+        }
+        Err(RevokeTokenError::UnauthorizedClient) => {
+            error_response(400, "unauthorized_client", None)
+        }
+        Err(RevokeTokenError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
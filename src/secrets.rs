@@ -0,0 +1,425 @@
+extern crate ureq;
+
+use self::ureq::Agent;
+use serde_json::{Map, Value};
+use std::{
+    env,
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+use url::Url;
+
+/// Where Docker and Kubernetes mount secrets by default
+const DEFAULT_SECRETS_DIR: &str = "/run/secrets";
+const VAULT_TIMEOUT_SECS: u64 = 10;
+
+pub enum SecretError {
+    ReadError(PathBuf, io::Error),
+    VaultError(String),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadError(path, error) => {
+                write!(f, "Error reading secret {}: {error}", path.display())
+            }
+            Self::VaultError(reason) => {
+                write!(f, "Error reading secrets from Vault: {reason}")
+            }
+        }
+    }
+}
+
+/// Somewhere secrets can be looked up by name, such as `JWT_SECRET`
+pub trait SecretProvider: Send + Sync {
+    /// Returns `None` if the provider doesn't have the secret, so the next
+    /// one can be tried
+    ///
+    /// # Errors
+    ///
+    /// If the provider has the secret but can't read it
+    fn get(&self, name: &str) -> Result<Option<String>, SecretError>;
+}
+
+/// Reads a secret file, without the trailing newline most tools add
+fn read_secret_file(path: &Path) -> Result<String, SecretError> {
+    fs::read_to_string(path)
+        .map(|contents| contents.trim_end_matches(['\r', '\n']).to_owned())
+        .map_err(|error| SecretError::ReadError(path.to_owned(), error))
+}
+
+/// Reads `NAME` from the environment, or the file named by `NAME_FILE`
+pub struct EnvProvider;
+
+impl SecretProvider for EnvProvider {
+    fn get(&self, name: &str) -> Result<Option<String>, SecretError> {
+        if let Ok(value) = env::var(name) {
+            return Ok(Some(value));
+        }
+        env::var_os(format!("{name}_FILE"))
+            .map(|path| read_secret_file(Path::new(&path)))
+            .transpose()
+    }
+}
+
+/// Reads secrets mounted as files in a directory, named after the secret as
+/// given or in lower case
+pub struct DirectoryProvider {
+    dir: PathBuf,
+}
+
+impl DirectoryProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self { Self { dir: dir.into() } }
+}
+
+impl SecretProvider for DirectoryProvider {
+    fn get(&self, name: &str) -> Result<Option<String>, SecretError> {
+        [name.to_owned(), name.to_ascii_lowercase()]
+            .iter()
+            .map(|file_name| self.dir.join(file_name))
+            .find(|path| path.is_file())
+            .map(|path| read_secret_file(&path))
+            .transpose()
+    }
+}
+
+/// Reads secrets from a Vault KV secret, whose keys are the secret names.
+///
+/// The secret is read once, when the provider is created.
+pub struct VaultProvider {
+    secrets: Map<String, Value>,
+}
+
+/// Sends a `GET` request, returning the status code and body
+fn vault_get(url: &Url, token: &str) -> Result<(u16, String), ureq::Error> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(VAULT_TIMEOUT_SECS)))
+        .http_status_as_error(false)
+        .build()
+        .into();
+    let mut response = agent
+        .get(url.as_str())
+        .header("X-Vault-Token", token)
+        .call()?;
+    let status = response.status().as_u16();
+    let body = response.body_mut().read_to_string()?;
+    Ok((status, body))
+}
+
+impl VaultProvider {
+    /// Reads the secret at `path` from the Vault at `address`, such as
+    /// `http://127.0.0.1:8200`. `path` is the API path without `/v1/`, so
+    /// `secret/data/login_api` for KV version 2 or `secret/login_api` for
+    /// version 1.
+    ///
+    /// # Errors
+    ///
+    /// If the address isn't an http or https URL, or the secret can't be read
+    pub fn new(
+        address: &str,
+        path: &str,
+        token: &str,
+    ) -> Result<Self, SecretError> {
+        let vault_error = |reason: &str| SecretError::VaultError(reason.into());
+        let url = Url::parse(address)
+            .and_then(|url| url.join(&format!("/v1/{path}")))
+            .map_err(|error| vault_error(&error.to_string()))?;
+        if !["http", "https"].contains(&url.scheme()) {
+            return Err(vault_error("the address must be http or https"));
+        }
+        let (status, body) = vault_get(&url, token)
+            .map_err(|error| vault_error(&error.to_string()))?;
+        if status != 200 {
+            return Err(vault_error(&format!(
+                "reading {path} returned {status}"
+            )));
+        }
+        let response: Value = serde_json::from_str(&body)
+            .map_err(|error| vault_error(&error.to_string()))?;
+        // KV version 2 nests the secret's data and metadata under `data`
+        let data = &response["data"];
+        let data = if data["data"].is_object() && data["metadata"].is_object()
+        {
+            &data["data"]
+        } else {
+            data
+        };
+        let secrets = data
+            .as_object()
+            .ok_or_else(|| vault_error(&format!("{path} has no data")))?
+            .clone();
+        Ok(Self { secrets })
+    }
+}
+
+impl SecretProvider for VaultProvider {
+    fn get(&self, name: &str) -> Result<Option<String>, SecretError> {
+        match self.secrets.get(name) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(SecretError::VaultError(format!(
+                "{name} isn't a string"
+            ))),
+        }
+    }
+}
+
+static PROVIDERS: OnceLock<Vec<Box<dyn SecretProvider>>> = OnceLock::new();
+
+/// Sets where secrets are looked up, in order
+///
+/// # Panics
+///
+/// If the providers have already been set
+pub fn set_providers(providers: Vec<Box<dyn SecretProvider>>) {
+    assert!(
+        PROVIDERS.set(providers).is_ok(),
+        "The secret providers should only be set once"
+    );
+}
+
+/// Looks secrets up in the environment and `*_FILE` files, then
+/// `SECRETS_DIR` (`/run/secrets` if it exists), then the Vault secret at
+/// `VAULT_SECRET_PATH` if `VAULT_ADDR` is set
+///
+/// # Errors
+///
+/// If Vault is configured without a path or token, or can't be read
+pub fn init() -> Result<(), SecretError> {
+    let mut providers: Vec<Box<dyn SecretProvider>> =
+        vec![Box::new(EnvProvider)];
+    let secrets_dir = env::var_os("SECRETS_DIR").map(PathBuf::from).or_else(
+        || {
+            Some(PathBuf::from(DEFAULT_SECRETS_DIR))
+                .filter(|dir| dir.is_dir())
+        },
+    );
+    if let Some(dir) = secrets_dir {
+        providers.push(Box::new(DirectoryProvider::new(dir)));
+    }
+    if let Ok(address) = env::var("VAULT_ADDR") {
+        let missing = |name: &str| {
+            SecretError::VaultError(format!(
+                "{name} must be set along with VAULT_ADDR"
+            ))
+        };
+        let path = env::var("VAULT_SECRET_PATH")
+            .map_err(|_| missing("VAULT_SECRET_PATH"))?;
+        // The token can come from a mounted file too
+        let token = lookup(&providers, "VAULT_TOKEN")?
+            .ok_or_else(|| missing("VAULT_TOKEN"))?;
+        providers.push(Box::new(VaultProvider::new(&address, &path, &token)?));
+    }
+    set_providers(providers);
+    Ok(())
+}
+
+fn lookup(
+    providers: &[Box<dyn SecretProvider>],
+    name: &str,
+) -> Result<Option<String>, SecretError> {
+    providers
+        .iter()
+        .find_map(|provider| provider.get(name).transpose())
+        .transpose()
+}
+
+/// Looks a secret up with each provider in turn
+///
+/// # Errors
+///
+/// If a provider fails to read the secret
+///
+/// # Panics
+///
+/// If called before `init`
+pub fn get(name: &str) -> Result<Option<String>, SecretError> {
+    let providers = PROVIDERS
+        .get()
+        .expect("The secret providers should be set up at startup");
+    lookup(providers, name)
+}
+
+/// Looks up a secret the server can't run without
+///
+/// # Panics
+///
+/// If the secret is missing or can't be read
+#[must_use]
+pub fn require(name: &str) -> String {
+    match get(name) {
+        Ok(Some(secret)) => secret,
+        Ok(None) => panic!("{} must be set", name),
+        Err(error) => panic!("{}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        process,
+        sync::mpsc::{self, Receiver},
+        thread,
+    };
+
+    /// Answers one request with `status` and `body`, returning the address
+    /// and the request line and headers it receives
+    fn serve_once(status: &str, body: &str) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            sender.send(head).unwrap();
+        });
+        (address, receiver)
+    }
+
+    fn secret(provider: &impl SecretProvider, name: &str) -> Option<String> {
+        match provider.get(name) {
+            Ok(secret) => secret,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    fn vault_error(result: Result<VaultProvider, SecretError>) -> String {
+        match result {
+            Ok(_) => panic!("Reading the Vault secret should fail"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_kv_version_2_secrets() {
+        let (address, request) = serve_once(
+            "200 OK",
+            r#"{"data": {
+                "data": {"JWT_SECRET": "jwt"},
+                "metadata": {"version": 3}
+            }}"#,
+        );
+        let provider =
+            VaultProvider::new(&address, "secret/data/login_api", "token")
+                .unwrap_or_else(|error| panic!("{}", error));
+        let request = request.recv().unwrap().to_ascii_lowercase();
+        assert!(request.starts_with("get /v1/secret/data/login_api "));
+        assert!(request.contains("x-vault-token: token\r\n"));
+        assert_eq!(secret(&provider, "JWT_SECRET").as_deref(), Some("jwt"));
+        assert_eq!(secret(&provider, "HMAC_HASH"), None);
+    }
+
+    #[test]
+    fn reads_kv_version_1_secrets() {
+        let (address, _) = serve_once(
+            "200 OK",
+            r#"{"lease_duration": 60, "data": {"JWT_SECRET": "jwt"}}"#,
+        );
+        let provider = VaultProvider::new(&address, "secret/login_api", "t")
+            .unwrap_or_else(|error| panic!("{}", error));
+        assert_eq!(secret(&provider, "JWT_SECRET").as_deref(), Some("jwt"));
+    }
+
+    #[test]
+    fn reports_vault_errors() {
+        let (address, _) =
+            serve_once("403 Forbidden", r#"{"errors": ["permission denied"]}"#);
+        let error =
+            vault_error(VaultProvider::new(&address, "secret/login_api", "t"));
+        assert!(error.contains("returned 403"), "{}", error);
+
+        let (address, _) = serve_once("200 OK", r#"{"data": ["JWT_SECRET"]}"#);
+        let error =
+            vault_error(VaultProvider::new(&address, "secret/login_api", "t"));
+        assert!(error.contains("has no data"), "{}", error);
+
+        let error = vault_error(VaultProvider::new(
+            "ftp://127.0.0.1",
+            "secret/login_api",
+            "t",
+        ));
+        assert!(error.contains("http or https"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_values_that_arent_strings() {
+        let (address, _) = serve_once(
+            "200 OK",
+            r#"{"data": {"JWT_SECRET": 42, "HMAC_HASH": "pepper"}}"#,
+        );
+        let provider = VaultProvider::new(&address, "secret/login_api", "t")
+            .unwrap_or_else(|error| panic!("{}", error));
+        assert!(provider.get("JWT_SECRET").is_err());
+        assert_eq!(secret(&provider, "HMAC_HASH").as_deref(), Some("pepper"));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir()
+            .join(format!("login_api_secrets_{}_{name}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reads_secrets_from_the_environment_and_files() {
+        let path = temp_dir("env").join("secret");
+        fs::write(&path, "from file\n").unwrap();
+        env::set_var("LOGIN_API_TEST_SECRET", "from env");
+        env::set_var("LOGIN_API_TEST_SECRET_FILE", &path);
+        env::set_var("LOGIN_API_TEST_FILE_SECRET_FILE", &path);
+        env::set_var(
+            "LOGIN_API_TEST_MISSING_SECRET_FILE",
+            path.with_file_name("missing"),
+        );
+
+        assert_eq!(
+            secret(&EnvProvider, "LOGIN_API_TEST_SECRET").as_deref(),
+            Some("from env")
+        );
+        assert_eq!(
+            secret(&EnvProvider, "LOGIN_API_TEST_FILE_SECRET").as_deref(),
+            Some("from file")
+        );
+        assert!(matches!(
+            EnvProvider.get("LOGIN_API_TEST_MISSING_SECRET"),
+            Err(SecretError::ReadError(..))
+        ));
+        assert_eq!(secret(&EnvProvider, "LOGIN_API_TEST_UNSET_SECRET"), None);
+    }
+
+    #[test]
+    fn reads_secrets_from_a_directory() {
+        let dir = temp_dir("directory");
+        fs::write(dir.join("jwt_secret"), "lower\r\n").unwrap();
+        fs::write(dir.join("HMAC_HASH"), "exact").unwrap();
+        fs::write(dir.join("hmac_hash"), "lower").unwrap();
+        let provider = DirectoryProvider::new(&dir);
+
+        assert_eq!(secret(&provider, "JWT_SECRET").as_deref(), Some("lower"));
+        assert_eq!(secret(&provider, "HMAC_HASH").as_deref(), Some("exact"));
+        assert_eq!(secret(&provider, "HASH_CHAIN_KEY"), None);
+    }
+}
@@ -0,0 +1,380 @@
+extern crate bytes;
+extern crate http;
+extern crate opentelemetry;
+extern crate opentelemetry_http;
+extern crate opentelemetry_otlp;
+extern crate opentelemetry_sdk;
+extern crate ureq;
+
+use self::bytes::Bytes;
+use self::opentelemetry::{
+    propagation::TextMapPropagator,
+    trace::{
+        SpanKind, Status, TraceContextExt, Tracer as _,
+        TracerProvider as _,
+    },
+    Context, ContextGuard, KeyValue,
+};
+use self::opentelemetry_http::{HttpClient, HttpError};
+use self::opentelemetry_otlp::{
+    Protocol, SpanExporter, WithExportConfig, WithHttpConfig,
+};
+use self::opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracer, SdkTracerProvider},
+    Resource,
+};
+use std::{
+    collections::HashMap,
+    env,
+    future::{self, Future},
+    pin::Pin,
+    sync::OnceLock,
+    time::Duration,
+};
+
+const EXPORT_TIMEOUT_SECS: u64 = 10;
+
+struct Tracing {
+    provider: SdkTracerProvider,
+    tracer: SdkTracer,
+}
+
+static TRACING: OnceLock<Tracing> = OnceLock::new();
+
+/// Sends the exporter's requests with ureq, which the OTLP exporter has no
+/// built in support for. Spans are exported on the SDK's own thread, so
+/// blocking there doesn't hold up requests.
+#[derive(Debug)]
+struct UreqClient {
+    agent: ureq::Agent,
+}
+
+impl UreqClient {
+    fn new() -> Self {
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(EXPORT_TIMEOUT_SECS)))
+            .http_status_as_error(false)
+            .build()
+            .new_agent();
+        Self { agent }
+    }
+
+    fn send(
+        &self,
+        request: http::Request<Bytes>,
+    ) -> Result<http::Response<Bytes>, HttpError> {
+        let response = self.agent.run(request.map(|body| body.to_vec()))?;
+        let (parts, mut body) = response.into_parts();
+        let body = body.read_to_vec()?;
+        Ok(http::Response::from_parts(parts, Bytes::from(body)))
+    }
+}
+
+impl HttpClient for UreqClient {
+    fn send_bytes<'a, 'b>(
+        &'a self,
+        request: http::Request<Bytes>,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<http::Response<Bytes>, HttpError>>
+                + Send
+                + 'b,
+        >,
+    >
+    where
+        'a: 'b,
+        Self: 'b,
+    {
+        Box::pin(future::ready(self.send(request)))
+    }
+}
+
+/// Starts recording spans with `provider`
+fn set_provider(provider: SdkTracerProvider) {
+    let tracer = provider.tracer("login_api");
+    assert!(
+        TRACING.set(Tracing { provider, tracer }).is_ok(),
+        "Tracing should only be set up once"
+    );
+}
+
+/// Exports to `OTEL_EXPORTER_OTLP_ENDPOINT` if it's set, as
+/// `OTEL_SERVICE_NAME` (`login_api` by default). Until this is called spans
+/// aren't recorded.
+///
+/// # Panics
+///
+/// If the endpoint isn't a valid URL
+pub fn init() {
+    let Ok(endpoint) = env::var("OTEL_EXPORTER_OTLP_ENDPOINT") else {
+        return;
+    };
+    let service_name = env::var("OTEL_SERVICE_NAME")
+        .unwrap_or_else(|_| "login_api".to_owned());
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_http_client(UreqClient::new())
+        .with_protocol(Protocol::HttpJson)
+        .with_endpoint(format!(
+            "{}/v1/traces",
+            endpoint.trim_end_matches('/')
+        ))
+        .with_timeout(Duration::from_secs(EXPORT_TIMEOUT_SECS))
+        .build()
+        .unwrap_or_else(|error| {
+            panic!("Invalid OTEL_EXPORTER_OTLP_ENDPOINT: {}", error)
+        });
+    set_provider(
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            // Callers that aren't sampling a request don't want it recorded
+            .with_sampler(Sampler::ParentBased(Box::new(Sampler::AlwaysOn)))
+            .with_resource(
+                Resource::builder().with_service_name(service_name).build(),
+            )
+            .build(),
+    );
+}
+
+/// Waits for every span ended so far to be exported
+pub fn flush() {
+    if let Some(tracing) = TRACING.get() {
+        if let Err(error) = tracing.provider.force_flush() {
+            warn!("Exporting spans failed: {error}");
+        }
+    }
+}
+
+/// A span in progress, which is the current span on this thread until it's
+/// dropped. Spans that aren't being recorded do nothing.
+pub struct Span {
+    context: Option<(Context, ContextGuard)>,
+}
+
+fn start(
+    tracer: &SdkTracer,
+    name: &str,
+    kind: SpanKind,
+    parent: &Context,
+) -> Span {
+    let span = tracer
+        .span_builder(name.to_owned())
+        .with_kind(kind)
+        .start_with_context(tracer, parent);
+    let context = parent.with_span(span);
+    let guard = context.clone().attach();
+    Span {
+        context: Some((context, guard)),
+    }
+}
+
+/// Starts the span for handling a request, continuing the caller's trace if
+/// it sent a valid `traceparent`. Requests the caller isn't sampling aren't
+/// recorded.
+#[must_use]
+pub fn request_span(name: &str, traceparent: Option<&str>) -> Span {
+    let Some(tracing) = TRACING.get() else {
+        return Span { context: None };
+    };
+    let headers = traceparent
+        .map(|traceparent| ("traceparent".to_owned(), traceparent.to_owned()))
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let parent = TraceContextPropagator::new()
+        .extract_with_context(&Context::new(), &headers);
+    start(&tracing.tracer, name, SpanKind::Server, &parent)
+}
+
+/// Starts a span within the current one. Nothing is recorded outside of a
+/// request.
+#[must_use]
+pub fn span(name: &str) -> Span { child_span(name, SpanKind::Internal) }
+
+/// Starts a span for a database query
+#[must_use]
+pub fn db_span(name: &str) -> Span {
+    let mut span = child_span(name, SpanKind::Client);
+    span.set_string("db.system", "postgresql");
+    span
+}
+
+fn child_span(name: &str, kind: SpanKind) -> Span {
+    let current = Context::current();
+    match TRACING.get() {
+        // Background work isn't traced, rather than each query starting a
+        // trace of its own
+        Some(tracing) if current.has_active_span() => {
+            start(&tracing.tracer, name, kind, &current)
+        }
+        _ => Span { context: None },
+    }
+}
+
+impl Span {
+    pub fn set_name(&mut self, name: &str) {
+        if let Some((context, _)) = &self.context {
+            context.span().update_name(name.to_owned());
+        }
+    }
+
+    pub fn set_string(&mut self, key: &'static str, value: &str) {
+        if let Some((context, _)) = &self.context {
+            context
+                .span()
+                .set_attribute(KeyValue::new(key, value.to_owned()));
+        }
+    }
+
+    pub fn set_int(&mut self, key: &'static str, value: i64) {
+        if let Some((context, _)) = &self.context {
+            context.span().set_attribute(KeyValue::new(key, value));
+        }
+    }
+
+    /// Marks the span as failed
+    pub fn set_error(&mut self, message: &str) {
+        if let Some((context, _)) = &self.context {
+            context.span().set_status(Status::error(message.to_owned()));
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some((context, guard)) = self.context.take() {
+            context.span().end();
+            drop(guard);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::opentelemetry::trace::{SpanId, TraceId};
+    use super::opentelemetry_sdk::{
+        error::OTelSdkResult,
+        trace::{self, SpanData},
+    };
+    use super::*;
+    use std::sync::{Arc, Mutex, Once};
+
+    /// Keeps exported spans in memory
+    #[derive(Clone, Debug, Default)]
+    struct InMemoryExporter {
+        spans: Arc<Mutex<Vec<SpanData>>>,
+    }
+
+    impl trace::SpanExporter for InMemoryExporter {
+        fn export(
+            &self,
+            batch: Vec<SpanData>,
+        ) -> impl Future<Output = OTelSdkResult> + Send {
+            self.spans.lock().unwrap().extend(batch);
+            future::ready(Ok(()))
+        }
+    }
+
+    static EXPORTER: OnceLock<InMemoryExporter> = OnceLock::new();
+
+    /// Records spans in memory, as `init` would to a collector
+    fn init() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            let exporter = EXPORTER.get_or_init(InMemoryExporter::default);
+            set_provider(
+                SdkTracerProvider::builder()
+                    .with_simple_exporter(exporter.clone())
+                    .with_sampler(Sampler::ParentBased(Box::new(
+                        Sampler::AlwaysOn,
+                    )))
+                    .build(),
+            );
+        });
+    }
+
+    /// Gets the exported span named `name`. Tests run at the same time, so
+    /// each names its spans uniquely.
+    fn exported(name: &str) -> Option<SpanData> {
+        flush();
+        let spans = EXPORTER.get()?.spans.lock().unwrap();
+        spans.iter().find(|span| span.name == name).cloned()
+    }
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const SPAN_ID: &str = "00f067aa0ba902b7";
+
+    #[test]
+    fn continues_the_callers_trace() {
+        init();
+        drop(request_span(
+            "continued",
+            Some(&format!("00-{TRACE_ID}-{SPAN_ID}-01")),
+        ));
+        let span = exported("continued").expect("The span should be exported");
+        assert_eq!(span.span_context.trace_id().to_string(), TRACE_ID);
+        assert_eq!(span.parent_span_id.to_string(), SPAN_ID);
+        assert_eq!(span.span_kind, SpanKind::Server);
+    }
+
+    #[test]
+    fn starts_a_new_trace_for_invalid_traceparents() {
+        init();
+        let invalid = [
+            ("version ff", format!("ff-{TRACE_ID}-{SPAN_ID}-01")),
+            ("zero trace", format!("00-{}-{SPAN_ID}-01", "0".repeat(32))),
+            ("zero span", format!("00-{TRACE_ID}-{}-01", "0".repeat(16))),
+        ];
+        for (name, traceparent) in &invalid {
+            drop(request_span(name, Some(traceparent)));
+            let span = exported(name).expect("The span should be exported");
+            let trace_id = span.span_context.trace_id();
+            assert_ne!(trace_id, TraceId::INVALID, "{name}");
+            assert_ne!(trace_id.to_string(), TRACE_ID, "{name}");
+            assert_eq!(span.parent_span_id, SpanId::INVALID, "{name}");
+        }
+    }
+
+    #[test]
+    fn doesnt_record_unsampled_requests() {
+        init();
+        let request = request_span(
+            "unsampled",
+            Some(&format!("00-{TRACE_ID}-{SPAN_ID}-00")),
+        );
+        drop(db_span("unsampled query"));
+        drop(request);
+        assert!(exported("unsampled").is_none());
+        assert!(exported("unsampled query").is_none());
+    }
+
+    #[test]
+    fn links_spans_to_the_request() {
+        init();
+        let request = request_span("linked request", None);
+        let handler = span("linked handler");
+        drop(db_span("linked query"));
+        drop(handler);
+        drop(request);
+        let request = exported("linked request").unwrap();
+        let handler = exported("linked handler").unwrap();
+        let query = exported("linked query").unwrap();
+        let trace_id = request.span_context.trace_id();
+        assert_eq!(request.parent_span_id, SpanId::INVALID);
+        assert_eq!(handler.span_context.trace_id(), trace_id);
+        assert_eq!(handler.parent_span_id, request.span_context.span_id());
+        assert_eq!(query.span_context.trace_id(), trace_id);
+        assert_eq!(query.parent_span_id, handler.span_context.span_id());
+        assert_eq!(query.span_kind, SpanKind::Client);
+        assert!(query
+            .attributes
+            .contains(&KeyValue::new("db.system", "postgresql")));
+    }
+
+    #[test]
+    fn doesnt_trace_work_outside_of_requests() {
+        init();
+        drop(db_span("background query"));
+        assert!(exported("background query").is_none());
+    }
+}
@@ -0,0 +1,90 @@
+use chrono::Utc;
+use config;
+use dal::{
+    self,
+    schema::user_roles,
+    users::{self, NewUser, User},
+    DalConnection,
+};
+use diesel::{self, Connection, ExpressionMethods, RunQueryDsl};
+use secrets::{self, EnvProvider, SecretError, SecretProvider};
+use std::{sync::Once, time::Duration};
+
+/// Fixed secrets, so tests don't depend on the environment's
+struct TestSecrets;
+
+impl SecretProvider for TestSecrets {
+    fn get(&self, name: &str) -> Result<Option<String>, SecretError> {
+        match name {
+            "JWT_SECRET" | "HASH_CHAIN_KEY" | "HMAC_HASH" => {
+                Ok(Some(format!("test {name}")))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Loads the configuration from the environment, with fixed secrets. A
+/// placeholder database URL is used if `DATABASE_URL` isn't set, so tests
+/// that don't touch the database can still run.
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        secrets::set_providers(vec![
+            Box::new(TestSecrets),
+            Box::new(EnvProvider),
+        ]);
+        let mut args = vec![];
+        if secrets::get("DATABASE_URL").ok().flatten().is_none() {
+            args.push(
+                "--database-url=postgres://localhost/login_api".to_owned(),
+            );
+        }
+        let args = config::parse_args(args)
+            .unwrap_or_else(|error| panic!("{}", error));
+        if let Err(error) = config::load(&args) {
+            panic!("{}", error);
+        }
+    });
+}
+
+/// Connects to `DATABASE_URL` in a transaction that's never committed, so
+/// nothing a test does is kept
+pub fn connection() -> DalConnection {
+    init();
+    let config = config::get();
+    let pool =
+        dal::create_pool(config.database_url(), 1, Duration::from_secs(5));
+    let connection = DalConnection::new(
+        pool.get().expect("Tests need a database at DATABASE_URL"),
+    );
+    connection
+        .pg_connection
+        .begin_test_transaction()
+        .expect("The test transaction should start");
+    connection
+}
+
+/// Creates a user with the given roles
+pub fn create_user(connection: &DalConnection, roles: &[&str]) -> User {
+    let email = format!("test{}@example.com", rand::random::<u32>());
+    let new_user = NewUser {
+        email: &email,
+        password: "",
+        pepper_id: 1,
+    };
+    let Ok(user) = users::create_user(connection, &new_user) else {
+        panic!("The test user should be created");
+    };
+    for role in roles {
+        diesel::insert_into(user_roles::table)
+            .values((
+                user_roles::user_id.eq(user.id),
+                user_roles::role.eq(role),
+                user_roles::date_created.eq(Utc::now()),
+            ))
+            .execute(&connection.pg_connection)
+            .expect("The test user's role should be added");
+    }
+    user
+}
@@ -0,0 +1,565 @@
+use chrono::{DateTime, Utc};
+use dal::{
+    audit::{AuditEvent, AuditEventFilter},
+    auth::{AuthLog, AuthLogFilter, GetAuthLogError},
+    DalConnection,
+};
+use handlers::{
+    self,
+    audit::{Actor, Event, EventType, Target},
+    auth::Identity,
+    hash_chain::{to_hex, Chain, VerifyChainError},
+    oauth::RegisterClientError,
+    pagination::SearchPageError,
+    password::PepperUsageError,
+};
+use oauth::query_params;
+use rouille::{
+    input::{json::JsonError, json_input},
+    Request,
+    Response,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::{collections::HashMap, str::FromStr};
+use telemetry;
+use v1::{
+    audit::record_event,
+    auth::require_role,
+    export::{export_response, CsvRecord, ExportFormat},
+    models::{
+        admin::{
+            AuditEventPageResponse,
+            AuditEventResponse,
+            AuthLogEntryResponse,
+            AuthLogPageResponse,
+            BrokenLinkResponse,
+            ChainReportResponse,
+            CreateClientRequest,
+            CreateClientResponse,
+            IpFailureCountResponse,
+            PepperReportResponse,
+            PepperUsageResponse,
+            TargetedAccountResponse,
+        },
+        response::SingleErrorResponse,
+    },
+};
+use validator::Validate;
+
+const ADMIN_ROLE: &str = "admin";
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+/// Exports can be larger pages, as they're meant for bulk analysis
+const MAX_EXPORT_PAGE_SIZE: i64 = 10_000;
+
+pub fn routes(request: &Request, connection: &DalConnection) -> Response {
+    let identity = match require_role(request, connection, ADMIN_ROLE) {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+
+    router!(
+        request,
+        (POST) ["/clients"] => create_client(request, connection, &identity),
+        (GET) ["/peppers"] => pepper_report(connection),
+        (GET) ["/auth_log"] => search_auth_log(request, connection),
+        (GET) ["/auth_log/failures_by_ip"] => {
+            failures_by_ip(request, connection)
+        },
+        (GET) ["/auth_log/targeted_accounts"] => {
+            targeted_accounts(request, connection)
+        },
+        (GET) ["/audit_events"] => search_audit_events(request, connection),
+        (GET) ["/hash_chains"] => verify_hash_chains(connection),
+        _ => Response::empty_404(),
+    )
+}
+
+fn create_client(
+    request: &Request,
+    connection: &DalConnection,
+    identity: &Identity,
+) -> Response {
+    let _span = telemetry::span("v1::admin::create_client");
+    let body: CreateClientRequest = match json_input(request) {
+        Ok(body) => body,
+        Err(
+            JsonError::WrongContentType
+            | JsonError::IoError(_)
+            | JsonError::ParseError(_),
+        ) => {
+            let mut response = Response::json(&SingleErrorResponse {
+                error: "Body format error".to_owned(),
+            });
+            response.status_code = 400;
+            return response;
+        }
+        _ => panic!("Body should only be extracted once."),
+    };
+    // Validate fields
+    match body.validate() {
+        Ok(()) => (),
+        Err(e) => {
+            let mut response = Response::json(&e);
+            response.status_code = 422;
+            return response;
+        }
+    }
+
+    match handlers::oauth::register_client(
+        connection,
+        &body.name,
+        &body.redirect_uris,
+        &body.grant_types,
+        &body.allowed_scopes,
+        body.confidential,
+    ) {
+        Ok((client, client_secret)) => {
+            record_event(
+                request,
+                connection,
+                &Event {
+                    event_type: EventType::ClientRegistered,
+                    actor: Actor::from_principal(&identity.principal),
+                    target: Target::Client(&client.client_id),
+                    details: json!({
+                        "name": client.name,
+                        "redirect_uris": client.redirect_uris,
+                        "grant_types": client.grant_types,
+                        "allowed_scopes": client.allowed_scopes,
+                        "confidential": client_secret.is_some(),
+                    }),
+                },
+            );
+            let mut response = Response::json(&CreateClientResponse {
+                client_id: client.client_id,
+                client_secret,
+                name: client.name,
+                redirect_uris: client.redirect_uris,
+                grant_types: client.grant_types,
+                allowed_scopes: client.allowed_scopes,
+                date_created: client.date_created,
+            });
+            response.status_code = 201;
+            response
+        }
+        Err(RegisterClientError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+fn pepper_report(connection: &DalConnection) -> Response {
+    let _span = telemetry::span("v1::admin::pepper_report");
+    match handlers::password::pepper_usage(connection) {
+        Ok(usage) => Response::json(&PepperReportResponse {
+            current_pepper_id: handlers::password::peppers().current_id,
+            peppers: usage
+                .into_iter()
+                .map(|pepper| PepperUsageResponse {
+                    pepper_id: pepper.pepper_id,
+                    users: pepper.users,
+                    current: pepper.current,
+                    configured: pepper.configured,
+                })
+                .collect(),
+        }),
+        Err(PepperUsageError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+fn bad_request(error: &str) -> Response {
+    let mut response = Response::json(&SingleErrorResponse {
+        error: error.to_owned(),
+    });
+    response.status_code = 400;
+    response
+}
+
+/// Parses an optional query parameter, responding with a 400 if it's invalid
+fn param<T: FromStr>(
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<T>, Response> {
+    params
+        .get(name)
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| bad_request(&format!("Invalid {name} parameter")))
+        })
+        .transpose()
+}
+
+fn export_format(
+    params: &HashMap<String, String>,
+) -> Result<ExportFormat, Response> {
+    ExportFormat::from_param(params.get("format").map(String::as_str))
+        .ok_or_else(|| bad_request("Invalid format parameter"))
+}
+
+/// Parses the `limit` parameter, which can't be more than `max`
+fn limit_param(
+    params: &HashMap<String, String>,
+    max: i64,
+) -> Result<i64, Response> {
+    match param::<i64>(params, "limit")? {
+        None => Ok(DEFAULT_PAGE_SIZE.min(max)),
+        Some(limit) if (1..=max).contains(&limit) => Ok(limit),
+        Some(_) => Err(bad_request(&format!("limit must be 1-{max}"))),
+    }
+}
+
+/// Parses the `format` and `limit` parameters of a search. Exported pages
+/// can be larger, as they're meant for bulk analysis.
+fn page_params(
+    params: &HashMap<String, String>,
+) -> Result<(ExportFormat, i64), Response> {
+    let format = export_format(params)?;
+    let max_limit = if format == ExportFormat::Json {
+        MAX_PAGE_SIZE
+    } else {
+        MAX_EXPORT_PAGE_SIZE
+    };
+    Ok((format, limit_param(params, max_limit)?))
+}
+
+fn search_error(error: SearchPageError) -> Response {
+    match error {
+        SearchPageError::InvalidCursor => {
+            bad_request("Invalid cursor parameter")
+        }
+        SearchPageError::OtherDbError(err) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+/// Returns a page of search results as JSON built by `json_page`, or as an
+/// export named `name`. Exports give the next cursor in a header, leaving
+/// the body as records.
+fn page_response<T: CsvRecord + Serialize>(
+    format: ExportFormat,
+    name: &str,
+    records: Vec<T>,
+    next_cursor: Option<String>,
+    json_page: impl FnOnce(Vec<T>, Option<String>) -> Response,
+) -> Response {
+    match (format, next_cursor) {
+        (ExportFormat::Json, next_cursor) => json_page(records, next_cursor),
+        (format, Some(next_cursor)) => {
+            export_response(format, name, &records)
+                .with_unique_header("X-Next-Cursor", next_cursor)
+        }
+        (format, None) => export_response(format, name, &records),
+    }
+}
+
+fn auth_log_entry_response(entry: AuthLog) -> AuthLogEntryResponse {
+    AuthLogEntryResponse {
+        id: entry.id,
+        email: entry.email,
+        success: entry.success,
+        ip_address: entry.ip_address,
+        user_agent: entry.user_agent,
+        date_created: entry.date_created,
+        flagged: entry.flagged,
+        flag_reasons: entry.flag_reasons,
+    }
+}
+
+fn auth_log_filter(
+    params: &HashMap<String, String>,
+) -> Result<AuthLogFilter<'_>, Response> {
+    Ok(AuthLogFilter {
+        email: params.get("email").map(String::as_str),
+        ip_address: params.get("ip_address").map(String::as_str),
+        success: param(params, "success")?,
+        flagged: param(params, "flagged")?,
+        date_from: param(params, "date_from")?,
+        date_to: param(params, "date_to")?,
+    })
+}
+
+fn search_auth_log(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("v1::admin::search_auth_log");
+    let params = query_params(request);
+    let ((format, limit), filter) =
+        match (page_params(&params), auth_log_filter(&params)) {
+            (Ok(page_params), Ok(filter)) => (page_params, filter),
+            (Err(response), _) | (_, Err(response)) => return response,
+        };
+
+    let page = match handlers::auth_log::search_auth_log(
+        connection,
+        &filter,
+        params.get("cursor").map(String::as_str),
+        limit,
+    ) {
+        Ok(page) => page,
+        Err(error) => return search_error(error),
+    };
+    let entries: Vec<AuthLogEntryResponse> =
+        page.items.into_iter().map(auth_log_entry_response).collect();
+    page_response(
+        format,
+        "auth_log",
+        entries,
+        page.next_cursor,
+        |entries, next_cursor| {
+            Response::json(&AuthLogPageResponse {
+                entries,
+                next_cursor,
+            })
+        },
+    )
+}
+
+/// The parameters shared by the auth log reports
+struct ReportParams {
+    format: ExportFormat,
+    date_from: DateTime<Utc>,
+    date_to: DateTime<Utc>,
+    limit: i64,
+}
+
+fn report_params(
+    params: &HashMap<String, String>,
+) -> Result<ReportParams, Response> {
+    let (date_from, date_to) = handlers::auth_log::report_range(
+        param(params, "date_from")?,
+        param(params, "date_to")?,
+    );
+    Ok(ReportParams {
+        format: export_format(params)?,
+        date_from,
+        date_to,
+        limit: limit_param(params, MAX_EXPORT_PAGE_SIZE)?,
+    })
+}
+
+fn failures_by_ip(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("v1::admin::failures_by_ip");
+    let params = query_params(request);
+    let (report, min_failures) =
+        match (report_params(&params), param(&params, "min_failures")) {
+            (Ok(report), Ok(min_failures)) => {
+                (report, min_failures.unwrap_or(1))
+            }
+            (Err(response), _) | (_, Err(response)) => return response,
+        };
+
+    match handlers::auth_log::failures_by_ip(
+        connection,
+        report.date_from,
+        report.date_to,
+        min_failures,
+        report.limit,
+    ) {
+        Ok(counts) => {
+            let counts: Vec<IpFailureCountResponse> = counts
+                .into_iter()
+                .map(|count| IpFailureCountResponse {
+                    ip_address: count.ip_address,
+                    hour: count.hour,
+                    failures: count.failures,
+                })
+                .collect();
+            export_response(report.format, "failures_by_ip", &counts)
+        }
+        Err(GetAuthLogError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+fn targeted_accounts(
+    request: &Request,
+    connection: &DalConnection,
+) -> Response {
+    let _span = telemetry::span("v1::admin::targeted_accounts");
+    let report = match report_params(&query_params(request)) {
+        Ok(report) => report,
+        Err(response) => return response,
+    };
+
+    match handlers::auth_log::targeted_accounts(
+        connection,
+        report.date_from,
+        report.date_to,
+        report.limit,
+    ) {
+        Ok(accounts) => {
+            let accounts: Vec<TargetedAccountResponse> = accounts
+                .into_iter()
+                .map(|account| TargetedAccountResponse {
+                    email: account.email,
+                    failures: account.failures,
+                    ip_addresses: account.ip_addresses,
+                    last_failure: account.last_failure,
+                })
+                .collect();
+            export_response(report.format, "targeted_accounts", &accounts)
+        }
+        Err(GetAuthLogError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+fn audit_event_response(event: AuditEvent) -> AuditEventResponse {
+    AuditEventResponse {
+        id: event.id,
+        event_type: event.event_type,
+        actor_type: event.actor_type,
+        actor_id: event.actor_id,
+        target_type: event.target_type,
+        target_id: event.target_id,
+        ip_address: event.ip_address,
+        user_agent: event.user_agent,
+        details: event.details,
+        date_created: event.date_created,
+    }
+}
+
+fn audit_event_filter(
+    params: &HashMap<String, String>,
+) -> Result<AuditEventFilter<'_>, Response> {
+    Ok(AuditEventFilter {
+        event_type: params.get("event_type").map(String::as_str),
+        actor_type: params.get("actor_type").map(String::as_str),
+        actor_id: params.get("actor_id").map(String::as_str),
+        target_type: params.get("target_type").map(String::as_str),
+        target_id: params.get("target_id").map(String::as_str),
+        ip_address: params.get("ip_address").map(String::as_str),
+        date_from: param(params, "date_from")?,
+        date_to: param(params, "date_to")?,
+    })
+}
+
+fn search_audit_events(
+    request: &Request,
+    connection: &DalConnection,
+) -> Response {
+    let _span = telemetry::span("v1::admin::search_audit_events");
+    let params = query_params(request);
+    let ((format, limit), filter) =
+        match (page_params(&params), audit_event_filter(&params)) {
+            (Ok(page_params), Ok(filter)) => (page_params, filter),
+            (Err(response), _) | (_, Err(response)) => return response,
+        };
+
+    let page = match handlers::audit::search_audit_events(
+        connection,
+        &filter,
+        params.get("cursor").map(String::as_str),
+        limit,
+    ) {
+        Ok(page) => page,
+        Err(error) => return search_error(error),
+    };
+    let events: Vec<AuditEventResponse> =
+        page.items.into_iter().map(audit_event_response).collect();
+    page_response(
+        format,
+        "audit_events",
+        events,
+        page.next_cursor,
+        |events, next_cursor| {
+            Response::json(&AuditEventPageResponse {
+                events,
+                next_cursor,
+            })
+        },
+    )
+}
+
+fn verify_hash_chains(connection: &DalConnection) -> Response {
+    let _span = telemetry::span("v1::admin::verify_hash_chains");
+    let mut reports = Vec::new();
+    for chain in &Chain::ALL {
+        match handlers::hash_chain::verify_chain(connection, *chain) {
+            Ok(report) => reports.push(ChainReportResponse {
+                chain: report.chain.name().to_owned(),
+                intact: report.broken_link.is_none(),
+                unchained_rows: report.unchained_rows,
+                verified_rows: report.verified_rows,
+                first_id: report.first_id,
+                last_id: report.last_id,
+                last_hash: report.last_hash.as_deref().map(to_hex),
+                broken_link: report.broken_link.map(|broken_link| {
+                    BrokenLinkResponse {
+                        id: broken_link.id,
+                        reason: broken_link.reason.code().to_owned(),
+                    }
+                }),
+            }),
+            Err(VerifyChainError::OtherDbError(err)) => {
+                panic!("Unexpected database error: {}", err);
+            }
+        }
+    }
+    Response::json(&reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use dal::DalConnection;
+    use handlers::personal_token::{self, CreatePersonalTokenError};
+    use rouille::Request;
+    use test_support;
+    use v1;
+
+    fn create_client_status(connection: &DalConnection, token: &str) -> u16 {
+        let request = Request::fake_http(
+            "POST",
+            "/v1/admin/clients",
+            vec![
+                ("Authorization".to_owned(), format!("Bearer {token}")),
+                ("Content-Type".to_owned(), "application/json".to_owned()),
+            ],
+            br#"{
+                "name": "test",
+                "grant_types": ["client_credentials"],
+                "confidential": true
+            }"#
+            .to_vec(),
+        );
+        let request = request.remove_prefix("/v1").unwrap();
+        v1::routes(&request, connection).status_code
+    }
+
+    fn personal_token(
+        connection: &DalConnection,
+        user_id: i64,
+        scopes: &[&str],
+    ) -> String {
+        let scopes: Vec<String> =
+            scopes.iter().map(|scope| (*scope).to_owned()).collect();
+        match personal_token::create_personal_token(
+            connection, user_id, "test", &scopes, None,
+        ) {
+            Ok((_, secret)) => secret,
+            Err(CreatePersonalTokenError::OtherDbError(db_error)) => {
+                panic!("{}", db_error)
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    fn scoped_tokens_need_the_role_as_a_scope() {
+        let connection = test_support::connection();
+        let admin = test_support::create_user(&connection, &["admin"]);
+
+        let read_token = personal_token(&connection, admin.id, &["read"]);
+        assert_eq!(create_client_status(&connection, &read_token), 403);
+
+        let admin_token = personal_token(&connection, admin.id, &["admin"]);
+        assert_eq!(create_client_status(&connection, &admin_token), 201);
+    }
+}
@@ -0,0 +1,27 @@
+use client;
+use dal::{audit::CreateAuditEventError, DalConnection};
+use handlers::{self, audit::Event};
+use rouille::Request;
+
+/// Records an event caused by a request in the audit log, on the request's
+/// transaction
+///
+/// # Panics
+///
+/// If the event can't be recorded, so the request's changes are rolled back
+pub fn record_event(
+    request: &Request,
+    connection: &DalConnection,
+    event: &Event<'_>,
+) {
+    match handlers::audit::record_event(
+        connection,
+        event,
+        &client::client_info(request),
+    ) {
+        Ok(_) => (),
+        Err(CreateAuditEventError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use dal::{auth::GetAuthTokenError, DalConnection};
+use handlers::{
+    self,
+    auth::{CheckAccessError, Identity},
+    user::{Principal, VerifyTokenError},
+};
+use rouille::{input::cookies, Request, Response};
+use telemetry;
+use v1::models::response::SingleErrorResponse;
+
+pub const SESSION_COOKIE_NAME: &str = "login_api_session";
+/// Readable by scripts so they can echo it back in `CSRF_HEADER_NAME`
+pub const CSRF_COOKIE_NAME: &str = "login_api_csrf";
+pub const CSRF_HEADER_NAME: &str = "X-CSRF-Token";
+
+pub fn routes(request: &Request, connection: &DalConnection) -> Response {
+    router!(
+        request,
+        (GET) ["/check"] => check(request, connection),
+        _ => Response::empty_404(),
+    )
+}
+
+fn bearer_token(request: &Request) -> Option<String> {
+    let header = request.header("Authorization")?;
+    let mut parts = header.splitn(2, ' ');
+    let scheme = parts.next().unwrap_or("");
+    if scheme.eq_ignore_ascii_case("Bearer") {
+        parts.next().map(|token| token.trim().to_owned())
+    } else {
+        None
+    }
+}
+
+fn session_token(request: &Request) -> Option<String> {
+    cookies(request)
+        .find(|&(name, _)| name == SESSION_COOKIE_NAME)
+        .map(|(_, value)| value.to_owned())
+}
+
+/// Reads the token from an `Authorization: Bearer` header, falling back to
+/// the session cookie. This doesn't check for CSRF, so should only be used
+/// for requests that don't change any state.
+#[must_use]
+pub fn request_token(request: &Request) -> Option<String> {
+    bearer_token(request).or_else(|| session_token(request))
+}
+
+fn is_state_changing(method: &str) -> bool {
+    !matches!(method, "GET" | "HEAD" | "OPTIONS")
+}
+
+fn csrf_failed_response() -> Response {
+    let mut response = Response::json(&SingleErrorResponse {
+        error: "CSRF token missing or invalid".to_owned(),
+    });
+    response.status_code = 403;
+    response
+}
+
+/// Reads the request's token like `request_token`, with CSRF protection.
+///
+/// When a request for `method` is authenticated by the session cookie and
+/// changes state, it must also carry the session's CSRF token in the
+/// `X-CSRF-Token` header. Bearer tokens can't be sent by another site, so
+/// don't need the check.
+///
+/// # Errors
+///
+/// A 403 response if the CSRF token is missing or wrong
+pub fn csrf_checked_token(
+    request: &Request,
+    method: &str,
+) -> Result<Option<String>, Response> {
+    if let Some(token) = bearer_token(request) {
+        return Ok(Some(token));
+    }
+    let Some(token) = session_token(request) else {
+        return Ok(None);
+    };
+    if is_state_changing(method)
+        && !request.header(CSRF_HEADER_NAME).is_some_and(|csrf_token| {
+            handlers::auth::verify_csrf_token(&token, csrf_token)
+        })
+    {
+        return Err(csrf_failed_response());
+    }
+    Ok(Some(token))
+}
+
+/// Sets the session and CSRF cookies for `session`, or clears them if it's
+/// `None`
+#[must_use]
+pub fn with_session_cookies(
+    response: Response,
+    session: Option<&str>,
+) -> Response {
+    let (session_token, csrf_token, max_age) = session.map_or_else(
+        || (String::new(), String::new(), 0),
+        |token| {
+            (
+                token.to_owned(),
+                handlers::auth::csrf_token(token),
+                handlers::user::token_lifetime().num_seconds(),
+            )
+        },
+    );
+    response
+        .with_additional_header(
+            "Set-Cookie",
+            format!(
+                "{SESSION_COOKIE_NAME}={session_token}; Max-Age={max_age}; \
+                 Path=/; Secure; HttpOnly; SameSite=Lax"
+            ),
+        )
+        .with_additional_header(
+            "Set-Cookie",
+            format!(
+                "{CSRF_COOKIE_NAME}={csrf_token}; Max-Age={max_age}; Path=/; \
+                 Secure; SameSite=Lax"
+            ),
+        )
+}
+
+fn unauthorized_response() -> Response {
+    let mut response = Response::json(&SingleErrorResponse {
+        error: "Unauthorized".to_owned(),
+    });
+    response.status_code = 401;
+    response
+}
+
+/// Authenticates the request, with CSRF protection for the session cookie,
+/// returning the error response to send otherwise
+///
+/// # Errors
+///
+/// A 401 response without a token, or a 403 response if the CSRF check fails
+pub fn authenticated_token(request: &Request) -> Result<String, Response> {
+    match csrf_checked_token(request, request.method()) {
+        Ok(Some(token)) => Ok(token),
+        Ok(None) => Err(unauthorized_response()),
+        Err(response) => Err(response),
+    }
+}
+
+fn access_error_response(error: CheckAccessError) -> Response {
+    match error {
+        CheckAccessError::Unauthenticated(_) => {
+            let mut response = Response::json(&SingleErrorResponse {
+                error: "Unauthorized".to_owned(),
+            });
+            response.status_code = 401;
+            response
+        }
+        CheckAccessError::Forbidden => {
+            let mut response = Response::json(&SingleErrorResponse {
+                error: "Forbidden".to_owned(),
+            });
+            response.status_code = 403;
+            response
+        }
+        CheckAccessError::OtherDbError(err) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+/// Authenticates the request and checks the caller holds `role`, returning
+/// the error response to send otherwise
+///
+/// # Errors
+///
+/// A 401 response if the token is missing or invalid, or a 403 response without
+/// the role
+pub fn require_role(
+    request: &Request,
+    connection: &DalConnection,
+    role: &str,
+) -> Result<Identity, Response> {
+    let token = authenticated_token(request)?;
+    handlers::auth::require_role(connection, &token, role)
+        .map_err(access_error_response)
+}
+
+/// Authenticates the request with a login token, returning the user's ID.
+/// Scoped tokens are refused, so a leaked token can't be used to mint broader
+/// ones or change the account.
+///
+/// # Errors
+///
+/// A 401 response if the token is missing or invalid, or a 403 response for
+/// scoped tokens
+///
+/// # Panics
+///
+/// On unexpected database errors
+pub fn require_login(
+    request: &Request,
+    connection: &DalConnection,
+) -> Result<i64, Response> {
+    let token = authenticated_token(request)?;
+    let principal = match handlers::user::verify_token(connection, &token) {
+        Ok(principal) => principal,
+        Err(VerifyTokenError::GetAuthTokenError(
+            GetAuthTokenError::OtherDbError(err),
+        )) => {
+            panic!("Unexpected database error: {}", err);
+        }
+        Err(_) => return Err(unauthorized_response()),
+    };
+    if let Principal::User {
+        user_id,
+        scopes: None,
+        ..
+    } = principal
+    {
+        return Ok(user_id);
+    }
+    let mut response = Response::json(&SingleErrorResponse {
+        error: "This endpoint requires a login token.".to_owned(),
+    });
+    response.status_code = 403;
+    Err(response)
+}
+
+fn empty_response(status_code: u16) -> Response {
+    let mut response = Response::empty_204();
+    response.status_code = status_code;
+    response
+}
+
+/// Forward authentication endpoint for reverse proxies (nginx
+/// `auth_request`, Traefik `forwardAuth`, Envoy `ext_authz`)
+fn check(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("v1::auth::check");
+    // Without the original method there's no way to tell whether the request
+    // changes state, so it's treated as safe
+    let original_method = request
+        .header("X-Forwarded-Method")
+        .or_else(|| request.header("X-Original-Method"))
+        .unwrap_or("GET");
+    let token = match csrf_checked_token(request, original_method) {
+        Ok(Some(token)) => token,
+        Ok(None) => {
+            return empty_response(401)
+                .with_additional_header("WWW-Authenticate", "Bearer");
+        }
+        Err(_) => return empty_response(403),
+    };
+    let original_uri = request
+        .header("X-Forwarded-Uri")
+        .or_else(|| request.header("X-Original-URI"));
+
+    match handlers::auth::check_access(connection, &token, original_uri) {
+        Ok(identity) => {
+            let roles = identity.roles.join(",");
+            match identity.principal {
+                Principal::User {
+                    user_id,
+                    email,
+                    scopes,
+                } => {
+                    let response = empty_response(200)
+                        .with_additional_header(
+                            "X-User-Id",
+                            user_id.to_string(),
+                        )
+                        .with_additional_header("X-User-Email", email)
+                        .with_additional_header("X-User-Roles", roles);
+                    match scopes {
+                        Some(scopes) => response.with_additional_header(
+                            "X-User-Scopes",
+                            scopes.join(" "),
+                        ),
+                        None => response,
+                    }
+                }
+                Principal::Service { client_id, scopes } => {
+                    empty_response(200)
+                        .with_additional_header("X-Client-Id", client_id)
+                        .with_additional_header(
+                            "X-Client-Scopes",
+                            scopes.join(" "),
+                        )
+                }
+            }
+        }
+        Err(CheckAccessError::Unauthenticated(_)) => empty_response(401)
+            .with_additional_header("WWW-Authenticate", "Bearer"),
+        Err(CheckAccessError::Forbidden) => empty_response(403),
+        Err(CheckAccessError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use config;
+use rouille::{Request, Response};
+use std::sync::LazyLock;
+
+pub enum AllowedOrigins {
+    Any,
+    List(Vec<String>),
+}
+
+pub struct CorsConfig {
+    pub allowed_origins: AllowedOrigins,
+    pub allowed_methods: String,
+    pub allowed_headers: String,
+    pub allow_credentials: bool,
+    pub max_age: u32,
+}
+
+static CORS_CONFIG: LazyLock<Option<CorsConfig>> = LazyLock::new(|| {
+    let config = &config::get().cors;
+    if config.allowed_origins.is_empty() {
+        return None;
+    }
+    let allowed_origins = if config.allowed_origins == ["*"] {
+        AllowedOrigins::Any
+    } else {
+        AllowedOrigins::List(
+            config
+                .allowed_origins
+                .iter()
+                .map(|origin| origin.trim_end_matches('/').to_owned())
+                .collect(),
+        )
+    };
+    Some(CorsConfig {
+        allowed_origins,
+        allowed_methods: config.allowed_methods.join(", "),
+        allowed_headers: config.allowed_headers.join(", "),
+        allow_credentials: config.allow_credentials,
+        max_age: config.max_age_secs,
+    })
+});
+
+/// Returns the configuration along with the value for the
+/// `Access-Control-Allow-Origin` header if the request's origin is allowed
+fn allowed_origin(
+    request: &Request,
+) -> Option<(&'static CorsConfig, &'static str)> {
+    let config = CORS_CONFIG.as_ref()?;
+    let origin = request.header("Origin")?;
+    match &config.allowed_origins {
+        AllowedOrigins::Any => Some((config, "*")),
+        AllowedOrigins::List(origins) => origins
+            .iter()
+            .find(|allowed| *allowed == origin)
+            .map(|allowed| (config, allowed.as_str())),
+    }
+}
+
+fn with_origin_headers(
+    response: Response,
+    config: &CorsConfig,
+    origin: &str,
+) -> Response {
+    let response = response
+        .with_unique_header("Access-Control-Allow-Origin", origin.to_owned())
+        .with_additional_header("Vary", "Origin");
+    if config.allow_credentials {
+        response.with_unique_header("Access-Control-Allow-Credentials", "true")
+    } else {
+        response
+    }
+}
+
+/// Adds `Vary: Origin` to a response without CORS headers when only some
+/// origins are allowed, as an allowed origin would get a different response
+fn with_vary_origin(response: Response) -> Response {
+    match CORS_CONFIG.as_ref().map(|config| &config.allowed_origins) {
+        Some(AllowedOrigins::List(_)) => {
+            response.with_additional_header("Vary", "Origin")
+        }
+        Some(AllowedOrigins::Any) | None => response,
+    }
+}
+
+#[must_use]
+pub fn is_preflight(request: &Request) -> bool {
+    request.method() == "OPTIONS"
+        && request.header("Access-Control-Request-Method").is_some()
+}
+
+/// Answers a preflight request. This never needs the database, so is handled
+/// before a connection is taken from the pool.
+#[must_use]
+pub fn preflight(request: &Request) -> Response {
+    let Some((config, origin)) = allowed_origin(request) else {
+        return with_vary_origin(Response::empty_204());
+    };
+    with_origin_headers(Response::empty_204(), config, origin)
+        .with_unique_header(
+            "Access-Control-Allow-Methods",
+            config.allowed_methods.clone(),
+        )
+        .with_unique_header(
+            "Access-Control-Allow-Headers",
+            config.allowed_headers.clone(),
+        )
+        .with_unique_header(
+            "Access-Control-Max-Age",
+            config.max_age.to_string(),
+        )
+}
+
+/// Adds CORS headers to a response if the request's origin is allowed
+#[must_use]
+pub fn with_cors_headers(request: &Request, response: Response) -> Response {
+    match allowed_origin(request) {
+        Some((config, origin)) => {
+            with_origin_headers(response, config, origin)
+        }
+        None => with_vary_origin(response),
+    }
+}
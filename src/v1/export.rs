@@ -0,0 +1,93 @@
+use rouille::Response;
+use serde::Serialize;
+use serde_json;
+
+/// How a list of records is returned
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    #[must_use]
+    pub fn from_param(format: Option<&str>) -> Option<Self> {
+        match format {
+            None | Some("json") => Some(Self::Json),
+            Some("csv") => Some(Self::Csv),
+            Some("ndjson") => Some(Self::Ndjson),
+            Some(_) => None,
+        }
+    }
+}
+
+/// A record that can be written as a CSV row
+pub trait CsvRecord {
+    const HEADER: &'static [&'static str];
+
+    fn fields(&self) -> Vec<String>;
+}
+
+/// Quotes a CSV field if needed. Fields that spreadsheets would treat as
+/// formulas are prefixed with `'`, as they may come from attackers.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{value}")
+    } else {
+        value.to_owned()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+fn csv_row<'a>(fields: impl Iterator<Item = &'a str>) -> String {
+    let mut row = fields.map(csv_field).collect::<Vec<_>>().join(",");
+    row.push_str("\r\n");
+    row
+}
+
+/// Returns records as a JSON array, or a CSV or NDJSON download named `name`
+///
+/// # Panics
+///
+/// If a record can't be serialized
+pub fn export_response<T: CsvRecord + Serialize>(
+    format: ExportFormat,
+    name: &str,
+    records: &[T],
+) -> Response {
+    match format {
+        ExportFormat::Csv => {
+            let mut body = csv_row(T::HEADER.iter().copied());
+            for record in records {
+                let fields = record.fields();
+                body.push_str(&csv_row(fields.iter().map(String::as_str)));
+            }
+            Response::from_data("text/csv; charset=utf-8", body)
+                .with_unique_header(
+                    "Content-Disposition",
+                    format!("attachment; filename=\"{name}.csv\""),
+                )
+        }
+        ExportFormat::Json => Response::json(&records),
+        ExportFormat::Ndjson => {
+            let mut body = String::new();
+            for record in records {
+                body.push_str(
+                    &serde_json::to_string(record)
+                        .expect("Records should serialize"),
+                );
+                body.push('\n');
+            }
+            Response::from_data("application/x-ndjson", body)
+                .with_unique_header(
+                    "Content-Disposition",
+                    format!("attachment; filename=\"{name}.ndjson\""),
+                )
+        }
+    }
+}
@@ -1,3 +1,8 @@
+pub mod admin;
+pub mod audit;
+pub mod auth;
+pub mod cors;
+pub mod export;
 pub mod models;
 pub mod token;
 pub mod user;
@@ -12,7 +17,11 @@ pub fn routes(request: &Request, connection: &DalConnection) -> Response {
             Response::empty_404()
         },
         _ => {
-            if let Some(user_request) = request.remove_prefix("/user") {
+            if let Some(admin_request) = request.remove_prefix("/admin") {
+                admin::routes(&admin_request, connection)
+            } else if let Some(auth_request) = request.remove_prefix("/auth") {
+                auth::routes(&auth_request, connection)
+            } else if let Some(user_request) = request.remove_prefix("/user") {
                 user::routes(&user_request, connection)
             } else if let Some(token_request) = request.remove_prefix("/token") {
                 token::routes(&token_request, connection)
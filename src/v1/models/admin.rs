@@ -0,0 +1,252 @@
+use chrono::{DateTime, Utc};
+use handlers::oauth::{
+    AUTHORIZATION_CODE_GRANT,
+    CLIENT_CREDENTIALS_GRANT,
+    DEVICE_CODE_GRANT,
+};
+use serde_json::Value;
+use url::Url;
+use v1::export::CsvRecord;
+use validator::{Validate, ValidationError};
+
+#[derive(Deserialize, Validate)]
+#[validate(schema(function = "validate_create_client_request"))]
+pub struct CreateClientRequest {
+    #[validate(length(min = 1, max = 255, message = "Name is not valid"))]
+    pub name: String,
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+    #[serde(default = "default_grant_types")]
+    pub grant_types: Vec<String>,
+    #[serde(default)]
+    pub allowed_scopes: Vec<String>,
+    #[serde(default)]
+    pub confidential: bool,
+}
+
+fn default_grant_types() -> Vec<String> {
+    vec![AUTHORIZATION_CODE_GRANT.to_owned()]
+}
+
+fn validate_create_client_request(
+    request: &CreateClientRequest,
+) -> Result<(), ValidationError> {
+    if request.grant_types.is_empty()
+        || !request.grant_types.iter().all(|grant_type| {
+            grant_type == AUTHORIZATION_CODE_GRANT
+                || grant_type == CLIENT_CREDENTIALS_GRANT
+                || grant_type == DEVICE_CODE_GRANT
+        })
+    {
+        return Err(ValidationError::new("grant_types invalid"));
+    }
+    if request
+        .grant_types
+        .iter()
+        .any(|grant_type| grant_type == CLIENT_CREDENTIALS_GRANT)
+        && !request.confidential
+    {
+        return Err(ValidationError::new(
+            "client_credentials requires a confidential client",
+        ));
+    }
+    if !request
+        .grant_types
+        .iter()
+        .any(|grant_type| grant_type == AUTHORIZATION_CODE_GRANT)
+    {
+        return Ok(());
+    }
+
+    if request.redirect_uris.is_empty() {
+        return Err(ValidationError::new("redirect_uris missing"));
+    }
+    // Redirect URIs must be absolute and can't contain a fragment
+    if request.redirect_uris.iter().all(|uri| {
+        Url::parse(uri).is_ok_and(|url| url.fragment().is_none())
+    }) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("redirect_uris invalid"))
+    }
+}
+
+#[derive(Serialize)]
+pub struct CreateClientResponse {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub allowed_scopes: Vec<String>,
+    pub date_created: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct PepperUsageResponse {
+    pub pepper_id: i32,
+    pub users: i64,
+    pub current: bool,
+    pub configured: bool,
+}
+
+#[derive(Serialize)]
+pub struct PepperReportResponse {
+    pub current_pepper_id: i32,
+    pub peppers: Vec<PepperUsageResponse>,
+}
+
+#[derive(Serialize)]
+pub struct AuthLogEntryResponse {
+    pub id: i64,
+    pub email: String,
+    pub success: bool,
+    pub ip_address: String,
+    pub user_agent: String,
+    pub date_created: DateTime<Utc>,
+    pub flagged: bool,
+    pub flag_reasons: Option<String>,
+}
+
+impl CsvRecord for AuthLogEntryResponse {
+    const HEADER: &'static [&'static str] = &[
+        "id",
+        "email",
+        "success",
+        "ip_address",
+        "user_agent",
+        "date_created",
+        "flagged",
+        "flag_reasons",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.email.clone(),
+            self.success.to_string(),
+            self.ip_address.clone(),
+            self.user_agent.clone(),
+            self.date_created.to_rfc3339(),
+            self.flagged.to_string(),
+            self.flag_reasons.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct AuthLogPageResponse {
+    pub entries: Vec<AuthLogEntryResponse>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct IpFailureCountResponse {
+    pub ip_address: String,
+    pub hour: DateTime<Utc>,
+    pub failures: i64,
+}
+
+impl CsvRecord for IpFailureCountResponse {
+    const HEADER: &'static [&'static str] = &["ip_address", "hour", "failures"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.ip_address.clone(),
+            self.hour.to_rfc3339(),
+            self.failures.to_string(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct TargetedAccountResponse {
+    pub email: String,
+    pub failures: i64,
+    pub ip_addresses: i64,
+    pub last_failure: DateTime<Utc>,
+}
+
+impl CsvRecord for TargetedAccountResponse {
+    const HEADER: &'static [&'static str] =
+        &["email", "failures", "ip_addresses", "last_failure"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.email.clone(),
+            self.failures.to_string(),
+            self.ip_addresses.to_string(),
+            self.last_failure.to_rfc3339(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct AuditEventResponse {
+    pub id: i64,
+    pub event_type: String,
+    pub actor_type: String,
+    pub actor_id: Option<String>,
+    pub target_type: String,
+    pub target_id: String,
+    pub ip_address: String,
+    pub user_agent: String,
+    pub details: Value,
+    pub date_created: DateTime<Utc>,
+}
+
+impl CsvRecord for AuditEventResponse {
+    const HEADER: &'static [&'static str] = &[
+        "id",
+        "event_type",
+        "actor_type",
+        "actor_id",
+        "target_type",
+        "target_id",
+        "ip_address",
+        "user_agent",
+        "details",
+        "date_created",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.event_type.clone(),
+            self.actor_type.clone(),
+            self.actor_id.clone().unwrap_or_default(),
+            self.target_type.clone(),
+            self.target_id.clone(),
+            self.ip_address.clone(),
+            self.user_agent.clone(),
+            // Kept as JSON, as events of each type have different details
+            self.details.to_string(),
+            self.date_created.to_rfc3339(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct AuditEventPageResponse {
+    pub events: Vec<AuditEventResponse>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BrokenLinkResponse {
+    pub id: i64,
+    pub reason: String,
+}
+
+#[derive(Serialize)]
+pub struct ChainReportResponse {
+    pub chain: String,
+    pub intact: bool,
+    pub unchained_rows: i64,
+    pub verified_rows: i64,
+    pub first_id: Option<i64>,
+    pub last_id: Option<i64>,
+    /// Hex encoded
+    pub last_hash: Option<String>,
+    pub broken_link: Option<BrokenLinkResponse>,
+}
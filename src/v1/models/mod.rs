@@ -1,3 +1,4 @@
+pub mod admin;
 pub mod response;
 pub mod token;
 pub mod user;
@@ -1,10 +1,14 @@
-use validator::Validate;
+use chrono::{DateTime, Utc};
+use validator::{Validate, ValidationError};
 
 #[derive(Deserialize, Validate)]
 pub struct CreateTokenRequest {
     #[validate(email(message = "Email is not valid"))]
     pub email: String,
     pub password: String,
+    /// Sets the token in an HTTP-only session cookie instead of returning it
+    #[serde(default)]
+    pub cookie: bool,
 }
 
 #[derive(Serialize)]
@@ -12,6 +16,11 @@ pub struct CreateTokenResponse {
     pub token: String,
 }
 
+#[derive(Serialize)]
+pub struct CreateSessionResponse {
+    pub csrf_token: String,
+}
+
 #[derive(Deserialize, Validate)]
 pub struct ValidateTokenRequest {
     pub token: String,
@@ -19,6 +28,67 @@ pub struct ValidateTokenRequest {
 
 #[derive(Serialize)]
 pub struct ValidateTokenResponse {
-    pub user_id: i64,
-    pub email: String,
+    pub principal_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Validate)]
+#[validate(schema(function = "validate_create_personal_token_request"))]
+pub struct CreatePersonalTokenRequest {
+    #[validate(length(min = 1, max = 255, message = "Name is not valid"))]
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    pub date_expired: Option<DateTime<Utc>>,
+}
+
+fn validate_create_personal_token_request(
+    request: &CreatePersonalTokenRequest,
+) -> Result<(), ValidationError> {
+    // Scopes are space separated when stored, as in OAuth
+    if !request.scopes.iter().all(|scope| {
+        !scope.is_empty()
+            && scope.chars().all(|character| {
+                character.is_ascii_graphic()
+                    && character != '"'
+                    && character != '\\'
+            })
+    }) {
+        return Err(ValidationError::new("scopes invalid"));
+    }
+    if request
+        .date_expired
+        .is_some_and(|date_expired| date_expired <= Utc::now())
+    {
+        return Err(ValidationError::new("date_expired must be in the future"));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct PersonalTokenResponse {
+    pub id: i64,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub date_created: DateTime<Utc>,
+    pub date_expired: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct CreatePersonalTokenResponse {
+    pub token: String,
+    #[serde(flatten)]
+    pub details: PersonalTokenResponse,
+}
+
+#[derive(Serialize)]
+pub struct PersonalTokenListResponse {
+    pub tokens: Vec<PersonalTokenResponse>,
 }
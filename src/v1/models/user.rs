@@ -1,5 +1,7 @@
 use chrono::{DateTime, Utc};
-use validator::{Validate, ValidationError};
+use handlers::password::{check_password, PasswordViolation};
+use std::borrow::Cow;
+use validator::{Validate, ValidationError, ValidationErrors};
 
 #[derive(Deserialize, Validate)]
 pub struct CreateUserRequest {
@@ -43,3 +45,56 @@ fn validate_patch_user_request(
         },
     }
 }
+
+/// Adds a field error to `errors` for every password policy rule `password`
+/// breaks
+pub fn add_password_policy_errors(
+    errors: &mut ValidationErrors,
+    field: &'static str,
+    password: &str,
+    email: Option<&str>,
+) {
+    for violation in check_password(password, email) {
+        let (code, message, min, max) = match violation {
+            PasswordViolation::TooShort(min_length) => (
+                "password_too_short",
+                format!("Password must be at least {min_length} characters"),
+                Some(min_length),
+                None,
+            ),
+            PasswordViolation::TooLong(max_length) => (
+                "password_too_long",
+                format!("Password must be at most {max_length} characters"),
+                None,
+                Some(max_length),
+            ),
+            PasswordViolation::TooWeak(min_strength) => (
+                "password_too_weak",
+                "Password is too easy to guess".to_owned(),
+                Some(usize::from(min_strength)),
+                None,
+            ),
+            PasswordViolation::ContainsEmail => (
+                "password_contains_email",
+                "Password must not contain the email address".to_owned(),
+                None,
+                None,
+            ),
+            PasswordViolation::Breached => (
+                "password_breached",
+                "Password has appeared in a data breach".to_owned(),
+                None,
+                None,
+            ),
+        };
+        let mut error = ValidationError::new(code);
+        error.message = Some(Cow::from(message));
+        if let Some(min) = min {
+            error.add_param(Cow::from("min"), &min);
+        }
+        if let Some(max) = max {
+            error.add_param(Cow::from("max"), &max);
+        }
+        errors.add(field, error);
+    }
+}
@@ -1,20 +1,43 @@
-use dal::DalConnection;
-use handlers::{self, user::VerifyTokenError};
+use client;
+use dal::{auth::GetAuthTokenError, DalConnection};
+use handlers::{
+    self,
+    audit::{Actor, Event, EventType, Target},
+    personal_token::{
+        CreatePersonalTokenError,
+        ListPersonalTokensError,
+        PersonalAccessToken,
+        RevokePersonalTokenError,
+        PERSONAL_TOKEN_TYPE,
+    },
+    user::{EndSessionError, Principal, VerifyTokenError},
+};
 use jwt;
 use rouille::{
     input::{json::JsonError, json_input},
     Request,
     Response,
 };
-use v1::models::{
-    response::SingleErrorResponse,
-    token::{
-        CreateTokenRequest,
-        CreateTokenResponse,
-        ValidateTokenRequest,
-        ValidateTokenResponse,
+use v1::{
+    audit::record_event,
+    auth::{csrf_checked_token, require_login, with_session_cookies},
+    models::{
+        response::SingleErrorResponse,
+        token::{
+            CreatePersonalTokenRequest,
+            CreatePersonalTokenResponse,
+            CreateSessionResponse,
+            CreateTokenRequest,
+            CreateTokenResponse,
+            PersonalTokenListResponse,
+            PersonalTokenResponse,
+            ValidateTokenRequest,
+            ValidateTokenResponse,
+        },
     },
 };
+use serde_json::json;
+use telemetry;
 use validator::Validate;
 
 pub fn routes(request: &Request, connection: &DalConnection) -> Response {
@@ -22,16 +45,25 @@ pub fn routes(request: &Request, connection: &DalConnection) -> Response {
         request,
         (POST) [""] => create_token(request, connection),
         (POST) ["/validate"] => validate_token(request, connection),
+        (POST) ["/logout"] => logout(request, connection),
+        (POST) ["/personal"] => create_personal_token(request, connection),
+        (GET) ["/personal"] => list_personal_tokens(request, connection),
+        (DELETE) ["/personal/{token_id}", token_id: i64] => {
+            revoke_personal_token(request, connection, token_id)
+        },
         _ => Response::empty_404(),
     )
 }
 
 fn create_token(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("v1::token::create_token");
     let body: CreateTokenRequest = match json_input(request) {
         Ok(body) => body,
-        Err(JsonError::WrongContentType)
-        | Err(JsonError::IoError(_))
-        | Err(JsonError::ParseError(_)) => {
+        Err(
+            JsonError::WrongContentType
+            | JsonError::IoError(_)
+            | JsonError::ParseError(_),
+        ) => {
             let mut response = Response::json(&SingleErrorResponse {
                 error: "Body format error".to_owned(),
             });
@@ -42,7 +74,7 @@ fn create_token(request: &Request, connection: &DalConnection) -> Response {
     };
     // Validate fields
     match body.validate() {
-        Ok(_) => (),
+        Ok(()) => (),
         Err(e) => {
             let mut response = Response::json(&e);
             response.status_code = 422;
@@ -50,13 +82,23 @@ fn create_token(request: &Request, connection: &DalConnection) -> Response {
         }
     }
 
+    let client_info = client::client_info(request);
     match handlers::user::create_token(
         connection,
         &body.email,
         &body.password,
-        &request.remote_addr().ip().to_string(),
-        request.header("User-Agent").unwrap(),
+        &client_info.ip_address,
+        client_info.user_agent,
     ) {
+        // In cookie mode the token is kept out of reach of scripts, which
+        // only get the CSRF token
+        Ok(token) if body.cookie => {
+            let mut response = Response::json(&CreateSessionResponse {
+                csrf_token: handlers::auth::csrf_token(&token),
+            });
+            response.status_code = 201;
+            with_session_cookies(response, Some(&token)).with_no_cache()
+        }
         Ok(token) => {
             let mut response = Response::json(&CreateTokenResponse { token });
             response.status_code = 201;
@@ -75,12 +117,49 @@ fn create_token(request: &Request, connection: &DalConnection) -> Response {
     }
 }
 
+/// Revokes the login token the request was made with and clears the session
+/// cookies
+fn logout(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("v1::token::logout");
+    let token = match csrf_checked_token(request, request.method()) {
+        Ok(token) => token,
+        Err(response) => return response,
+    };
+    if let Some(token) = token {
+        match handlers::user::end_session(connection, &token) {
+            Ok(Some(auth_token)) => record_event(
+                request,
+                connection,
+                &Event {
+                    event_type: EventType::TokenRevoked,
+                    actor: auth_token
+                        .user_id
+                        .map_or(Actor::Anonymous, Actor::User),
+                    target: Target::Token(auth_token.id),
+                    details: json!({
+                        "token_type": auth_token.token_type,
+                        "reason": "logout",
+                    }),
+                },
+            ),
+            Ok(None) => (),
+            Err(EndSessionError::OtherDbError(err)) => {
+                panic!("Unexpected database error: {}", err);
+            }
+        }
+    }
+    with_session_cookies(Response::empty_204(), None)
+}
+
 fn validate_token(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("v1::token::validate_token");
     let body: ValidateTokenRequest = match json_input(request) {
         Ok(body) => body,
-        Err(JsonError::WrongContentType)
-        | Err(JsonError::IoError(_))
-        | Err(JsonError::ParseError(_)) => {
+        Err(
+            JsonError::WrongContentType
+            | JsonError::IoError(_)
+            | JsonError::ParseError(_),
+        ) => {
             let mut response = Response::json(&SingleErrorResponse {
                 error: "Body format error".to_owned(),
             });
@@ -91,27 +170,83 @@ fn validate_token(request: &Request, connection: &DalConnection) -> Response {
     };
 
     match handlers::user::verify_token(connection, &body.token) {
-        Ok((user_id, email)) => {
-            let mut response =
-                Response::json(&ValidateTokenResponse { user_id, email });
+        Ok(Principal::User {
+            user_id,
+            email,
+            scopes,
+        }) => {
+            let mut response = Response::json(&ValidateTokenResponse {
+                principal_type: "user".to_owned(),
+                user_id: Some(user_id),
+                email: Some(email),
+                client_id: None,
+                scopes,
+            });
+            response.status_code = 200;
+            response
+        }
+        Ok(Principal::Service { client_id, scopes }) => {
+            let mut response = Response::json(&ValidateTokenResponse {
+                principal_type: "service".to_owned(),
+                user_id: None,
+                email: None,
+                client_id: Some(client_id),
+                scopes: Some(scopes),
+            });
             response.status_code = 200;
             response
         }
-        Err(VerifyTokenError::TokenMismatch) => {
+        Err(error) => verify_token_error_response(error),
+    }
+}
+
+fn verify_token_error_response(error: VerifyTokenError) -> Response {
+    match error {
+        VerifyTokenError::TokenMismatch => {
             let mut response = Response::json(&SingleErrorResponse {
                 error: "Token signature valid, but token string doesn't match database.".to_owned(),
             });
             response.status_code = 401;
             response
         }
-        Err(VerifyTokenError::UserMismatch) => {
+        VerifyTokenError::UserMismatch => {
             let mut response = Response::json(&SingleErrorResponse {
                 error: "Token user_id doesn't match database.".to_owned(),
             });
             response.status_code = 401;
             response
         }
-        Err(VerifyTokenError::JwtError(error)) => match error.into_kind() {
+        VerifyTokenError::Revoked => {
+            let mut response = Response::json(&SingleErrorResponse {
+                error: "Token has been revoked.".to_owned(),
+            });
+            response.status_code = 401;
+            response
+        }
+        VerifyTokenError::Expired => {
+            let mut response = Response::json(&SingleErrorResponse {
+                error: "Token has expired.".to_owned(),
+            });
+            response.status_code = 401;
+            response
+        }
+        VerifyTokenError::GetAuthTokenError(
+            GetAuthTokenError::AuthTokenNotFound,
+        ) => {
+            let mut response = Response::json(&SingleErrorResponse {
+                error: "Token not found.".to_owned(),
+            });
+            response.status_code = 401;
+            response
+        }
+        VerifyTokenError::ClientMismatch => {
+            let mut response = Response::json(&SingleErrorResponse {
+                error: "Token client_id doesn't match database.".to_owned(),
+            });
+            response.status_code = 401;
+            response
+        }
+        VerifyTokenError::JwtError(error) => match error.into_kind() {
             jwt::errors::ErrorKind::ExpiredSignature => {
                 let mut response = Response::json(&SingleErrorResponse {
                     error: "Token has expired.".to_owned(),
@@ -128,18 +263,163 @@ fn validate_token(request: &Request, connection: &DalConnection) -> Response {
             }
             error => {
                 let mut response = Response::json(&SingleErrorResponse {
-                    error: format!("Unhandled jwt error: {:?}", error),
+                    error: format!("Unhandled jwt error: {error:?}"),
                 });
                 response.status_code = 500;
                 response
             }
         },
-        Err(error) => {
+        error @ VerifyTokenError::GetAuthTokenError(_) => {
             let mut response = Response::json(&SingleErrorResponse {
-                error: format!("Unhandled error: {:?}", error),
+                error: format!("Unhandled error: {error:?}"),
             });
             response.status_code = 500;
             response
         }
     }
 }
+
+fn personal_token_response(
+    token: PersonalAccessToken,
+) -> PersonalTokenResponse {
+    PersonalTokenResponse {
+        id: token.id,
+        name: token.name,
+        scopes: token.scopes,
+        date_created: token.date_created,
+        date_expired: token.date_expired,
+    }
+}
+
+fn create_personal_token(
+    request: &Request,
+    connection: &DalConnection,
+) -> Response {
+    let _span = telemetry::span("v1::token::create_personal_token");
+    let user_id = match require_login(request, connection) {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    let body: CreatePersonalTokenRequest = match json_input(request) {
+        Ok(body) => body,
+        Err(
+            JsonError::WrongContentType
+            | JsonError::IoError(_)
+            | JsonError::ParseError(_),
+        ) => {
+            let mut response = Response::json(&SingleErrorResponse {
+                error: "Body format error".to_owned(),
+            });
+            response.status_code = 400;
+            return response;
+        }
+        _ => panic!("Body should only be extracted once."),
+    };
+    // Validate fields
+    match body.validate() {
+        Ok(()) => (),
+        Err(e) => {
+            let mut response = Response::json(&e);
+            response.status_code = 422;
+            return response;
+        }
+    }
+
+    match handlers::personal_token::create_personal_token(
+        connection,
+        user_id,
+        &body.name,
+        &body.scopes,
+        body.date_expired,
+    ) {
+        Ok((token, secret)) => {
+            record_event(
+                request,
+                connection,
+                &Event {
+                    event_type: EventType::TokenCreated,
+                    actor: Actor::User(user_id),
+                    target: Target::Token(token.id),
+                    details: json!({
+                        "token_type": PERSONAL_TOKEN_TYPE,
+                        "name": token.name,
+                        "scopes": token.scopes,
+                        "date_expired": token.date_expired,
+                    }),
+                },
+            );
+            let mut response = Response::json(&CreatePersonalTokenResponse {
+                token: secret,
+                details: personal_token_response(token),
+            });
+            response.status_code = 201;
+            response
+        }
+        Err(CreatePersonalTokenError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+fn list_personal_tokens(
+    request: &Request,
+    connection: &DalConnection,
+) -> Response {
+    let _span = telemetry::span("v1::token::list_personal_tokens");
+    let user_id = match require_login(request, connection) {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    match handlers::personal_token::list_personal_tokens(connection, user_id) {
+        Ok(tokens) => Response::json(&PersonalTokenListResponse {
+            tokens: tokens.into_iter().map(personal_token_response).collect(),
+        }),
+        Err(ListPersonalTokensError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
+
+fn revoke_personal_token(
+    request: &Request,
+    connection: &DalConnection,
+    token_id: i64,
+) -> Response {
+    let _span = telemetry::span("v1::token::revoke_personal_token");
+    let user_id = match require_login(request, connection) {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    match handlers::personal_token::revoke_personal_token(
+        connection, user_id, token_id,
+    ) {
+        Ok(()) => {
+            record_event(
+                request,
+                connection,
+                &Event {
+                    event_type: EventType::TokenRevoked,
+                    actor: Actor::User(user_id),
+                    target: Target::Token(token_id),
+                    details: json!({
+                        "token_type": PERSONAL_TOKEN_TYPE,
+                        "reason": "revoked",
+                    }),
+                },
+            );
+            Response::empty_204()
+        }
+        Err(RevokePersonalTokenError::TokenNotFound) => {
+            let mut response = Response::json(&SingleErrorResponse {
+                error: "Token not found".to_owned(),
+            });
+            response.status_code = 404;
+            response
+        }
+        Err(RevokePersonalTokenError::OtherDbError(err)) => {
+            panic!("Unexpected database error: {}", err);
+        }
+    }
+}
@@ -1,15 +1,33 @@
-use dal::{users::CreateUserError, DalConnection};
-use handlers;
+use dal::{
+    users::{get_user_by_id, CreateUserError, GetUserError, User},
+    DalConnection,
+};
+use handlers::{
+    self,
+    audit::{Actor, Event, EventType, Target},
+    user::RegisterUserError,
+};
 use rouille::{
     input::{json::JsonError, json_input},
     Request,
     Response,
 };
-use v1::models::{
-    response::SingleErrorResponse,
-    user::{CreateUserRequest, CreateUserResponse, PatchUserRequest},
+use serde_json::json;
+use telemetry;
+use v1::{
+    audit::record_event,
+    auth::require_login,
+    models::{
+        response::SingleErrorResponse,
+        user::{
+            add_password_policy_errors,
+            CreateUserRequest,
+            CreateUserResponse,
+            PatchUserRequest,
+        },
+    },
 };
-use validator::Validate;
+use validator::{Validate, ValidationErrors};
 
 pub fn routes(request: &Request, connection: &DalConnection) -> Response {
     router!(
@@ -21,11 +39,14 @@ pub fn routes(request: &Request, connection: &DalConnection) -> Response {
 }
 
 fn create_user(request: &Request, connection: &DalConnection) -> Response {
+    let _span = telemetry::span("v1::user::create_user");
     let body: CreateUserRequest = match json_input(request) {
         Ok(body) => body,
-        Err(JsonError::WrongContentType)
-        | Err(JsonError::IoError(_))
-        | Err(JsonError::ParseError(_)) => {
+        Err(
+            JsonError::WrongContentType
+            | JsonError::IoError(_)
+            | JsonError::ParseError(_),
+        ) => {
             let mut response = Response::json(&SingleErrorResponse {
                 error: "Body format error".to_owned(),
             });
@@ -35,19 +56,47 @@ fn create_user(request: &Request, connection: &DalConnection) -> Response {
         _ => panic!("Body should only be extracted once."),
     };
     // Validate other fields
-    match body.validate() {
-        Ok(_) => (),
-        Err(e) => {
-            let mut response = Response::json(&e);
-            response.status_code = 422;
-            return response;
-        }
+    let mut errors = match body.validate() {
+        Ok(()) => ValidationErrors::new(),
+        Err(e) => e,
+    };
+    add_password_policy_errors(
+        &mut errors,
+        "password",
+        &body.password,
+        Some(&body.email),
+    );
+    if !errors.is_empty() {
+        let mut response = Response::json(&errors);
+        response.status_code = 422;
+        return response;
+    }
+
+    if handlers::user::signup_enumeration_protection() {
+        return match handlers::user::register_user(
+            connection,
+            &body.email,
+            &body.password,
+        ) {
+            Ok(new_user) => {
+                if let Some(user) = new_user {
+                    record_user_created(request, connection, &user);
+                }
+                let mut response = Response::empty_204();
+                response.status_code = 202;
+                response
+            }
+            Err(RegisterUserError::OtherDbError(err)) => {
+                panic!("Unexpected database error: {}", err);
+            }
+        };
     }
 
     let user_result =
         handlers::user::create_user(connection, &body.email, &body.password);
     match user_result {
         Ok(user) => {
+            record_user_created(request, connection, &user);
             let mut response = Response::json(&CreateUserResponse {
                 id: user.id,
                 email: user.email,
@@ -69,16 +118,47 @@ fn create_user(request: &Request, connection: &DalConnection) -> Response {
     }
 }
 
+fn record_user_created(
+    request: &Request,
+    connection: &DalConnection,
+    user: &User,
+) {
+    record_event(
+        request,
+        connection,
+        &Event {
+            event_type: EventType::UserCreated,
+            actor: Actor::Anonymous,
+            target: Target::User(user.id),
+            details: json!({ "email": user.email }),
+        },
+    );
+}
+
 fn patch_user(
     request: &Request,
     connection: &DalConnection,
     user_id: i64,
 ) -> Response {
+    let _span = telemetry::span("v1::user::patch_user");
+    match require_login(request, connection) {
+        Ok(caller_id) if caller_id == user_id => (),
+        Ok(_) => {
+            let mut response = Response::json(&SingleErrorResponse {
+                error: "Forbidden".to_owned(),
+            });
+            response.status_code = 403;
+            return response;
+        }
+        Err(response) => return response,
+    }
     let body: PatchUserRequest = match json_input(request) {
         Ok(body) => body,
-        Err(JsonError::WrongContentType)
-        | Err(JsonError::IoError(_))
-        | Err(JsonError::ParseError(_)) => {
+        Err(
+            JsonError::WrongContentType
+            | JsonError::IoError(_)
+            | JsonError::ParseError(_),
+        ) => {
             let mut response = Response::json(&SingleErrorResponse {
                 error: "Body format error".to_owned(),
             });
@@ -89,8 +169,32 @@ fn patch_user(
     };
 
     // Validate other fields
-    match body.validate() {
-        Ok(_) => (),
+    let mut result = body.validate();
+    if let Some(change_password_data) = &body.change_password_data {
+        let email = match get_user_by_id(connection, user_id) {
+            Ok(user) => user.email,
+            Err(GetUserError::UserNotFound) => return Response::empty_404(),
+            Err(GetUserError::OtherDbError(err)) => {
+                panic!("Unexpected database error: {}", err);
+            }
+        };
+        let mut errors = ValidationErrors::new();
+        add_password_policy_errors(
+            &mut errors,
+            "new_password",
+            &change_password_data.new_password,
+            Some(&email),
+        );
+        if !errors.is_empty() {
+            result = ValidationErrors::merge(
+                result,
+                "change_password_data",
+                Err(errors),
+            );
+        }
+    }
+    match result {
+        Ok(()) => (),
         Err(e) => {
             let mut response = Response::json(&e);
             response.status_code = 422;